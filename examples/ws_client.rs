@@ -0,0 +1,117 @@
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use log::debug;
+use simple_ws_server::http::{build_client_handshake, Frame, FrameDecoder};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+/// Max payload size of frames received from the server
+const MAX_PAYLOAD_SIZE: usize = 1024 * 1024;
+
+/// Status code of Close for normal closure (RFC 6455 7.4.1)
+const CLOSE_NORMAL: u16 = 1000;
+
+/// Simple WebSocket client, which sends a message and prints the reply
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// URL of the server, e.g. ws://127.0.0.1:8888/
+    url: String,
+    /// Text message sent to the server
+    message: String,
+}
+
+/// Split `ws://host[:port][/path]` into the address to connect, Host header and path
+fn parse_url(url: &str) -> Result<(String, String, String)> {
+    let rest = match url.strip_prefix("ws://") {
+        Some(rest) => rest,
+        None => bail!("Only ws:// URL is supported: {}", url),
+    };
+    let (host, path) = match rest.find('/') {
+        Some(pos) => (&rest[..pos], &rest[pos..]),
+        None => (rest, "/"),
+    };
+    if host.is_empty() {
+        bail!("Missing host in URL: {}", url);
+    }
+    let addr = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:80", host)
+    };
+    Ok((addr, host.to_string(), path.to_string()))
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+
+    let args = Args::parse();
+    let (addr, host, path) = parse_url(&args.url)?;
+
+    let stream = TcpStream::connect(&addr)
+        .await
+        .with_context(|| format!("Failed to connect to {}", addr))?;
+    // bytes following the handshake response are kept in the buffer for frames
+    let mut stream = BufReader::new(stream);
+
+    let request = build_client_handshake(&path, &host);
+    stream.write_all(&request.encode()).await?;
+    let mut status_line = String::new();
+    stream.read_line(&mut status_line).await?;
+    if status_line.split(' ').nth(1) != Some("101") {
+        bail!("Failed to upgrade: {}", status_line.trim_end());
+    }
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await? == 0 {
+            bail!("Connection closed in handshake");
+        }
+        if line == "\r\n" {
+            break;
+        }
+        debug!("Handshake header: {}", line.trim_end());
+    }
+
+    let text = Frame::Text {
+        message: args.message,
+    };
+    stream.write_all(&text.encode_client()?).await?;
+    // kept across frames so that a reply fragmented around a Ping is assembled
+    let mut decoder = FrameDecoder::new();
+    loop {
+        match decoder.decode(&mut stream, MAX_PAYLOAD_SIZE).await? {
+            Frame::Text { message } => {
+                println!("{}", message);
+                break;
+            }
+            Frame::Ping { data } => {
+                stream
+                    .write_all(&Frame::Pong { data }.encode_client()?)
+                    .await?;
+            }
+            Frame::Close { status_code, .. } => {
+                bail!("Connection closed by server: {:?}", status_code);
+            }
+            frame => debug!("Ignore frame: {}", frame.summary(16)),
+        }
+    }
+
+    let close = Frame::Close {
+        status_code: Some(CLOSE_NORMAL),
+        message: vec![],
+    };
+    stream.write_all(&close.encode_client()?).await?;
+    // wait for Close from the server before closing the connection (RFC 6455 7.1.1)
+    loop {
+        if let Frame::Close { status_code, .. } =
+            decoder.decode(&mut stream, MAX_PAYLOAD_SIZE).await?
+        {
+            debug!("Connection closed with status code: {:?}", status_code);
+            break;
+        }
+    }
+    stream.shutdown().await?;
+
+    Ok(())
+}
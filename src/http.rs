@@ -1,6 +1,29 @@
+mod access_log;
 mod common;
+mod compression;
+mod cookie;
+mod deflate;
+mod error_page;
+mod etag;
 mod handler;
 mod headers;
+mod metrics;
+mod multipart;
+mod outgoing;
+mod rate_limit;
 mod request;
 mod response;
+mod room;
 pub mod server;
+mod shutdown;
+mod tls;
+
+pub use access_log::AccessLogSink;
+pub use handler::cors::CorsHandler;
+pub use handler::echo::EchoHandler;
+pub use handler::router::RouterHandler;
+pub use handler::websocket::app::{EchoApp, WebSocketApp};
+pub use handler::websocket::{build_client_handshake, Frame, FrameDecoder, WebSocketHandler};
+pub use handler::{AsyncStream, ClientStream, HandleOutcome, Handler};
+pub use request::{Request, RequestMethod};
+pub use response::ResponseStatus;
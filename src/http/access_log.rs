@@ -0,0 +1,67 @@
+use crate::http::common::IMFDateTime;
+use crate::http::handler::HandleOutcome;
+use crate::http::request::Request;
+use log::info;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+/// Target of access log records, e.g. to filter them with `RUST_LOG=access=info`
+pub const TARGET: &str = "access";
+
+/// Destination of access log lines, e.g. to keep them apart from other logs
+pub trait AccessLogSink: Send + Sync {
+    fn write(&self, line: &str);
+}
+
+/// Sink logging lines with `TARGET`, which servers use by default
+pub struct LogSink;
+
+impl AccessLogSink for LogSink {
+    fn write(&self, line: &str) {
+        info!(target: TARGET, "{}", line);
+    }
+}
+
+/// Request of an access log line, which is recorded before the request is handled
+pub struct AccessLog {
+    client_addr: SocketAddr,
+    time: IMFDateTime,
+    request_line: (String, String, String),
+    started: Instant,
+}
+
+impl AccessLog {
+    pub fn start(request: &Request, client_addr: SocketAddr) -> AccessLog {
+        AccessLog {
+            client_addr,
+            time: IMFDateTime::now(),
+            request_line: (
+                request.get_method().to_string(),
+                request.get_path().to_owned(),
+                request.get_version().to_string(),
+            ),
+            started: Instant::now(),
+        }
+    }
+
+    /// Format the line by `template`, whose placeholders are `{client_addr}`, `{time}`,
+    /// `{method}`, `{path}`, `{version}`, `{status}`, `{bytes}` and `{duration_ms}`.
+    /// e.g. `{client_addr} - - [{time}] "{method} {path} {version}" {status} {bytes}`
+    /// for a line like common log format.
+    pub fn format(&self, template: &str, outcome: &HandleOutcome) -> String {
+        let (method, path, version) = &self.request_line;
+        // path is replaced last so that placeholders in it are kept as they are
+        template
+            .replace("{client_addr}", &self.client_addr.to_string())
+            .replace("{time}", &self.time.to_string())
+            .replace("{method}", method)
+            .replace("{version}", version)
+            .replace("{status}", &outcome.get_status().status_code().to_string())
+            .replace("{bytes}", &outcome.get_bytes_written().to_string())
+            .replace(
+                "{duration_ms}",
+                &self.started.elapsed().as_millis().to_string(),
+            )
+            .replace("{path}", path)
+    }
+}
@@ -1,10 +1,12 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Timelike, Utc};
 use std::fmt;
 use std::fmt::Formatter;
 use std::str::FromStr;
+use std::time::SystemTime;
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum HTTPVersion {
+    V1_0,
     V1_1,
 }
 
@@ -13,6 +15,7 @@ impl FromStr for HTTPVersion {
 
     fn from_str(str: &str) -> Result<Self, Self::Err> {
         match str {
+            "HTTP/1.0" => Ok(HTTPVersion::V1_0),
             "HTTP/1.1" => Ok(HTTPVersion::V1_1),
             _ => Err(format!("Illegal format as HTTP version: {}", str)),
         }
@@ -22,6 +25,7 @@ impl FromStr for HTTPVersion {
 impl fmt::Display for HTTPVersion {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
+            HTTPVersion::V1_0 => f.write_str("HTTP/1.0"),
             HTTPVersion::V1_1 => f.write_str("HTTP/1.1"),
         }
     }
@@ -29,8 +33,16 @@ impl fmt::Display for HTTPVersion {
 
 /// Date format used in HTTP header
 /// See RFC7231 7.1.1
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct IMFDateTime(DateTime<Utc>);
 
+/// Formats of HTTP-date accepted on parsing, which are IMF-fixdate and obsolete RFC 850 and asctime
+const DATE_FORMATS: [&str; 3] = [
+    "%a, %d %b %Y %H:%M:%S GMT",
+    "%A, %d-%b-%y %H:%M:%S GMT",
+    "%a %b %e %H:%M:%S %Y",
+];
+
 impl IMFDateTime {
     #[allow(dead_code)]
     pub fn new(inner: DateTime<Utc>) -> IMFDateTime {
@@ -40,6 +52,39 @@ impl IMFDateTime {
     pub fn now() -> IMFDateTime {
         IMFDateTime(Utc::now())
     }
+
+    /// Parse HTTP-date (RFC 7231 7.1.1.1), which is IMF-fixdate like
+    /// `Tue, 26 Apr 2022 12:24:36 GMT` or one of the obsolete formats.
+    /// Two digit years of RFC 850 format are interpreted as 1970-2069.
+    pub fn parse(s: &str) -> Result<IMFDateTime, String> {
+        let s = s.trim();
+        DATE_FORMATS
+            .iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+            .map(|naive| IMFDateTime(Utc.from_utc_datetime(&naive)))
+            .ok_or_else(|| format!("Illegal format as HTTP-date: {}", s))
+    }
+
+    #[allow(dead_code)]
+    pub fn get_inner(&self) -> &DateTime<Utc> {
+        &self.0
+    }
+}
+
+impl FromStr for IMFDateTime {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        IMFDateTime::parse(s)
+    }
+}
+
+impl From<SystemTime> for IMFDateTime {
+    /// Sub-second part is truncated since HTTP-date has the precision of seconds
+    fn from(time: SystemTime) -> Self {
+        let time = DateTime::<Utc>::from(time);
+        IMFDateTime(time.with_nanosecond(0).unwrap_or(time))
+    }
 }
 
 impl fmt::Display for IMFDateTime {
@@ -58,4 +103,26 @@ mod tests {
         let dt = IMFDateTime::new(Utc.ymd(2022, 4, 26).and_hms(12, 24, 36));
         assert_eq!(format!("{}", dt), "Tue, 26 Apr 2022 12:24:36 GMT");
     }
+
+    #[test]
+    fn test_imf_datetime_parse() {
+        let expected = IMFDateTime::new(Utc.ymd(2022, 4, 26).and_hms(12, 24, 36));
+        assert_eq!(
+            IMFDateTime::parse("Tue, 26 Apr 2022 12:24:36 GMT"),
+            Ok(expected.clone())
+        );
+        assert_eq!(IMFDateTime::parse(&expected.to_string()), Ok(expected));
+
+        let expected = IMFDateTime::new(Utc.ymd(1994, 11, 6).and_hms(8, 49, 37));
+        assert_eq!(
+            IMFDateTime::parse("Sunday, 06-Nov-94 08:49:37 GMT"),
+            Ok(expected.clone())
+        );
+        assert_eq!(
+            IMFDateTime::parse("Sun Nov  6 08:49:37 1994"),
+            Ok(expected.clone())
+        );
+        assert!(IMFDateTime::parse("Sun Nov  6 08:49:38 1994").unwrap() > expected);
+        assert!(IMFDateTime::parse("2022-04-26T12:24:36Z").is_err());
+    }
 }
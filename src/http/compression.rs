@@ -0,0 +1,280 @@
+use crate::http::request::{Request, RequestParseError};
+use crate::http::response::{Response, ResponseBody, ResponseStatus};
+use crate::settings::Http;
+use anyhow::Result;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::{Read, Write};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ContentCoding {
+    Gzip,
+    Identity,
+}
+
+impl ContentCoding {
+    /// Select content coding acceptable for Accept-Encoding (RFC 7231 5.3.4)
+    pub fn negotiate(accept_encoding: Option<&str>) -> ContentCoding {
+        let accept_encoding = match accept_encoding {
+            Some(s) => s,
+            None => return ContentCoding::Identity,
+        };
+
+        let mut gzip_q = None;
+        let mut any_q = None;
+        for item in accept_encoding.split(',') {
+            let mut params = item.split(';').map(|x| x.trim());
+            let coding = params.next().unwrap_or("").to_ascii_lowercase();
+            let q = params
+                .find_map(|param| param.strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            match coding.as_str() {
+                "gzip" | "x-gzip" => gzip_q = Some(q),
+                "*" => any_q = Some(q),
+                _ => {}
+            }
+        }
+
+        match gzip_q.or(any_q) {
+            Some(q) if q > 0.0 => ContentCoding::Gzip,
+            _ => ContentCoding::Identity,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ContentCoding::Gzip => "gzip",
+            ContentCoding::Identity => "identity",
+        }
+    }
+}
+
+/// Add Accept-Encoding to Vary header, keeping the existing value (RFC 7231 7.1.4)
+fn add_vary_accept_encoding(response: &mut Response) {
+    let vary = match response.get_header("Vary") {
+        Some(vary) => {
+            let listed = vary
+                .split(',')
+                .map(|x| x.trim())
+                .any(|x| x == "*" || x.eq_ignore_ascii_case("Accept-Encoding"));
+            if listed {
+                return;
+            }
+            format!("{}, Accept-Encoding", vary)
+        }
+        None => "Accept-Encoding".to_string(),
+    };
+    response.insert_header("Vary".to_string(), vary);
+}
+
+/// Encode response body with the content coding.
+/// Vary header is set because the response is selected by Accept-Encoding.
+#[allow(dead_code)]
+pub fn compress(mut response: Response, coding: ContentCoding) -> Result<Response> {
+    add_vary_accept_encoding(&mut response);
+    match coding {
+        ContentCoding::Gzip => {
+            let mut encoder = GzEncoder::new(vec![], Compression::default());
+            encoder.write_all(response.get_body())?;
+            let compressed = encoder.finish()?;
+            response.replace_body(ResponseBody::new(compressed));
+            response.insert_header("Content-Encoding".to_string(), coding.name().to_string());
+            Ok(response)
+        }
+        ContentCoding::Identity => Ok(response),
+    }
+}
+
+/// Return whether the media type is compressed by itself, so compressing it again hardly helps
+fn is_compressed_media_type(content_type: &str) -> bool {
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    match media_type.split_once('/') {
+        Some(("image", subtype)) => subtype != "svg+xml",
+        Some(("audio" | "video", _)) => true,
+        Some(("application", subtype)) => matches!(
+            subtype,
+            "gzip" | "x-gzip" | "zip" | "zstd" | "x-7z-compressed" | "x-bzip2" | "x-xz"
+        ),
+        _ => false,
+    }
+}
+
+/// Compress the response for the request if compression is enabled in settings.
+/// Small bodies, bodies already encoded and compressed media types are sent as they are.
+pub fn compress_for(response: Response, request: &Request, http: &Http) -> Result<Response> {
+    let compressible = *http.enable_compression()
+        && response.get_body().len() >= http.compression_min_bytes().to_bytes() as usize
+        && response.get_header("Content-Encoding").is_none()
+        && !matches!(response.get_header("Content-Type"), Some(t) if is_compressed_media_type(t));
+    if !compressible {
+        return Ok(response);
+    }
+    let coding = ContentCoding::negotiate(request.get_header("Accept-Encoding"));
+    compress(response, coding)
+}
+
+/// Decode request body with Content-Encoding (RFC 7231 3.1.2.2).
+/// Decoding stops at `max_bytes` so that a small body cannot expand without limit.
+pub fn decompress(
+    body: &[u8],
+    content_encoding: &str,
+    max_bytes: Option<usize>,
+) -> Result<Vec<u8>, RequestParseError> {
+    let decoder: Box<dyn Read + '_> = match content_encoding.to_ascii_lowercase().as_str() {
+        "gzip" | "x-gzip" => Box::new(GzDecoder::new(body)),
+        "deflate" => Box::new(ZlibDecoder::new(body)),
+        "identity" => return Ok(body.to_vec()),
+        _ => {
+            return Err(RequestParseError::new(
+                ResponseStatus::UnsupportedMediaType,
+                &format!("Unsupported Content-Encoding: {}", content_encoding),
+            ))
+        }
+    };
+
+    // read one more byte than the limit to detect the excess
+    let limit = max_bytes.map_or(u64::MAX, |max| max as u64 + 1);
+    let mut decoded = vec![];
+    decoder.take(limit).read_to_end(&mut decoded).map_err(|_| {
+        RequestParseError::new(ResponseStatus::BadRequest, "Failed to decode request body")
+    })?;
+    if matches!(max_bytes, Some(max) if decoded.len() > max) {
+        return Err(RequestParseError::new(
+            ResponseStatus::PayloadTooLarge,
+            "Too big decoded request body",
+        ));
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::common::HTTPVersion;
+    use crate::http::request::{RequestBody, RequestHeaders, RequestLine, RequestMethod};
+    use crate::http::response::{ResponseHeaders, StatusLine};
+    use flate2::write::ZlibEncoder;
+
+    fn create_request(headers: RequestHeaders) -> Request {
+        Request::new(
+            RequestLine::new(RequestMethod::GET, "/", HTTPVersion::V1_1),
+            headers,
+            RequestBody::new(vec![]),
+        )
+    }
+
+    #[test]
+    fn test_negotiate_content_coding() {
+        assert_eq!(ContentCoding::negotiate(None), ContentCoding::Identity);
+        assert_eq!(
+            ContentCoding::negotiate(Some("gzip, deflate")),
+            ContentCoding::Gzip
+        );
+        assert_eq!(ContentCoding::negotiate(Some("*")), ContentCoding::Gzip);
+        assert_eq!(
+            ContentCoding::negotiate(Some("gzip;q=0, *")),
+            ContentCoding::Identity
+        );
+        assert_eq!(
+            ContentCoding::negotiate(Some("br")),
+            ContentCoding::Identity
+        );
+    }
+
+    #[test]
+    fn test_compress_response_with_gzip() {
+        let data = "a".repeat(1024);
+        let response = Response::new(
+            StatusLine::new(HTTPVersion::V1_1, ResponseStatus::Ok),
+            ResponseHeaders::from([("Content-Length", data.len().to_string())]),
+            ResponseBody::new(data.as_bytes().to_owned()),
+        );
+
+        let actual = compress(response, ContentCoding::Gzip).unwrap();
+
+        assert_eq!(actual.get_header("Content-Encoding"), Some("gzip"));
+        assert_eq!(
+            actual.get_header("Content-Length"),
+            Some(actual.get_body().len().to_string().as_str())
+        );
+        assert_eq!(actual.get_header("Vary"), Some("Accept-Encoding"));
+        let mut decoded = String::new();
+        GzDecoder::new(actual.get_body())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn test_compress_response_merges_vary() {
+        let response = Response::new(
+            StatusLine::new(HTTPVersion::V1_1, ResponseStatus::Ok),
+            ResponseHeaders::from([("Content-Length", "5"), ("Vary", "Origin")]),
+            ResponseBody::new("hello".as_bytes().to_owned()),
+        );
+
+        let actual = compress(response, ContentCoding::Gzip).unwrap();
+
+        assert_eq!(actual.get_header("Vary"), Some("Origin, Accept-Encoding"));
+    }
+
+    #[test]
+    fn test_compress_response_does_not_duplicate_vary() {
+        let response = Response::new(
+            StatusLine::new(HTTPVersion::V1_1, ResponseStatus::Ok),
+            ResponseHeaders::from([("Content-Length", "5"), ("Vary", "accept-encoding")]),
+            ResponseBody::new("hello".as_bytes().to_owned()),
+        );
+
+        let actual = compress(response, ContentCoding::Identity).unwrap();
+
+        assert_eq!(actual.get_header("Vary"), Some("accept-encoding"));
+    }
+
+    #[test]
+    fn test_compress_for_skips_small_and_compressed_bodies() {
+        let http: Http = serde_json::from_value(serde_json::json!({
+            "enable_compression": true,
+            "compression_min_bytes": "16B",
+        }))
+        .unwrap();
+        let request = create_request(RequestHeaders::from([("Accept-Encoding", "gzip")]));
+        let create_response = |content_type: &str, len: usize| {
+            Response::new(
+                StatusLine::new(HTTPVersion::V1_1, ResponseStatus::Ok),
+                ResponseHeaders::from([
+                    ("Content-Type", content_type.to_string()),
+                    ("Content-Length", len.to_string()),
+                ]),
+                ResponseBody::new(vec![b'a'; len]),
+            )
+        };
+
+        let actual = compress_for(create_response("text/plain", 32), &request, &http).unwrap();
+        assert_eq!(actual.get_header("Content-Encoding"), Some("gzip"));
+        let actual = compress_for(create_response("text/plain", 8), &request, &http).unwrap();
+        assert_eq!(actual.get_header("Content-Encoding"), None);
+        let actual = compress_for(create_response("image/png", 32), &request, &http).unwrap();
+        assert_eq!(actual.get_header("Content-Encoding"), None);
+    }
+
+    #[test]
+    fn test_decompress_deflate_body() {
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(b"hello").unwrap();
+        let body = encoder.finish().unwrap();
+
+        assert_eq!(decompress(&body, "deflate", None).unwrap(), b"hello");
+        assert!(matches!(
+            decompress(&body, "br", None),
+            Err(err) if err.get_status() == &ResponseStatus::UnsupportedMediaType
+        ));
+    }
+}
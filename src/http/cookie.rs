@@ -0,0 +1,139 @@
+use std::fmt;
+use std::fmt::Formatter;
+use std::time::Duration;
+
+/// SameSite attribute of Set-Cookie restricting cross-site requests
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[allow(dead_code)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SameSite::Strict => f.write_str("Strict"),
+            SameSite::Lax => f.write_str("Lax"),
+            SameSite::None => f.write_str("None"),
+        }
+    }
+}
+
+/// Cookie sent to the client in Set-Cookie header (RFC 6265 4.1), e.g.
+///
+/// ```ignore
+/// let cookie = Cookie::new("session", "abc")
+///     .with_path("/")
+///     .with_max_age(Duration::from_secs(3600))
+///     .with_http_only(true);
+/// ```
+///
+/// Name and value are sent as they are, so they must not contain characters like `;` or spaces.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    http_only: bool,
+    secure: bool,
+    same_site: Option<SameSite>,
+}
+
+#[allow(dead_code)]
+impl Cookie {
+    pub fn new(name: &str, value: &str) -> Cookie {
+        Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: None,
+            domain: None,
+            max_age: None,
+            http_only: false,
+            secure: false,
+            same_site: None,
+        }
+    }
+
+    pub fn with_path(mut self, path: &str) -> Cookie {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    pub fn with_domain(mut self, domain: &str) -> Cookie {
+        self.domain = Some(domain.to_string());
+        self
+    }
+
+    /// Set lifetime of the cookie, which is rounded down to seconds
+    pub fn with_max_age(mut self, max_age: Duration) -> Cookie {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn with_http_only(mut self, http_only: bool) -> Cookie {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn with_secure(mut self, secure: bool) -> Cookie {
+        self.secure = secure;
+        self
+    }
+
+    pub fn with_same_site(mut self, same_site: SameSite) -> Cookie {
+        self.same_site = Some(same_site);
+        self
+    }
+}
+
+/// Format as the value of Set-Cookie header
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_cookie() {
+        assert_eq!(Cookie::new("a", "1").to_string(), "a=1");
+
+        let cookie = Cookie::new("session", "abc")
+            .with_path("/")
+            .with_domain("example.com")
+            .with_max_age(Duration::from_millis(3_600_500))
+            .with_http_only(true)
+            .with_secure(true)
+            .with_same_site(SameSite::Lax);
+        assert_eq!(
+            cookie.to_string(),
+            "session=abc; Path=/; Domain=example.com; Max-Age=3600; HttpOnly; Secure; SameSite=Lax"
+        );
+    }
+}
@@ -0,0 +1,229 @@
+use crate::http::handler::websocket::FrameDecodeError;
+use crate::http::headers::WebSocketExtension;
+use anyhow::{Context, Result};
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+
+pub const PERMESSAGE_DEFLATE: &str = "permessage-deflate";
+
+// appended to each compressed message by deflate with sync flush (RFC 7692 7.2.1)
+const SYNC_FLUSH_TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+const CHUNK_SIZE: usize = 4096;
+
+/// Parameters of permessage-deflate extension agreed in the handshake (RFC 7692 7.1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DeflateParams {
+    server_no_context_takeover: bool,
+    client_no_context_takeover: bool,
+}
+
+impl DeflateParams {
+    /// Accept the first offer of permessage-deflate whose parameters are supported.
+    /// Offers restricting the window size of the server are declined.
+    pub fn negotiate(offers: &[WebSocketExtension]) -> Option<DeflateParams> {
+        offers
+            .iter()
+            .filter(|offer| offer.name == PERMESSAGE_DEFLATE)
+            .find_map(Self::accept)
+    }
+
+    fn accept(offer: &WebSocketExtension) -> Option<DeflateParams> {
+        let mut params = DeflateParams::default();
+        let mut seen = vec![];
+        for (name, value) in offer.params.iter() {
+            // each parameter must not appear more than once (RFC 7692 7.1)
+            if seen.contains(name) {
+                return None;
+            }
+            seen.push(name.clone());
+            match (name.as_str(), value.as_deref()) {
+                ("server_no_context_takeover", None) => params.server_no_context_takeover = true,
+                ("client_no_context_takeover", None) => params.client_no_context_takeover = true,
+                // the client's window is always acceptable since inflation uses the maximum window
+                ("client_max_window_bits", _) => {}
+                ("server_max_window_bits", Some("15")) => {}
+                _ => return None,
+            }
+        }
+        Some(params)
+    }
+
+    /// Return value of Sec-WebSocket-Extensions header in the handshake response
+    pub fn to_header_value(self) -> String {
+        let mut value = PERMESSAGE_DEFLATE.to_string();
+        if self.server_no_context_takeover {
+            value.push_str("; server_no_context_takeover");
+        }
+        if self.client_no_context_takeover {
+            value.push_str("; client_no_context_takeover");
+        }
+        value
+    }
+
+    /// Return inflater of messages sent by the client
+    pub fn inflater(&self) -> Inflater {
+        Inflater {
+            decompress: Decompress::new(false),
+            no_context_takeover: self.client_no_context_takeover,
+        }
+    }
+
+    /// Return deflater of messages sent by the server
+    pub fn deflater(&self) -> Deflater {
+        Deflater {
+            compress: Compress::new(Compression::default(), false),
+            no_context_takeover: self.server_no_context_takeover,
+        }
+    }
+}
+
+/// Decompressor of messages, which keeps the sliding window over messages
+/// unless `client_no_context_takeover` is agreed
+#[derive(Debug)]
+pub struct Inflater {
+    decompress: Decompress,
+    no_context_takeover: bool,
+}
+
+impl Inflater {
+    /// Inflate payload of a message, which must not exceed `max_size` bytes after inflation
+    pub fn inflate(&mut self, data: &[u8], max_size: usize) -> Result<Vec<u8>, FrameDecodeError> {
+        let mut input = data.to_vec();
+        input.extend(SYNC_FLUSH_TAIL);
+
+        let mut res = Vec::with_capacity(CHUNK_SIZE);
+        let mut consumed = 0;
+        loop {
+            res.reserve(CHUNK_SIZE);
+            let total_in = self.decompress.total_in();
+            let total_out = self.decompress.total_out();
+            self.decompress
+                .decompress_vec(&input[consumed..], &mut res, FlushDecompress::Sync)
+                .map_err(|err| {
+                    FrameDecodeError::Protocol(format!("Failed to inflate message: {}", err))
+                })?;
+            consumed += (self.decompress.total_in() - total_in) as usize;
+            if res.len() > max_size {
+                return Err(FrameDecodeError::TooBig(
+                    "Inflated payload is too big".to_string(),
+                ));
+            }
+            let progressed =
+                self.decompress.total_in() != total_in || self.decompress.total_out() != total_out;
+            // output has room, so no more data is pending in the decompressor
+            let finished = consumed == input.len() && res.len() < res.capacity();
+            if finished || !progressed {
+                break;
+            }
+        }
+
+        if self.no_context_takeover {
+            self.decompress.reset(false);
+        }
+        Ok(res)
+    }
+}
+
+/// Compressor of messages, which keeps the sliding window over messages
+/// unless `server_no_context_takeover` is agreed
+#[derive(Debug)]
+pub struct Deflater {
+    compress: Compress,
+    no_context_takeover: bool,
+}
+
+impl Deflater {
+    /// Deflate payload of a message without the trailing bytes of sync flush
+    pub fn deflate(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut res = Vec::with_capacity(data.len() + CHUNK_SIZE);
+        let mut consumed = 0;
+        loop {
+            res.reserve(CHUNK_SIZE);
+            let total_in = self.compress.total_in();
+            self.compress
+                .compress_vec(&data[consumed..], &mut res, FlushCompress::Sync)
+                .context("Failed to deflate message")?;
+            consumed += (self.compress.total_in() - total_in) as usize;
+            if consumed == data.len() && res.len() < res.capacity() {
+                break;
+            }
+        }
+
+        if res.ends_with(&SYNC_FLUSH_TAIL) {
+            res.truncate(res.len() - SYNC_FLUSH_TAIL.len());
+        }
+        if self.no_context_takeover {
+            self.compress.reset();
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offer(params: &[(&str, Option<&str>)]) -> WebSocketExtension {
+        WebSocketExtension {
+            name: PERMESSAGE_DEFLATE.to_string(),
+            params: params
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.map(|v| v.to_string())))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_negotiate_deflate_params() {
+        let params = DeflateParams::negotiate(&[
+            offer(&[("server_max_window_bits", Some("10"))]),
+            offer(&[
+                ("server_no_context_takeover", None),
+                ("client_max_window_bits", None),
+            ]),
+        ])
+        .unwrap();
+        assert_eq!(
+            params.to_header_value(),
+            "permessage-deflate; server_no_context_takeover"
+        );
+
+        assert_eq!(
+            DeflateParams::negotiate(&[offer(&[("unknown", None)])]),
+            None
+        );
+        assert_eq!(DeflateParams::negotiate(&[]), None);
+    }
+
+    #[test]
+    fn test_inflate_message() {
+        // "Hello" compressed in RFC 7692 7.2.3.1
+        let mut inflater = DeflateParams::default().inflater();
+        let data = [0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00];
+        assert_eq!(inflater.inflate(&data, 1024).unwrap(), b"Hello");
+        // the same message referring to the previous one (RFC 7692 7.2.3.2)
+        let data = [0xf2, 0x00, 0x11, 0x00, 0x00];
+        assert_eq!(inflater.inflate(&data, 1024).unwrap(), b"Hello");
+
+        let mut inflater = DeflateParams::default().inflater();
+        let data = [0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00];
+        assert!(inflater.inflate(&data, 4).is_err());
+    }
+
+    #[test]
+    fn test_deflate_and_inflate_round_trip() {
+        let params = DeflateParams {
+            server_no_context_takeover: true,
+            client_no_context_takeover: false,
+        };
+        let mut deflater = params.deflater();
+        let mut inflater = params.inflater();
+        let message = "hello, hello, hello, websocket".repeat(100);
+        for _ in 0..2 {
+            let compressed = deflater.deflate(message.as_bytes()).unwrap();
+            assert!(compressed.len() < message.len());
+            let inflated = inflater.inflate(&compressed, 64 * 1024).unwrap();
+            assert_eq!(inflated, message.as_bytes());
+        }
+    }
+}
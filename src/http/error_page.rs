@@ -0,0 +1,122 @@
+use crate::http::common::{HTTPVersion, IMFDateTime};
+use crate::http::response::{Response, ResponseBody, ResponseHeaders, ResponseStatus, StatusLine};
+
+#[derive(Debug, PartialEq, Eq)]
+enum ErrorFormat {
+    Html,
+    Json,
+    Empty,
+}
+
+impl ErrorFormat {
+    /// Select format preferred by Accept header (RFC 7231 5.3.2).
+    /// Only media types explicitly listed are taken into account.
+    fn negotiate(accept: Option<&str>) -> ErrorFormat {
+        let accept = match accept {
+            Some(accept) => accept,
+            None => return ErrorFormat::Empty,
+        };
+
+        let mut best = (ErrorFormat::Empty, 0.0);
+        for media_range in accept.split(',') {
+            let mut params = media_range.split(';').map(|x| x.trim());
+            let format = match params.next().map(|x| x.to_ascii_lowercase()).as_deref() {
+                Some("text/html") => ErrorFormat::Html,
+                Some("application/json") => ErrorFormat::Json,
+                _ => continue,
+            };
+            let q = params
+                .find_map(|param| param.strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if q > best.1 {
+                best = (format, q);
+            }
+        }
+        best.0
+    }
+}
+
+/// Create error response whose body is negotiated with Accept header of the request.
+/// Connection header is left to the caller like other responses.
+pub fn error_response(status: &ResponseStatus, accept: Option<&str>, template: &str) -> Response {
+    let (content_type, body) = match ErrorFormat::negotiate(accept) {
+        ErrorFormat::Html => {
+            let body = template
+                .replace("{status}", &status.status_code().to_string())
+                .replace("{reason}", &status.reason_phrase());
+            (Some("text/html"), body.into_bytes())
+        }
+        ErrorFormat::Json => {
+            let body = serde_json::json!({
+                "status": status.status_code(),
+                "reason": status.reason_phrase(),
+            })
+            .to_string();
+            (Some("application/json"), body.into_bytes())
+        }
+        ErrorFormat::Empty => (None, vec![]),
+    };
+
+    let mut headers = ResponseHeaders::from([
+        ("Date", IMFDateTime::now().to_string()),
+        ("Content-Length", body.len().to_string()),
+    ]);
+    if let Some(content_type) = content_type {
+        headers.insert("Content-Type".to_string(), content_type.to_string());
+    }
+
+    Response::new(
+        StatusLine::new(HTTPVersion::V1_1, status.clone()),
+        headers,
+        ResponseBody::new(body),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATE: &str = "<h1>{status} {reason}</h1>";
+
+    #[test]
+    fn test_error_response_for_html() {
+        let res = error_response(&ResponseStatus::BadRequest, Some("text/html"), TEMPLATE);
+        assert_eq!(res.get_header("Content-Type"), Some("text/html"));
+        assert_eq!(res.get_body(), "<h1>400 Bad Request</h1>".as_bytes());
+    }
+
+    #[test]
+    fn test_error_response_for_json() {
+        let res = error_response(
+            &ResponseStatus::InternalServerError,
+            Some("application/json"),
+            TEMPLATE,
+        );
+        assert_eq!(res.get_header("Content-Type"), Some("application/json"));
+        let body: serde_json::Value = serde_json::from_slice(res.get_body()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({"status": 500, "reason": "Internal Server Error"})
+        );
+    }
+
+    #[test]
+    fn test_error_response_without_preference() {
+        let res = error_response(&ResponseStatus::BadRequest, Some("*/*"), TEMPLATE);
+        assert_eq!(res.get_header("Content-Type"), None);
+        assert_eq!(res.get_header("Content-Length"), Some("0"));
+    }
+
+    #[test]
+    fn test_negotiate_error_format_with_quality() {
+        assert_eq!(
+            ErrorFormat::negotiate(Some("text/html;q=0.5, application/json")),
+            ErrorFormat::Json
+        );
+        assert_eq!(
+            ErrorFormat::negotiate(Some("application/json;q=0.1, text/html")),
+            ErrorFormat::Html
+        );
+    }
+}
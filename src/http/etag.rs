@@ -0,0 +1,115 @@
+use crate::http::common::HTTPVersion;
+use crate::http::request::{Request, RequestMethod};
+use crate::http::response::{Response, ResponseBody, ResponseHeaders, ResponseStatus, StatusLine};
+use sha1::{Digest, Sha1};
+
+/// Return a strong entity-tag computed from the body (RFC 7232 2.3)
+pub fn etag_for(body: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(body);
+    format!("\"{}\"", base64::encode(hasher.finalize()))
+}
+
+/// Return true if If-None-Match value matches the etag.
+/// Weak comparison is used for If-None-Match (RFC 7232 3.2).
+pub fn if_none_match(header_value: &str, etag: &str) -> bool {
+    fn opaque_tag(tag: &str) -> &str {
+        tag.strip_prefix("W/").unwrap_or(tag)
+    }
+
+    let header_value = header_value.trim();
+    if header_value == "*" {
+        return true;
+    }
+    header_value
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| opaque_tag(tag) == opaque_tag(etag))
+}
+
+/// Add ETag header to a cacheable response,
+/// and replace it with 304 Not Modified if the request has matching If-None-Match.
+pub fn apply_etag(request: &Request, mut response: Response) -> Response {
+    let cacheable_method = matches!(request.get_method(), RequestMethod::GET);
+    if !cacheable_method || response.get_status() != &ResponseStatus::Ok {
+        return response;
+    }
+
+    let etag = response
+        .get_header("ETag")
+        .map(|s| s.to_owned())
+        .unwrap_or_else(|| etag_for(response.get_body()));
+
+    match request.get_header("If-None-Match") {
+        Some(value) if if_none_match(value, &etag) => {
+            let mut headers = ResponseHeaders::from([("ETag", etag)]);
+            if let Some(date) = response.get_header("Date") {
+                headers.insert("Date".to_string(), date.to_string());
+            }
+            Response::new(
+                StatusLine::new(HTTPVersion::V1_1, ResponseStatus::NotModified),
+                headers,
+                ResponseBody::new(vec![]),
+            )
+        }
+        _ => {
+            response.insert_header("ETag".to_string(), etag);
+            response
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::request::{RequestBody, RequestHeaders, RequestLine};
+
+    fn create_response() -> Response {
+        let data = "hello".as_bytes();
+        Response::new(
+            StatusLine::new(HTTPVersion::V1_1, ResponseStatus::Ok),
+            ResponseHeaders::from([("Content-Length", data.len().to_string())]),
+            ResponseBody::new(data.to_owned()),
+        )
+    }
+
+    fn create_request(headers: RequestHeaders) -> Request {
+        Request::new(
+            RequestLine::new(RequestMethod::GET, "/", HTTPVersion::V1_1),
+            headers,
+            RequestBody::new(vec![]),
+        )
+    }
+
+    #[test]
+    fn test_apply_etag_returns_not_modified_for_matching_etag() {
+        let first = apply_etag(&create_request(RequestHeaders::new()), create_response());
+        assert_eq!(first.get_status(), &ResponseStatus::Ok);
+        let etag = first.get_header("ETag").unwrap().to_owned();
+
+        let second = apply_etag(
+            &create_request(RequestHeaders::from([("If-None-Match", etag.as_str())])),
+            create_response(),
+        );
+        assert_eq!(second.get_status(), &ResponseStatus::NotModified);
+        assert_eq!(second.get_header("ETag"), Some(etag.as_str()));
+        assert!(second.get_body().is_empty());
+    }
+
+    #[test]
+    fn test_apply_etag_for_unmatched_etag() {
+        let res = apply_etag(
+            &create_request(RequestHeaders::from([("If-None-Match", "\"foo\"")])),
+            create_response(),
+        );
+        assert_eq!(res.get_status(), &ResponseStatus::Ok);
+        assert_eq!(res.get_body(), "hello".as_bytes());
+    }
+
+    #[test]
+    fn test_if_none_match() {
+        assert!(if_none_match("*", "\"a\""));
+        assert!(if_none_match("\"b\", W/\"a\"", "\"a\""));
+        assert!(!if_none_match("\"b\"", "\"a\""));
+    }
+}
@@ -1,24 +1,86 @@
-use crate::http::request::Request;
+use crate::http::request::{Request, RequestMethod};
+use crate::http::response::{Response, ResponseStatus};
 use crate::settings::Settings;
 use anyhow::Result;
 use async_trait::async_trait;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::net::TcpStream;
+use tokio::io::{AsyncRead, AsyncWrite};
 
+pub mod builtin;
+pub mod cors;
 pub mod echo;
+pub mod negotiate;
+pub mod options;
+pub mod router;
+pub mod static_files;
 pub mod websocket;
 
+/// Stream of a connection to the client, e.g. a plain TCP, TLS or Unix domain socket stream
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> AsyncStream for T {}
+
+pub type ClientStream = Box<dyn AsyncStream>;
+
+/// What a handler responded to the request, e.g. for access logs
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct HandleOutcome {
+    status: ResponseStatus,
+    bytes_written: usize,
+}
+
+impl HandleOutcome {
+    pub fn new(status: ResponseStatus, bytes_written: usize) -> HandleOutcome {
+        HandleOutcome {
+            status,
+            bytes_written,
+        }
+    }
+
+    pub fn get_status(&self) -> &ResponseStatus {
+        &self.status
+    }
+
+    pub fn get_bytes_written(&self) -> usize {
+        self.bytes_written
+    }
+}
+
+/// Write `response` to `writer` and return the outcome of it
+pub async fn respond<W: AsyncWrite + Unpin>(
+    response: &Response,
+    writer: &mut W,
+) -> Result<HandleOutcome> {
+    let bytes_written = response.write_to(writer).await?;
+    Ok(HandleOutcome::new(
+        response.get_status().clone(),
+        bytes_written,
+    ))
+}
+
 #[async_trait]
 pub trait Handler {
     /// Return true if the handler target this request.
     fn accepts(&self, request: &Request, client_addr: SocketAddr, settings: Arc<Settings>) -> bool;
 
+    /// Respond to the request and return what was responded
     async fn handle(
         &self,
         request: Request,
-        mut stream: TcpStream,
+        mut stream: ClientStream,
         client_addr: SocketAddr,
         settings: Arc<Settings>,
-    ) -> Result<()>;
+    ) -> Result<HandleOutcome>;
+
+    /// Priority to decide the order of handlers. Handlers with higher priority are tried first,
+    /// so catch-all handlers should have lower priority than specific ones.
+    fn priority(&self) -> i32 {
+        0
+    }
+
+    /// Methods which the handler may accept, e.g. for `Allow` header of OPTIONS response
+    fn allowed_methods(&self) -> Vec<RequestMethod> {
+        RequestMethod::all()
+    }
 }
@@ -0,0 +1,311 @@
+use crate::http::error_page::error_response;
+use crate::http::handler::{respond, ClientStream, HandleOutcome, Handler};
+use crate::http::metrics;
+use crate::http::request::{Request, RequestMethod};
+use crate::http::response::{Response, ResponseBuilder, ResponseStatus};
+use crate::settings::Settings;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use log::error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+fn accepts_builtin(request: &Request, settings: &Settings, path: &str) -> bool {
+    *settings.builtin().enabled()
+        && request.get_method().is_get_or_head()
+        && request.get_path() == path
+}
+
+fn ok_response(content_type: &str, body: Vec<u8>) -> Response {
+    ResponseBuilder::ok().body(body, content_type).build()
+}
+
+/// Serve /favicon.ico from the configured file
+pub struct FaviconHandler;
+
+impl FaviconHandler {
+    async fn prepare_response(&self, settings: &Settings) -> Result<Response> {
+        let icon = match settings.builtin().favicon_path() {
+            Some(path) => tokio::fs::read(path)
+                .await
+                .with_context(|| format!("Failed to read favicon at {:?}", path))?,
+            None => vec![],
+        };
+        Ok(ok_response("image/x-icon", icon))
+    }
+}
+
+#[async_trait]
+impl Handler for FaviconHandler {
+    fn accepts(
+        &self,
+        request: &Request,
+        _client_addr: SocketAddr,
+        settings: Arc<Settings>,
+    ) -> bool {
+        accepts_builtin(request, &settings, "/favicon.ico")
+    }
+
+    async fn handle(
+        &self,
+        request: Request,
+        mut stream: ClientStream,
+        client_addr: SocketAddr,
+        settings: Arc<Settings>,
+    ) -> Result<HandleOutcome> {
+        let mut response = self
+            .prepare_response(&settings)
+            .await
+            .unwrap_or_else(|err| {
+                // a missing file is answered like an absent static file
+                let status = match err.downcast_ref::<std::io::Error>() {
+                    Some(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                        ResponseStatus::NotFound
+                    }
+                    _ => {
+                        error!(
+                            "Error occurred while handling request from {}: {:?}",
+                            client_addr, err
+                        );
+                        ResponseStatus::InternalServerError
+                    }
+                };
+                error_response(
+                    &status,
+                    request.get_header("Accept"),
+                    settings.http().error_page_template(),
+                )
+            });
+        response.add_headers(request.get_response_headers());
+        response.set_headers_only(request.get_method() == &RequestMethod::HEAD);
+        respond(&response, &mut stream).await
+    }
+
+    fn priority(&self) -> i32 {
+        50
+    }
+
+    fn allowed_methods(&self) -> Vec<RequestMethod> {
+        vec![RequestMethod::GET, RequestMethod::HEAD]
+    }
+}
+
+/// Serve /robots.txt with the configured content
+pub struct RobotsTxtHandler;
+
+impl RobotsTxtHandler {
+    fn prepare_response(&self, settings: &Settings) -> Response {
+        ok_response(
+            "text/plain",
+            settings.builtin().robots_txt().as_bytes().to_owned(),
+        )
+    }
+}
+
+#[async_trait]
+impl Handler for RobotsTxtHandler {
+    fn accepts(
+        &self,
+        request: &Request,
+        _client_addr: SocketAddr,
+        settings: Arc<Settings>,
+    ) -> bool {
+        accepts_builtin(request, &settings, "/robots.txt")
+    }
+
+    async fn handle(
+        &self,
+        request: Request,
+        mut stream: ClientStream,
+        _client_addr: SocketAddr,
+        settings: Arc<Settings>,
+    ) -> Result<HandleOutcome> {
+        let mut response = self.prepare_response(&settings);
+        response.add_headers(request.get_response_headers());
+        response.set_headers_only(request.get_method() == &RequestMethod::HEAD);
+        respond(&response, &mut stream).await
+    }
+
+    fn priority(&self) -> i32 {
+        50
+    }
+
+    fn allowed_methods(&self) -> Vec<RequestMethod> {
+        vec![RequestMethod::GET, RequestMethod::HEAD]
+    }
+}
+
+/// Serve metrics in Prometheus text format at the configured path
+pub struct MetricsHandler;
+
+impl MetricsHandler {
+    fn prepare_response(&self) -> Response {
+        ok_response(
+            "text/plain; version=0.0.4",
+            metrics::render_prometheus().into_bytes(),
+        )
+    }
+}
+
+#[async_trait]
+impl Handler for MetricsHandler {
+    fn accepts(
+        &self,
+        request: &Request,
+        _client_addr: SocketAddr,
+        settings: Arc<Settings>,
+    ) -> bool {
+        *settings.builtin().metrics_enabled()
+            && accepts_builtin(request, &settings, settings.builtin().metrics_path())
+    }
+
+    async fn handle(
+        &self,
+        request: Request,
+        mut stream: ClientStream,
+        _client_addr: SocketAddr,
+        _settings: Arc<Settings>,
+    ) -> Result<HandleOutcome> {
+        let mut response = self.prepare_response();
+        response.add_headers(request.get_response_headers());
+        response.set_headers_only(request.get_method() == &RequestMethod::HEAD);
+        respond(&response, &mut stream).await
+    }
+
+    fn priority(&self) -> i32 {
+        50
+    }
+
+    fn allowed_methods(&self) -> Vec<RequestMethod> {
+        vec![RequestMethod::GET, RequestMethod::HEAD]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::common::HTTPVersion;
+    use crate::http::request::{RequestBody, RequestHeaders, RequestLine};
+    use crate::util::temp::TempFile;
+    use std::str::FromStr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn create_request(path: &str) -> Request {
+        Request::new(
+            RequestLine::new(RequestMethod::GET, path, HTTPVersion::V1_1),
+            RequestHeaders::new(),
+            RequestBody::new(vec![]),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_favicon_handler() {
+        let icon = TempFile::new().unwrap();
+        icon.access_for_write()
+            .await
+            .unwrap()
+            .write_all(&[0x00, 0x00, 0x01, 0x00])
+            .await
+            .unwrap();
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "http": {},
+            "ws": {},
+            "builtin": {"favicon_path": icon.get_path()},
+        }))
+        .unwrap();
+        let addr = SocketAddr::from_str("127.0.0.1:8888").unwrap();
+        let settings = Arc::new(settings);
+
+        assert!(FaviconHandler.accepts(
+            &create_request("/favicon.ico"),
+            addr,
+            Arc::clone(&settings)
+        ));
+        let res = FaviconHandler.prepare_response(&settings).await.unwrap();
+        assert_eq!(res.get_header("Content-Type"), Some("image/x-icon"));
+        assert_eq!(res.get_body(), &[0x00, 0x00, 0x01, 0x00]);
+    }
+
+    #[tokio::test]
+    async fn test_favicon_handler_without_file() {
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "http": {},
+            "ws": {},
+            "builtin": {"favicon_path": "/nonexistent/favicon.ico"},
+        }))
+        .unwrap();
+        let (mut client, server) = tokio::io::duplex(1024);
+        let client_addr = SocketAddr::from_str("127.0.0.1:12345").unwrap();
+
+        FaviconHandler
+            .handle(
+                create_request("/favicon.ico"),
+                Box::new(server),
+                client_addr,
+                Arc::new(settings),
+            )
+            .await
+            .unwrap();
+
+        let mut res = String::new();
+        client.read_to_string(&mut res).await.unwrap();
+        assert!(res.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", res);
+    }
+
+    #[test]
+    fn test_robots_txt_handler() {
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "http": {},
+            "ws": {},
+            "builtin": {"robots_txt": "User-agent: *\nAllow: /\n"},
+        }))
+        .unwrap();
+        let addr = SocketAddr::from_str("127.0.0.1:8888").unwrap();
+        let settings = Arc::new(settings);
+
+        assert!(RobotsTxtHandler.accepts(
+            &create_request("/robots.txt"),
+            addr,
+            Arc::clone(&settings)
+        ));
+        let res = RobotsTxtHandler.prepare_response(&settings);
+        assert_eq!(res.get_header("Content-Type"), Some("text/plain"));
+        assert_eq!(res.get_body(), "User-agent: *\nAllow: /\n".as_bytes());
+    }
+
+    #[test]
+    fn test_metrics_handler() {
+        let addr = SocketAddr::from_str("127.0.0.1:8888").unwrap();
+        assert!(MetricsHandler.accepts(
+            &create_request("/metrics"),
+            addr,
+            Arc::new(Settings::default())
+        ));
+        let res = MetricsHandler.prepare_response();
+        assert_eq!(
+            res.get_header("Content-Type"),
+            Some("text/plain; version=0.0.4")
+        );
+        let body = String::from_utf8(res.get_body().to_vec()).unwrap();
+        assert!(body.contains("# TYPE ws_received_message_size_bytes histogram\n"));
+    }
+
+    #[test]
+    fn test_builtin_handlers_can_be_disabled() {
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "http": {},
+            "ws": {},
+            "builtin": {"enabled": false},
+        }))
+        .unwrap();
+        let addr = SocketAddr::from_str("127.0.0.1:8888").unwrap();
+        let settings = Arc::new(settings);
+
+        assert!(!FaviconHandler.accepts(
+            &create_request("/favicon.ico"),
+            addr,
+            Arc::clone(&settings)
+        ));
+        assert!(!RobotsTxtHandler.accepts(&create_request("/robots.txt"), addr, settings));
+    }
+}
@@ -0,0 +1,258 @@
+use crate::http::error_page::error_response;
+use crate::http::handler::{respond, ClientStream, HandleOutcome, Handler};
+use crate::http::request::{Request, RequestMethod};
+use crate::http::response::{Response, ResponseBuilder, ResponseStatus};
+use crate::settings::{Cors, Settings};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Allow cross-origin requests to `inner` from origins in `cors` settings, e.g.
+///
+/// ```ignore
+/// let handler = CorsHandler::new(Box::new(EchoHandler));
+/// ```
+///
+/// Preflight requests are responded by this handler with `Access-Control-Allow-*` headers,
+/// and `Access-Control-Allow-Origin` is added to responses of `inner` for allowed origins.
+pub struct CorsHandler {
+    inner: Box<dyn Handler + Send + Sync>,
+}
+
+impl CorsHandler {
+    pub fn new(inner: Box<dyn Handler + Send + Sync>) -> CorsHandler {
+        CorsHandler { inner }
+    }
+
+    /// Return the method of the actual request if the request is a preflight one
+    fn preflight_method(request: &Request) -> Option<RequestMethod> {
+        if request.get_method() != &RequestMethod::OPTIONS || request.get_header("Origin").is_none()
+        {
+            return None;
+        }
+        RequestMethod::from_str(request.get_header("Access-Control-Request-Method")?).ok()
+    }
+
+    /// Return value of Access-Control-Allow-Origin if `origin` is allowed
+    fn allowed_origin(cors: &Cors, origin: &str) -> Option<String> {
+        let origins = cors.allowed_origins();
+        if origins.iter().any(|allowed| allowed == "*") {
+            Some("*".to_string())
+        } else {
+            origins
+                .iter()
+                .find(|allowed| allowed.eq_ignore_ascii_case(origin))
+                .map(|_| origin.to_string())
+        }
+    }
+
+    /// Return whether responses differ by Origin, which caches should know by Vary
+    fn varies_by_origin(cors: &Cors) -> bool {
+        !cors.allowed_origins().iter().any(|allowed| allowed == "*")
+    }
+
+    fn preflight_response(
+        &self,
+        request: &Request,
+        method: &RequestMethod,
+        settings: &Settings,
+    ) -> Response {
+        let cors = settings.cors();
+        let allowed_methods = match cors.allowed_methods() {
+            Some(methods) => methods.clone(),
+            None => self
+                .inner
+                .allowed_methods()
+                .iter()
+                .map(|method| method.to_string())
+                .collect(),
+        };
+        let origin = request
+            .get_header("Origin")
+            .and_then(|origin| CorsHandler::allowed_origin(cors, origin));
+        let origin = match origin {
+            Some(origin) if allowed_methods.contains(&method.to_string()) => origin,
+            _ => {
+                return error_response(
+                    &ResponseStatus::Forbidden,
+                    request.get_header("Accept"),
+                    settings.http().error_page_template(),
+                )
+            }
+        };
+
+        let mut builder = ResponseBuilder::ok()
+            .header("Access-Control-Allow-Origin", origin)
+            .header("Access-Control-Allow-Methods", allowed_methods.join(", "));
+        if !cors.allowed_headers().is_empty() {
+            builder = builder.header(
+                "Access-Control-Allow-Headers",
+                cors.allowed_headers().join(", "),
+            );
+        }
+        if let Some(max_age) = cors.max_age() {
+            builder = builder.header("Access-Control-Max-Age", max_age.as_secs().to_string());
+        }
+        if CorsHandler::varies_by_origin(cors) {
+            builder = builder.header("Vary", "Origin");
+        }
+        builder.build()
+    }
+}
+
+#[async_trait]
+impl Handler for CorsHandler {
+    fn accepts(&self, request: &Request, client_addr: SocketAddr, settings: Arc<Settings>) -> bool {
+        match CorsHandler::preflight_method(request) {
+            Some(method) => {
+                // preflight requests are accepted if the actual request is
+                let mut actual = request.clone();
+                actual.set_method(method);
+                self.inner.accepts(&actual, client_addr, settings)
+            }
+            None => self.inner.accepts(request, client_addr, settings),
+        }
+    }
+
+    async fn handle(
+        &self,
+        mut request: Request,
+        mut stream: ClientStream,
+        client_addr: SocketAddr,
+        settings: Arc<Settings>,
+    ) -> Result<HandleOutcome> {
+        if let Some(method) = CorsHandler::preflight_method(&request) {
+            let mut response = self.preflight_response(&request, &method, &settings);
+            response.add_headers(request.get_response_headers());
+            return respond(&response, &mut stream).await;
+        }
+
+        let cors = settings.cors();
+        if let Some(origin) = request.get_header("Origin").map(|x| x.to_owned()) {
+            if let Some(allowed) = CorsHandler::allowed_origin(cors, &origin) {
+                request.add_response_header("Access-Control-Allow-Origin", &allowed);
+            }
+            if CorsHandler::varies_by_origin(cors) {
+                request.add_response_header("Vary", "Origin");
+            }
+        }
+        self.inner
+            .handle(request, stream, client_addr, settings)
+            .await
+    }
+
+    fn priority(&self) -> i32 {
+        self.inner.priority()
+    }
+
+    fn allowed_methods(&self) -> Vec<RequestMethod> {
+        self.inner.allowed_methods()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::handler::echo::EchoHandler;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// Send a raw request to CorsHandler wrapping EchoHandler and return the response
+    async fn request(raw_request: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, client_addr) = listener.accept().await.unwrap();
+            let request = Request::parse(&mut stream).await.unwrap();
+            let settings: Settings = serde_json::from_value(serde_json::json!({
+                "http": {},
+                "ws": {},
+                "cors": {
+                    "allowed_origins": ["https://example.com"],
+                    "allowed_headers": ["Content-Type"],
+                    "max_age": "10m",
+                },
+            }))
+            .unwrap();
+            let settings = Arc::new(settings);
+            let handler = CorsHandler::new(Box::new(EchoHandler));
+            assert!(handler.accepts(&request, client_addr, Arc::clone(&settings)));
+            handler
+                .handle(request, Box::new(stream), client_addr, settings)
+                .await
+                .unwrap();
+        });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(raw_request.as_bytes()).await.unwrap();
+        let mut buf = String::new();
+        client.read_to_string(&mut buf).await.unwrap();
+        server.await.unwrap();
+        buf
+    }
+
+    #[tokio::test]
+    async fn test_respond_preflight_request() {
+        let res = request(
+            &[
+                "OPTIONS /api HTTP/1.1",
+                "Host: localhost",
+                "Origin: https://example.com",
+                "Access-Control-Request-Method: PUT",
+                "",
+                "",
+            ]
+            .join("\r\n"),
+        )
+        .await;
+
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(res.contains("Access-Control-Allow-Origin: https://example.com\r\n"));
+        assert!(res.contains(
+            "Access-Control-Allow-Methods: GET, POST, PUT, DELETE, OPTIONS, HEAD, PATCH\r\n"
+        ));
+        assert!(res.contains("Access-Control-Allow-Headers: Content-Type\r\n"));
+        assert!(res.contains("Access-Control-Max-Age: 600\r\n"));
+        assert!(res.contains("Vary: Origin\r\n"));
+
+        let res = request(
+            &[
+                "OPTIONS /api HTTP/1.1",
+                "Host: localhost",
+                "Origin: https://evil.example.com",
+                "Access-Control-Request-Method: PUT",
+                "",
+                "",
+            ]
+            .join("\r\n"),
+        )
+        .await;
+        assert!(res.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+        assert!(!res.contains("Access-Control-Allow-Origin"));
+    }
+
+    #[tokio::test]
+    async fn test_add_allow_origin_to_cross_origin_request() {
+        let create_request = |origin: &str| {
+            [
+                "GET /api HTTP/1.1",
+                "Host: localhost",
+                &format!("Origin: {}", origin),
+                "",
+                "",
+            ]
+            .join("\r\n")
+        };
+
+        let res = request(&create_request("https://example.com")).await;
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(res.contains("Access-Control-Allow-Origin: https://example.com\r\n"));
+        assert!(res.contains("Vary: Origin\r\n"));
+
+        let res = request(&create_request("https://evil.example.com")).await;
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(!res.contains("Access-Control-Allow-Origin"));
+    }
+}
@@ -1,17 +1,18 @@
 use crate::http::common::{HTTPVersion, IMFDateTime};
-use crate::http::handler::Handler;
-use crate::http::request::{Request, RequestParseError};
+use crate::http::compression::compress_for;
+use crate::http::error_page::error_response;
+use crate::http::etag::apply_etag;
+use crate::http::handler::{respond, ClientStream, HandleOutcome, Handler};
+use crate::http::request::{Request, RequestMethod, RequestParseError};
 use crate::http::response::{Response, ResponseBody, ResponseHeaders, ResponseStatus, StatusLine};
 use crate::settings::Settings;
-use anyhow::{Context, Result};
+use anyhow::Result;
 use async_trait::async_trait;
 use log::error;
 use serde::Serialize;
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::io::AsyncWriteExt;
-use tokio::net::TcpStream;
 
 pub struct EchoHandler;
 
@@ -39,6 +40,75 @@ impl EchoResponse {
     }
 }
 
+impl EchoHandler {
+    /// Return request headers to be reflected, which are filtered and capped by settings.
+    /// Header names are compared case-insensitively.
+    fn echoed_headers(request: &Request, settings: &Settings) -> HashMap<String, String> {
+        let http = settings.http();
+        let listed = |list: &[String], key: &str| list.iter().any(|x| x.eq_ignore_ascii_case(key));
+
+        let mut headers: Vec<_> = request
+            .get_headers()
+            .iter()
+            .filter(|(k, _)| match http.echo_header_allowlist() {
+                Some(allowlist) => listed(allowlist, k),
+                None => true,
+            })
+            .filter(|(k, _)| !listed(http.echo_header_denylist(), k))
+            .map(|(k, v)| (k.to_owned(), v.to_owned()))
+            .collect();
+        // sort to decide which headers are kept regardless of the order of HashMap
+        headers.sort();
+        if let Some(max_headers) = http.echo_max_headers() {
+            headers.truncate(*max_headers);
+        }
+        headers.into_iter().collect()
+    }
+
+    /// Return the request echoed as JSON, which is compressed if the client accepts it
+    fn prepare_response(
+        request: &Request,
+        settings: &Settings,
+    ) -> Result<Response, RequestParseError> {
+        let echo_response = EchoResponse::new(
+            request.get_method().to_string(),
+            request.get_path().to_owned(),
+            EchoHandler::echoed_headers(request, settings),
+            String::from_utf8_lossy(request.get_body()).to_string(),
+        );
+        let response_body = ResponseBody::new(
+            serde_json::to_string(&echo_response)
+                .map_err(|_err| {
+                    RequestParseError::new(
+                        ResponseStatus::InternalServerError,
+                        "Failed to create response body",
+                    )
+                })?
+                .as_bytes()
+                .to_owned(),
+        );
+
+        let response = Response::new(
+            StatusLine::new(HTTPVersion::V1_1, ResponseStatus::Ok),
+            ResponseHeaders::from([
+                ("Date", IMFDateTime::now().to_string()),
+                ("Content-Type", "application/json".to_string()),
+                ("Content-Length", response_body.len().to_string()),
+            ]),
+            response_body,
+        );
+        // compressed before ETag is computed since the tag differs by the representation
+        let response = compress_for(response, request, settings.http()).map_err(|_err| {
+            RequestParseError::new(
+                ResponseStatus::InternalServerError,
+                "Failed to compress response body",
+            )
+        })?;
+
+        Ok(apply_etag(request, response))
+    }
+}
+
 #[async_trait]
 impl Handler for EchoHandler {
     fn accepts(
@@ -53,69 +123,162 @@ impl Handler for EchoHandler {
     async fn handle(
         &self,
         request: Request,
-        mut stream: TcpStream,
+        mut stream: ClientStream,
         client_addr: SocketAddr,
-        _settings: Arc<Settings>,
-    ) -> Result<()> {
-        fn prepare_response(request: Request) -> Result<Response, RequestParseError> {
-            let echo_response = EchoResponse::new(
-                request.get_method().to_string(),
-                request.get_path().to_owned(),
-                request
-                    .get_headers()
-                    .iter()
-                    .map(|(k, v)| (k.to_owned(), v.to_owned()))
-                    .collect(),
-                String::from_utf8_lossy(request.get_body()).to_string(),
-            );
-            let response_body = ResponseBody::new(
-                serde_json::to_string(&echo_response)
-                    .map_err(|_err| {
-                        RequestParseError::new(
-                            ResponseStatus::InternalServerError,
-                            "Failed to create response body",
-                        )
-                    })?
-                    .as_bytes()
-                    .to_owned(),
-            );
-
-            let response = Response::new(
-                StatusLine::new(HTTPVersion::V1_1, ResponseStatus::Ok),
-                ResponseHeaders::from([
-                    ("Date", IMFDateTime::now().to_string()),
-                    ("Content-Type", "application/json".to_string()),
-                    ("Content-Length", response_body.len().to_string()),
-                ]),
-                response_body,
-            );
-
-            Ok(response)
-        }
+        settings: Arc<Settings>,
+    ) -> Result<HandleOutcome> {
+        let mut response =
+            EchoHandler::prepare_response(&request, &settings).unwrap_or_else(|err| {
+                if err.get_status().is_server_error() {
+                    error!(
+                        "Error occurred while handling request from {}: {:?}",
+                        client_addr, err
+                    );
+                }
+                error_response(
+                    err.get_status(),
+                    request.get_header("Accept"),
+                    settings.http().error_page_template(),
+                )
+            });
+        response.add_headers(request.get_response_headers());
+        response.set_headers_only(request.get_method() == &RequestMethod::HEAD);
+
+        respond(&response, &mut stream).await
+    }
+
+    fn priority(&self) -> i32 {
+        // accepts any request
+        i32::MIN
+    }
+}
 
-        let response = prepare_response(request).unwrap_or_else(|err| {
-            if err.get_status().is_server_error() {
-                error!(
-                    "Error occurred while handling request from {}: {:?}",
-                    client_addr, err
-                );
-            }
-            Response::new(
-                StatusLine::new(HTTPVersion::V1_1, err.get_status().clone()),
-                ResponseHeaders::from([
-                    ("Date", IMFDateTime::now().to_string().as_str()),
-                    ("Connection", "close"),
-                    ("Content-Length", "0"),
-                ]),
-                ResponseBody::new(vec![]),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::request::{RequestBody, RequestHeaders, RequestLine, RequestMethod};
+    use tokio::io::AsyncReadExt;
+
+    #[test]
+    fn test_echo_handler_is_handler() {
+        let handler: Box<dyn Handler + Send + Sync> = Box::new(EchoHandler);
+        let request = Request::new(
+            RequestLine::new(RequestMethod::GET, "/", HTTPVersion::V1_1),
+            RequestHeaders::new(),
+            RequestBody::new(vec![]),
+        );
+        let client_addr = "127.0.0.1:12345".parse().unwrap();
+        assert!(handler.accepts(&request, client_addr, Arc::new(Settings::default())));
+        assert_eq!(handler.priority(), i32::MIN);
+    }
+
+    #[test]
+    fn test_large_echo_body_is_gzipped_if_accepted() {
+        let create_request = |headers: RequestHeaders| {
+            Request::new(
+                RequestLine::new(RequestMethod::POST, "/", HTTPVersion::V1_1),
+                headers,
+                RequestBody::new("a".repeat(4096).into_bytes()),
             )
-        });
+        };
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "http": {"enable_compression": true, "compression_min_bytes": "1KB"},
+            "ws": {},
+        }))
+        .unwrap();
+
+        let request = create_request(RequestHeaders::from([("Accept-Encoding", "gzip")]));
+        let response = EchoHandler::prepare_response(&request, &settings).unwrap();
+        assert_eq!(response.get_header("Content-Encoding"), Some("gzip"));
+        assert_eq!(
+            response.get_header("Content-Length"),
+            Some(response.get_body().len().to_string().as_str())
+        );
+        assert!(response.get_body().len() < 4096);
 
-        stream
-            .write(&response.encode())
+        let request = create_request(RequestHeaders::new());
+        let response = EchoHandler::prepare_response(&request, &settings).unwrap();
+        assert_eq!(response.get_header("Content-Encoding"), None);
+        assert!(response.get_body().len() > 4096);
+    }
+
+    #[tokio::test]
+    async fn test_echo_handler_over_duplex() {
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let request = Request::new(
+            RequestLine::new(RequestMethod::POST, "/foo", HTTPVersion::V1_1),
+            RequestHeaders::from([("Host", "localhost"), ("Content-Length", "5")]),
+            RequestBody::new(b"hello".to_vec()),
+        );
+        let client_addr = "127.0.0.1:12345".parse().unwrap();
+
+        EchoHandler
+            .handle(
+                request,
+                Box::new(server),
+                client_addr,
+                Arc::new(Settings::default()),
+            )
             .await
-            .context("Failed to write response")?;
+            .unwrap();
+
+        let mut res = String::new();
+        client.read_to_string(&mut res).await.unwrap();
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(res.ends_with(r#""data":"hello"}"#));
+    }
+
+    #[test]
+    fn test_echoed_headers_are_filtered_and_capped() {
+        let request = Request::new(
+            RequestLine::new(RequestMethod::GET, "/", HTTPVersion::V1_1),
+            RequestHeaders::from([
+                ("Host", "localhost"),
+                ("Accept", "*/*"),
+                ("User-Agent", "curl"),
+                ("Cookie", "secret"),
+            ]),
+            RequestBody::new(vec![]),
+        );
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "http": {
+                "echo_max_headers": 2,
+                "echo_header_allowlist": ["host", "accept", "user-agent", "cookie"],
+                "echo_header_denylist": ["Cookie"],
+            },
+            "ws": {},
+        }))
+        .unwrap();
+
+        let actual = EchoHandler::echoed_headers(&request, &settings);
+
+        assert_eq!(
+            actual,
+            HashMap::from([
+                ("Accept".to_string(), "*/*".to_string()),
+                ("Host".to_string(), "localhost".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_echoed_headers_by_allowlist() {
+        let request = Request::new(
+            RequestLine::new(RequestMethod::GET, "/", HTTPVersion::V1_1),
+            RequestHeaders::from([("Host", "localhost"), ("Cookie", "secret")]),
+            RequestBody::new(vec![]),
+        );
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "http": {"echo_header_allowlist": ["Host"]},
+            "ws": {},
+        }))
+        .unwrap();
+
+        let actual = EchoHandler::echoed_headers(&request, &settings);
 
-        Ok(())
+        assert_eq!(
+            actual,
+            HashMap::from([("Host".to_string(), "localhost".to_string())])
+        );
     }
 }
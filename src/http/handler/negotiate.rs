@@ -0,0 +1,119 @@
+use crate::http::handler::{ClientStream, HandleOutcome, Handler};
+use crate::http::request::Request;
+use crate::settings::Settings;
+use anyhow::Result;
+use async_trait::async_trait;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// Serve WebSocket and plain HTTP behind one endpoint.
+/// A request is dispatched to `websocket` if it asks for WebSocket upgrade, otherwise to `http`.
+pub struct NegotiateHandler {
+    websocket: Box<dyn Handler + Send + Sync>,
+    http: Box<dyn Handler + Send + Sync>,
+}
+
+impl NegotiateHandler {
+    pub fn new(
+        websocket: Box<dyn Handler + Send + Sync>,
+        http: Box<dyn Handler + Send + Sync>,
+    ) -> NegotiateHandler {
+        NegotiateHandler { websocket, http }
+    }
+
+    fn select(&self, request: &Request) -> &(dyn Handler + Send + Sync) {
+        if request.is_websocket_upgrade() {
+            self.websocket.as_ref()
+        } else {
+            self.http.as_ref()
+        }
+    }
+}
+
+#[async_trait]
+impl Handler for NegotiateHandler {
+    fn accepts(&self, request: &Request, client_addr: SocketAddr, settings: Arc<Settings>) -> bool {
+        self.select(request).accepts(request, client_addr, settings)
+    }
+
+    async fn handle(
+        &self,
+        request: Request,
+        stream: ClientStream,
+        client_addr: SocketAddr,
+        settings: Arc<Settings>,
+    ) -> Result<HandleOutcome> {
+        self.select(&request)
+            .handle(request, stream, client_addr, settings)
+            .await
+    }
+
+    fn priority(&self) -> i32 {
+        self.websocket.priority().min(self.http.priority())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::handler::echo::EchoHandler;
+    use crate::http::handler::websocket::WebSocketHandler;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// Send a raw request to the handler and return the status line of the response.
+    async fn request_status_line(handler: Arc<NegotiateHandler>, raw_request: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, client_addr) = listener.accept().await.unwrap();
+            let request = Request::parse(&mut stream).await.unwrap();
+            let settings = Arc::new(Settings::default());
+            assert!(handler.accepts(&request, client_addr, Arc::clone(&settings)));
+            let _ = handler
+                .handle(request, Box::new(stream), client_addr, settings)
+                .await;
+        });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(raw_request.as_bytes()).await.unwrap();
+        let mut buf = vec![];
+        while !buf.windows(2).any(|w| w == b"\r\n") {
+            let mut chunk = [0u8; 1024];
+            let n = client.read(&mut chunk).await.unwrap();
+            assert_ne!(n, 0, "connection closed before status line");
+            buf.extend(&chunk[..n]);
+        }
+        drop(client);
+        server.await.unwrap();
+
+        let res = String::from_utf8_lossy(&buf).to_string();
+        res.split("\r\n").next().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_negotiate_handler_dispatches_by_upgrade() {
+        let handler = Arc::new(NegotiateHandler::new(
+            Box::new(WebSocketHandler::new()),
+            Box::new(EchoHandler),
+        ));
+
+        let http_request = ["GET /chat HTTP/1.1", "Host: localhost", "", ""].join("\r\n");
+        let actual = request_status_line(Arc::clone(&handler), &http_request).await;
+        assert_eq!(actual, "HTTP/1.1 200 OK");
+
+        let ws_request = [
+            "GET /chat HTTP/1.1",
+            "Host: localhost",
+            "Upgrade: websocket",
+            "Connection: Upgrade",
+            "Sec-WebSocket-Version: 13",
+            "Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==",
+            "",
+            "",
+        ]
+        .join("\r\n");
+        let actual = request_status_line(Arc::clone(&handler), &ws_request).await;
+        assert_eq!(actual, "HTTP/1.1 101 Switching Protocol");
+    }
+}
@@ -0,0 +1,93 @@
+use crate::http::handler::{respond, ClientStream, HandleOutcome, Handler};
+use crate::http::request::{Request, RequestMethod};
+use crate::http::response::{Response, ResponseBuilder};
+use crate::settings::Settings;
+use anyhow::Result;
+use async_trait::async_trait;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// Return response to OPTIONS request telling `methods` in `Allow` header
+pub fn options_response(methods: &[RequestMethod]) -> Response {
+    let allow = methods
+        .iter()
+        .map(|method| method.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    ResponseBuilder::ok().header("Allow", allow).build()
+}
+
+/// Handler for `OPTIONS *`, which asks capabilities of the server (RFC 7231 4.3.7)
+pub struct ServerOptionsHandler;
+
+impl ServerOptionsHandler {
+    fn prepare_response(&self) -> Response {
+        options_response(&RequestMethod::all())
+    }
+}
+
+#[async_trait]
+impl Handler for ServerOptionsHandler {
+    fn accepts(
+        &self,
+        request: &Request,
+        _client_addr: SocketAddr,
+        _settings: Arc<Settings>,
+    ) -> bool {
+        request.get_method() == &RequestMethod::OPTIONS && request.raw_target() == "*"
+    }
+
+    async fn handle(
+        &self,
+        request: Request,
+        mut stream: ClientStream,
+        _client_addr: SocketAddr,
+        _settings: Arc<Settings>,
+    ) -> Result<HandleOutcome> {
+        let mut response = self.prepare_response();
+        response.add_headers(request.get_response_headers());
+        respond(&response, &mut stream).await
+    }
+
+    fn priority(&self) -> i32 {
+        100
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::request::{RequestBody, RequestHeaders, RequestLine};
+    use crate::http::response::ResponseStatus;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_server_options_handler() {
+        let request = Request::new(
+            RequestLine::parse("OPTIONS * HTTP/1.1").unwrap(),
+            RequestHeaders::new(),
+            RequestBody::new(vec![]),
+        );
+        let addr = SocketAddr::from_str("127.0.0.1:8888").unwrap();
+        assert!(ServerOptionsHandler.accepts(&request, addr, Arc::new(Settings::default())));
+
+        let res = ServerOptionsHandler.prepare_response();
+        assert_eq!(res.get_status(), &ResponseStatus::Ok);
+        assert_eq!(
+            res.get_header("Allow"),
+            Some("GET, POST, PUT, DELETE, OPTIONS, HEAD, PATCH")
+        );
+        assert!(res.get_body().is_empty());
+    }
+
+    #[test]
+    fn test_server_options_handler_ignores_other_targets() {
+        let request = Request::new(
+            RequestLine::parse("OPTIONS /foo HTTP/1.1").unwrap(),
+            RequestHeaders::new(),
+            RequestBody::new(vec![]),
+        );
+        let addr = SocketAddr::from_str("127.0.0.1:8888").unwrap();
+        assert!(!ServerOptionsHandler.accepts(&request, addr, Arc::new(Settings::default())));
+    }
+}
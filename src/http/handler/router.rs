@@ -0,0 +1,272 @@
+use crate::http::error_page::error_response;
+use crate::http::handler::options::options_response;
+use crate::http::handler::{respond, ClientStream, HandleOutcome, Handler};
+use crate::http::request::{Request, RequestMethod};
+use crate::http::response::ResponseStatus;
+use crate::settings::Settings;
+use anyhow::Result;
+use async_trait::async_trait;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// Dispatch requests to handlers by path, e.g.
+///
+/// ```ignore
+/// let router = RouterHandler::new()
+///     .route("/ws", Box::new(WebSocketHandler::new()))
+///     .route("/api/*", Box::new(EchoHandler));
+/// ```
+///
+/// A pattern is an exact path, or a prefix followed by `/*` matching the prefix and paths under it.
+/// Routes are tried in the added order, and the first handler which `accepts` the request
+/// handles it. Requests matching no route are responded with 404.
+/// OPTIONS requests are responded by the router with methods allowed by the matching routes.
+#[derive(Default)]
+pub struct RouterHandler {
+    routes: Vec<(String, Box<dyn Handler + Send + Sync>)>,
+}
+
+impl RouterHandler {
+    pub fn new() -> RouterHandler {
+        RouterHandler::default()
+    }
+
+    pub fn route(
+        mut self,
+        pattern: &str,
+        handler: Box<dyn Handler + Send + Sync>,
+    ) -> RouterHandler {
+        self.routes.push((pattern.to_string(), handler));
+        self
+    }
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        match pattern.strip_suffix("/*") {
+            Some(prefix) => match path.strip_prefix(prefix) {
+                Some(rest) => rest.is_empty() || rest.starts_with('/'),
+                None => false,
+            },
+            None => path == pattern,
+        }
+    }
+
+    /// Return methods allowed by handlers of routes matching `path`, or None if no route matches
+    fn allowed_methods_for(&self, path: &str) -> Option<Vec<RequestMethod>> {
+        let mut allowed = vec![];
+        for (_, handler) in self
+            .routes
+            .iter()
+            .filter(|(pattern, _)| RouterHandler::matches(pattern, path))
+        {
+            allowed.extend(handler.allowed_methods());
+        }
+        if allowed.is_empty() {
+            return None;
+        }
+        allowed.push(RequestMethod::OPTIONS);
+        // keep the order of `RequestMethod::all`
+        Some(
+            RequestMethod::all()
+                .into_iter()
+                .filter(|method| allowed.contains(method))
+                .collect(),
+        )
+    }
+
+    fn select(
+        &self,
+        request: &Request,
+        client_addr: SocketAddr,
+        settings: &Arc<Settings>,
+    ) -> Option<&(dyn Handler + Send + Sync)> {
+        self.routes
+            .iter()
+            .filter(|(pattern, _)| RouterHandler::matches(pattern, request.get_path()))
+            .find(|(_, handler)| handler.accepts(request, client_addr, Arc::clone(settings)))
+            .map(|(_, handler)| handler.as_ref())
+    }
+}
+
+#[async_trait]
+impl Handler for RouterHandler {
+    fn accepts(
+        &self,
+        _request: &Request,
+        _client_addr: SocketAddr,
+        _settings: Arc<Settings>,
+    ) -> bool {
+        true
+    }
+
+    async fn handle(
+        &self,
+        request: Request,
+        mut stream: ClientStream,
+        client_addr: SocketAddr,
+        settings: Arc<Settings>,
+    ) -> Result<HandleOutcome> {
+        if request.get_method() == &RequestMethod::OPTIONS {
+            if let Some(methods) = self.allowed_methods_for(request.get_path()) {
+                let mut response = options_response(&methods);
+                response.add_headers(request.get_response_headers());
+                return respond(&response, &mut stream).await;
+            }
+        }
+        match self.select(&request, client_addr, &settings) {
+            Some(handler) => handler.handle(request, stream, client_addr, settings).await,
+            None => {
+                let mut response = error_response(
+                    &ResponseStatus::NotFound,
+                    request.get_header("Accept"),
+                    settings.http().error_page_template(),
+                );
+                response.add_headers(request.get_response_headers());
+                response.set_headers_only(request.get_method() == &RequestMethod::HEAD);
+                respond(&response, &mut stream).await
+            }
+        }
+    }
+
+    fn priority(&self) -> i32 {
+        // accepts any request to respond 404
+        i32::MIN
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// Write its name as the response
+    struct NameHandler(&'static str);
+
+    #[async_trait]
+    impl Handler for NameHandler {
+        fn accepts(
+            &self,
+            _request: &Request,
+            _client_addr: SocketAddr,
+            _settings: Arc<Settings>,
+        ) -> bool {
+            true
+        }
+
+        async fn handle(
+            &self,
+            _request: Request,
+            mut stream: ClientStream,
+            _client_addr: SocketAddr,
+            _settings: Arc<Settings>,
+        ) -> Result<HandleOutcome> {
+            stream.write_all(self.0.as_bytes()).await?;
+            Ok(HandleOutcome::new(ResponseStatus::Ok, self.0.len()))
+        }
+    }
+
+    /// Send a request of `path` to the router and return the response
+    async fn request(router: Arc<RouterHandler>, path: &str) -> String {
+        request_with_method(router, "GET", path).await
+    }
+
+    async fn request_with_method(router: Arc<RouterHandler>, method: &str, path: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, client_addr) = listener.accept().await.unwrap();
+            let request = Request::parse(&mut stream).await.unwrap();
+            let settings = Arc::new(Settings::default());
+            router
+                .handle(request, Box::new(stream), client_addr, settings)
+                .await
+                .unwrap();
+        });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let request = format!("{} {} HTTP/1.1\r\nHost: localhost\r\n\r\n", method, path);
+        client.write_all(request.as_bytes()).await.unwrap();
+        let mut buf = String::new();
+        client.read_to_string(&mut buf).await.unwrap();
+        server.await.unwrap();
+        buf
+    }
+
+    fn create_router() -> Arc<RouterHandler> {
+        Arc::new(
+            RouterHandler::new()
+                .route("/ws", Box::new(NameHandler("ws")))
+                .route("/api/*", Box::new(NameHandler("api"))),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_route_exact_path() {
+        let router = create_router();
+        assert_eq!(request(Arc::clone(&router), "/ws").await, "ws");
+        assert!(request(router, "/ws/chat")
+            .await
+            .starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_route_prefix() {
+        let router = create_router();
+        assert_eq!(request(Arc::clone(&router), "/api/echo").await, "api");
+        assert_eq!(request(Arc::clone(&router), "/api").await, "api");
+        assert!(request(router, "/apiecho")
+            .await
+            .starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_respond_allowed_methods_to_options() {
+        struct GetHandler;
+
+        #[async_trait]
+        impl Handler for GetHandler {
+            fn accepts(
+                &self,
+                request: &Request,
+                _client_addr: SocketAddr,
+                _settings: Arc<Settings>,
+            ) -> bool {
+                request.get_method() == &RequestMethod::GET
+            }
+
+            async fn handle(
+                &self,
+                _request: Request,
+                _stream: ClientStream,
+                _client_addr: SocketAddr,
+                _settings: Arc<Settings>,
+            ) -> Result<HandleOutcome> {
+                Ok(HandleOutcome::new(ResponseStatus::Ok, 0))
+            }
+
+            fn allowed_methods(&self) -> Vec<RequestMethod> {
+                vec![RequestMethod::GET]
+            }
+        }
+
+        let router = Arc::new(
+            RouterHandler::new()
+                .route("/api/*", Box::new(GetHandler))
+                .route("/api/echo", Box::new(NameHandler("echo"))),
+        );
+
+        let res = request_with_method(Arc::clone(&router), "OPTIONS", "/api/users").await;
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(res.contains("Allow: GET, OPTIONS\r\n"));
+        let res = request_with_method(Arc::clone(&router), "OPTIONS", "/api/echo").await;
+        assert!(res.contains("Allow: GET, POST, PUT, DELETE, OPTIONS, HEAD, PATCH\r\n"));
+        let res = request_with_method(router, "OPTIONS", "/foo").await;
+        assert!(res.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_respond_not_found_without_route() {
+        let res = request(create_router(), "/foo").await;
+        assert!(res.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
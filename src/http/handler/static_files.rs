@@ -0,0 +1,328 @@
+use crate::http::common::{HTTPVersion, IMFDateTime};
+use crate::http::compression::ContentCoding;
+use crate::http::error_page::error_response;
+use crate::http::handler::{respond, ClientStream, HandleOutcome, Handler};
+use crate::http::request::{Request, RequestMethod};
+use crate::http::response::{Response, ResponseBody, ResponseHeaders, ResponseStatus, StatusLine};
+use crate::settings::Settings;
+use anyhow::Result;
+use async_trait::async_trait;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Serve files in `static_files.root` under `static_files.prefix`.
+/// A pre-compressed `.gz` sidecar is served instead if the client accepts gzip.
+pub struct StaticFileHandler;
+
+impl StaticFileHandler {
+    /// Return path of the file for the request, or None if it's not under the prefix
+    fn file_path(request: &Request, settings: &Settings) -> Option<PathBuf> {
+        let static_files = settings.static_files();
+        let root = static_files.root().as_ref()?;
+        let path = request.get_decoded_path().ok()?;
+        let relative = path.strip_prefix(static_files.prefix().as_str())?;
+        if relative.is_empty() || relative.ends_with('/') {
+            return None;
+        }
+        // the path is normalized before decoding, so dot segments may appear again, e.g. `%2e%2e`,
+        // and empty segments from `%2F`, which make `relative` absolute, e.g. `/static/%2Fetc`
+        if relative.split('/').any(|segment| {
+            segment.is_empty() || segment == ".." || segment == "." || segment.contains('\\')
+        }) {
+            return None;
+        }
+        let path = root.join(relative);
+        if !path.starts_with(root) {
+            return None;
+        }
+        Some(path)
+    }
+
+    fn content_type(path: &Path) -> &'static str {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("html") => "text/html",
+            Some("css") => "text/css",
+            Some("js") => "text/javascript",
+            Some("json") => "application/json",
+            Some("txt") => "text/plain",
+            Some("png") => "image/png",
+            Some("svg") => "image/svg+xml",
+            Some("ico") => "image/x-icon",
+            _ => "application/octet-stream",
+        }
+    }
+
+    /// Return true if the file is not modified since If-Modified-Since (RFC 7232 3.3).
+    /// The header is ignored if the request has If-None-Match or the date is illegal.
+    fn is_not_modified(request: &Request, last_modified: &IMFDateTime) -> bool {
+        if request.get_header("If-None-Match").is_some() {
+            return false;
+        }
+        let since = request
+            .get_header("If-Modified-Since")
+            .and_then(|value| IMFDateTime::parse(value).ok());
+        matches!(since, Some(since) if last_modified <= &since)
+    }
+
+    async fn prepare_response(
+        &self,
+        request: &Request,
+        path: &Path,
+        settings: &Settings,
+    ) -> Response {
+        let last_modified = tokio::fs::metadata(path)
+            .await
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .map(IMFDateTime::from);
+        if let Some(last_modified) = &last_modified {
+            if Self::is_not_modified(request, last_modified) {
+                return Response::new(
+                    StatusLine::new(HTTPVersion::V1_1, ResponseStatus::NotModified),
+                    ResponseHeaders::from([
+                        ("Date", IMFDateTime::now().to_string()),
+                        ("Last-Modified", last_modified.to_string()),
+                    ]),
+                    ResponseBody::new(vec![]),
+                );
+            }
+        }
+
+        let content_type = Self::content_type(path);
+        let mut headers = ResponseHeaders::from([
+            ("Date", IMFDateTime::now().to_string()),
+            ("Content-Type", content_type.to_string()),
+            // the response may differ by whether .gz sidecar is acceptable
+            ("Vary", "Accept-Encoding".to_string()),
+        ]);
+        if let Some(last_modified) = &last_modified {
+            headers.insert("Last-Modified".to_string(), last_modified.to_string());
+        }
+
+        let accepts_gzip =
+            ContentCoding::negotiate(request.get_header("Accept-Encoding")) == ContentCoding::Gzip;
+        let sidecar = if accepts_gzip {
+            let mut sidecar = path.as_os_str().to_owned();
+            sidecar.push(".gz");
+            tokio::fs::read(sidecar).await.ok()
+        } else {
+            None
+        };
+
+        let body = match sidecar {
+            Some(body) => {
+                headers.insert("Content-Encoding".to_string(), "gzip".to_string());
+                body
+            }
+            None => match tokio::fs::read(path).await {
+                Ok(body) => body,
+                Err(_) => {
+                    return error_response(
+                        &ResponseStatus::NotFound,
+                        request.get_header("Accept"),
+                        settings.http().error_page_template(),
+                    )
+                }
+            },
+        };
+        headers.insert("Content-Length".to_string(), body.len().to_string());
+
+        Response::new(
+            StatusLine::new(HTTPVersion::V1_1, ResponseStatus::Ok),
+            headers,
+            ResponseBody::new(body),
+        )
+    }
+}
+
+#[async_trait]
+impl Handler for StaticFileHandler {
+    fn accepts(
+        &self,
+        request: &Request,
+        _client_addr: SocketAddr,
+        settings: Arc<Settings>,
+    ) -> bool {
+        request.get_method().is_get_or_head()
+            && StaticFileHandler::file_path(request, &settings).is_some()
+    }
+
+    async fn handle(
+        &self,
+        request: Request,
+        mut stream: ClientStream,
+        _client_addr: SocketAddr,
+        settings: Arc<Settings>,
+    ) -> Result<HandleOutcome> {
+        let mut response = match StaticFileHandler::file_path(&request, &settings) {
+            Some(path) => self.prepare_response(&request, &path, &settings).await,
+            None => error_response(
+                &ResponseStatus::NotFound,
+                request.get_header("Accept"),
+                settings.http().error_page_template(),
+            ),
+        };
+        response.add_headers(request.get_response_headers());
+        response.set_headers_only(request.get_method() == &RequestMethod::HEAD);
+        respond(&response, &mut stream).await
+    }
+
+    fn priority(&self) -> i32 {
+        50
+    }
+
+    fn allowed_methods(&self) -> Vec<RequestMethod> {
+        vec![RequestMethod::GET, RequestMethod::HEAD]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::request::{RequestBody, RequestHeaders, RequestLine};
+    use crate::util::temp::TempFile;
+
+    fn create_request(path: &str, accept_encoding: Option<&str>) -> Request {
+        let mut headers = RequestHeaders::new();
+        if let Some(accept_encoding) = accept_encoding {
+            headers.insert("Accept-Encoding".to_string(), accept_encoding.to_string());
+        }
+        Request::new(
+            RequestLine::new(RequestMethod::GET, path, HTTPVersion::V1_1),
+            headers,
+            RequestBody::new(vec![]),
+        )
+    }
+
+    fn create_settings(file: &TempFile) -> Settings {
+        serde_json::from_value(serde_json::json!({
+            "http": {},
+            "ws": {},
+            "static_files": {"root": file.get_path().parent().unwrap()},
+        }))
+        .unwrap()
+    }
+
+    fn request_path(file: &TempFile) -> String {
+        let name = file.get_path().file_name().unwrap().to_str().unwrap();
+        format!("/static/{}", name)
+    }
+
+    #[tokio::test]
+    async fn test_serve_gzip_sidecar() {
+        let file = TempFile::new().unwrap();
+        std::fs::write(file.get_path(), "hello").unwrap();
+        let mut sidecar_path = file.get_path().as_os_str().to_owned();
+        sidecar_path.push(".gz");
+        let sidecar = TempFile::with_path(sidecar_path.into()).unwrap();
+        std::fs::write(sidecar.get_path(), [0x1f, 0x8b]).unwrap();
+        let settings = create_settings(&file);
+
+        let request = create_request(&request_path(&file), Some("gzip"));
+        let path = StaticFileHandler::file_path(&request, &settings).unwrap();
+        let res = StaticFileHandler
+            .prepare_response(&request, &path, &settings)
+            .await;
+
+        assert_eq!(res.get_header("Content-Encoding"), Some("gzip"));
+        assert_eq!(res.get_header("Content-Type"), Some("text/plain"));
+        assert_eq!(res.get_body(), &[0x1f, 0x8b]);
+
+        // the original file is served if the client doesn't accept gzip
+        let request = create_request(&request_path(&file), None);
+        let res = StaticFileHandler
+            .prepare_response(&request, &path, &settings)
+            .await;
+
+        assert_eq!(res.get_header("Content-Encoding"), None);
+        assert_eq!(res.get_body(), "hello".as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_fall_back_to_file_without_sidecar() {
+        let file = TempFile::new().unwrap();
+        std::fs::write(file.get_path(), "hello").unwrap();
+        let settings = create_settings(&file);
+
+        let request = create_request(&request_path(&file), Some("gzip"));
+        let path = StaticFileHandler::file_path(&request, &settings).unwrap();
+        let res = StaticFileHandler
+            .prepare_response(&request, &path, &settings)
+            .await;
+
+        assert_eq!(res.get_status(), &ResponseStatus::Ok);
+        assert_eq!(res.get_header("Content-Encoding"), None);
+        assert_eq!(res.get_header("Content-Type"), Some("text/plain"));
+        assert_eq!(res.get_body(), "hello".as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_respond_not_modified_since() {
+        let file = TempFile::new().unwrap();
+        std::fs::write(file.get_path(), "hello").unwrap();
+        let settings = create_settings(&file);
+        let request = create_request(&request_path(&file), None);
+        let path = StaticFileHandler::file_path(&request, &settings).unwrap();
+
+        let res = StaticFileHandler
+            .prepare_response(&request, &path, &settings)
+            .await;
+        let last_modified = res.get_header("Last-Modified").unwrap().to_owned();
+
+        let mut request = create_request(&request_path(&file), None);
+        request.insert_header("If-Modified-Since".to_string(), last_modified);
+        let res = StaticFileHandler
+            .prepare_response(&request, &path, &settings)
+            .await;
+        assert_eq!(res.get_status(), &ResponseStatus::NotModified);
+        assert_eq!(res.get_body(), b"");
+
+        let mut request = create_request(&request_path(&file), None);
+        let epoch = "Thu, 01 Jan 1970 00:00:00 GMT".to_string();
+        request.insert_header("If-Modified-Since".to_string(), epoch);
+        let res = StaticFileHandler
+            .prepare_response(&request, &path, &settings)
+            .await;
+        assert_eq!(res.get_status(), &ResponseStatus::Ok);
+        assert_eq!(res.get_body(), "hello".as_bytes());
+    }
+
+    #[test]
+    fn test_file_path_is_decoded() {
+        let file = TempFile::new().unwrap();
+        let settings = create_settings(&file);
+
+        let request = create_request("/static/my%20file.txt", None);
+        let expected = file.get_path().parent().unwrap().join("my file.txt");
+        assert_eq!(
+            StaticFileHandler::file_path(&request, &settings),
+            Some(expected)
+        );
+        let request = create_request("/static/%G1.txt", None);
+        assert_eq!(StaticFileHandler::file_path(&request, &settings), None);
+    }
+
+    #[test]
+    fn test_file_path_outside_of_prefix() {
+        let file = TempFile::new().unwrap();
+        let settings = create_settings(&file);
+
+        let request = create_request("/static/../secret.txt", None);
+        assert_eq!(StaticFileHandler::file_path(&request, &settings), None);
+        let request = create_request("/static/%2e%2e/secret.txt", None);
+        assert_eq!(StaticFileHandler::file_path(&request, &settings), None);
+        let request = create_request("/static/%2Fetc%2Fpasswd", None);
+        assert_eq!(StaticFileHandler::file_path(&request, &settings), None);
+        let request = create_request("/static/a%2f%2e%2e%2f%2e%2e/secret.txt", None);
+        assert_eq!(StaticFileHandler::file_path(&request, &settings), None);
+        let request = create_request("/static/%2f%2e%2e/secret.txt", None);
+        assert_eq!(StaticFileHandler::file_path(&request, &settings), None);
+        let request = create_request("/static/..%5Csecret.txt", None);
+        assert_eq!(StaticFileHandler::file_path(&request, &settings), None);
+        assert_eq!(
+            StaticFileHandler::file_path(&create_request("/other.txt", None), &settings),
+            None
+        );
+    }
+}
@@ -1,19 +1,100 @@
 use crate::http::common::{HTTPVersion, IMFDateTime};
-use crate::http::handler::Handler;
-use crate::http::request::{Request, RequestMethod, RequestParseError};
+use crate::http::deflate::{DeflateParams, Deflater, Inflater, PERMESSAGE_DEFLATE};
+use crate::http::error_page::error_response;
+use crate::http::handler::{respond, ClientStream, HandleOutcome, Handler};
+use crate::http::headers;
+use crate::http::metrics::{self, SizeHistogram};
+use crate::http::outgoing::{self, OutgoingSender};
+use crate::http::request::{
+    Request, RequestBody, RequestHeaders, RequestLine, RequestMethod, RequestParseError,
+};
 use crate::http::response::{Response, ResponseBody, ResponseHeaders, ResponseStatus, StatusLine};
+use crate::http::room::{RoomCommand, Rooms};
+use crate::http::shutdown::Shutdown;
 use crate::settings::Settings;
 use anyhow::{bail, Context, Result};
+use app::{EchoApp, WebSocketApp};
 use async_trait::async_trait;
+use codec::WsFrameCodec;
+use connection::WebSocketConnection;
+use futures::{Sink, SinkExt, Stream, StreamExt};
 use log::{debug, error};
+use once_cell::sync::OnceCell;
 use sha1::{Digest, Sha1};
+use std::fmt;
+use std::fmt::Formatter;
 use std::net::SocketAddr;
-use std::sync::Arc;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use tokio::sync::broadcast;
+use tokio::time::timeout;
+use tokio_util::codec::FramedWrite;
+use uuid::Uuid;
+
+pub mod app;
+pub mod codec;
+pub mod connection;
 
 const WS_VERSION: &str = "13";
 const WS_ACCEPT_STR: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+// RFC 6455 5.5
+const MAX_CONTROL_PAYLOAD_SIZE: usize = 125;
+
+// Status codes of Close frame (RFC 6455 7.4.1)
+const CLOSE_GOING_AWAY: u16 = 1001;
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+const CLOSE_INVALID_PAYLOAD: u16 = 1007;
+const CLOSE_MESSAGE_TOO_BIG: u16 = 1009;
+const CLOSE_INTERNAL_ERROR: u16 = 1011;
+
+/// Return true if the status code is allowed in Close frames (RFC 6455 7.4).
+/// 1004-1006 and 1015 are reserved, and 1016-2999 are not assigned yet.
+fn is_valid_close_code(code: u16) -> bool {
+    matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999)
+}
+
+/// Error of decoding frames from the peer, which decides the status code of Close
+/// sent before closing the connection (RFC 6455 7.4.1)
+#[derive(Debug, thiserror::Error)]
+pub enum FrameDecodeError {
+    /// Failed to read frames from the connection (1011)
+    #[error("Failed to read frame: {0}")]
+    Io(#[from] std::io::Error),
+    /// The peer violated the protocol (1002)
+    #[error("Protocol error: {0}")]
+    Protocol(String),
+    /// Message bigger than the server accepts (1009)
+    #[error("Message too big: {0}")]
+    TooBig(String),
+    /// Text message or reason of Close is not valid UTF-8 (1007)
+    #[error("Invalid UTF-8: {0}")]
+    InvalidUtf8(String),
+    /// Opcode not defined by RFC 6455 (1002)
+    #[error("Unknown opcode: 0x{0:02x}")]
+    InvalidOpcode(u8),
+}
+
+impl FrameDecodeError {
+    pub fn close_code(&self) -> u16 {
+        match self {
+            FrameDecodeError::Io(_) => CLOSE_INTERNAL_ERROR,
+            FrameDecodeError::Protocol(_) | FrameDecodeError::InvalidOpcode(_) => {
+                CLOSE_PROTOCOL_ERROR
+            }
+            FrameDecodeError::TooBig(_) => CLOSE_MESSAGE_TOO_BIG,
+            FrameDecodeError::InvalidUtf8(_) => CLOSE_INVALID_PAYLOAD,
+        }
+    }
+
+    /// Return reason of Close, which tells the peer why its message is not processed
+    pub fn close_reason(&self) -> &'static str {
+        match self {
+            FrameDecodeError::TooBig(_) => "Message too big",
+            _ => "",
+        }
+    }
+}
 
 /*
      WebSocket Frame (from RFC 6455 5.2):
@@ -38,7 +119,7 @@ const WS_ACCEPT_STR: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
     +---------------------------------------------------------------+
 */
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Frame {
     Text {
         message: String,
@@ -59,76 +140,151 @@ pub enum Frame {
 }
 
 impl Frame {
+    pub fn ping(data: impl Into<Vec<u8>>) -> Result<Frame> {
+        let data = Self::control_payload(data)?;
+        Ok(Self::Ping { data })
+    }
+
+    pub fn pong(data: impl Into<Vec<u8>>) -> Result<Frame> {
+        let data = Self::control_payload(data)?;
+        Ok(Self::Pong { data })
+    }
+
+    fn control_payload(data: impl Into<Vec<u8>>) -> Result<Vec<u8>> {
+        let data = data.into();
+        if data.len() > MAX_CONTROL_PAYLOAD_SIZE {
+            bail!("Too big payload for control frame: {} bytes", data.len());
+        }
+        Ok(data)
+    }
+
+    /// Decode a frame. Fragmented message is assembled into a Text or Binary frame.
+    /// This is for a single frame or message only: fragments read before a control frame
+    /// interleaved between them are dropped, so keep a `FrameDecoder` to read a connection.
     pub async fn decode<T: AsyncRead + Unpin>(
         reader: &mut T,
         max_payload_size: usize,
-    ) -> Result<Frame> {
-        // TODO: Handle fragmentation
+    ) -> Result<Frame, FrameDecodeError> {
+        FrameDecoder::new().decode(reader, max_payload_size).await
+    }
 
-        let metadata = reader
-            .read_u8()
+    /// Decode a frame like `decode`, reading payload into `buf` to reuse its capacity.
+    /// Fragments are dropped on an interleaved control frame as well as `decode`.
+    #[allow(dead_code)]
+    pub async fn decode_into<T: AsyncRead + Unpin>(
+        reader: &mut T,
+        max_payload_size: usize,
+        buf: &mut Vec<u8>,
+    ) -> Result<Frame, FrameDecodeError> {
+        FrameDecoder::new()
+            .decode_into(reader, max_payload_size, buf)
             .await
-            .context("Failed to read the first byte of frame")?;
-        let _fin = (metadata & 0x80) != 0;
-        let _rsv1 = (metadata & 0x40) != 0;
-        let _rsv1 = (metadata & 0x20) != 0;
-        let _rsv1 = (metadata & 0x10) != 0;
+    }
 
-        let first_len_byte = reader
-            .read_u8()
-            .await
-            .context("Failed to read first byte of length")?;
+    /// Parse the header of a frame at the head of `buf` as it is on the wire, or return
+    /// the number of bytes missing in the header. Bits are validated as soon as they're buffered.
+    /// Payload of non-control frames must fit in `max_payload_size - assembled_size`.
+    /// If `mask_required` is set, frames must be masked.
+    /// RSV1 is accepted instead of an error if `allow_rsv1` is set for permessage-deflate.
+    fn parse_header(
+        buf: &[u8],
+        max_payload_size: usize,
+        assembled_size: usize,
+        mask_required: bool,
+        allow_rsv1: bool,
+    ) -> Result<Result<FrameHeader, usize>, FrameDecodeError> {
+        if let Some(&metadata) = buf.first() {
+            let rsv1 = (metadata & 0x40) != 0;
+            let rsv2 = (metadata & 0x20) != 0;
+            let rsv3 = (metadata & 0x10) != 0;
+            // RSV bits are defined by extensions, and only RSV1 is used by permessage-deflate
+            if (rsv1 && !allow_rsv1) || rsv2 || rsv3 {
+                return Err(FrameDecodeError::Protocol(format!(
+                    "Reserved bits are set without extension: RSV1={}, RSV2={}, RSV3={}",
+                    rsv1, rsv2, rsv3
+                )));
+            }
+        }
+        let first_len_byte = match buf.get(1) {
+            Some(&b) => b,
+            None => return Ok(Err(2 - buf.len())),
+        };
         let is_masked = (first_len_byte & 0x80) != 0;
-        let len = match first_len_byte & 0x7f {
-            l if l <= 0x7d => l as usize,
-            l if l == 0x7e => reader
-                .read_u16()
-                .await
-                .context("Failed to read 16-bit length")? as usize,
-            l if l == 0x7f => reader
-                .read_u64()
-                .await
-                .context("Failed to read 64-bit length")? as usize,
-            _ => unreachable!(),
+        // RFC 6455 5.1
+        if mask_required && !is_masked {
+            return Err(FrameDecodeError::Protocol(
+                "Frames from client must be masked".to_string(),
+            ));
+        }
+        let len_size = match first_len_byte & 0x7f {
+            0x7e => 2,
+            0x7f => 8,
+            _ => 0,
         };
-        if len > max_payload_size {
-            bail!("Payload is too big");
+        // the length is checked before the mask key arrives
+        if buf.len() < 2 + len_size {
+            return Ok(Err(2 + len_size - buf.len()));
         }
 
-        let mask_key_opt: Option<[u8; 4]> = if is_masked {
-            Some(
-                reader
-                    .read_u32()
-                    .await
-                    .context("Failed to read mask key")?
-                    .to_be_bytes(),
-            )
+        let metadata = buf[0];
+        let opcode = metadata & 0x0f;
+        let len = match len_size {
+            2 => u16::from_be_bytes([buf[2], buf[3]]) as usize,
+            8 => u64::from_be_bytes(buf[2..10].try_into().unwrap()) as usize,
+            _ => (first_len_byte & 0x7f) as usize,
+        };
+        if Self::is_control_opcode(opcode) && len > MAX_CONTROL_PAYLOAD_SIZE {
+            return Err(FrameDecodeError::Protocol(format!(
+                "Too big payload for control frame: {} bytes",
+                len
+            )));
+        }
+        let limit = if Self::is_control_opcode(opcode) {
+            max_payload_size
         } else {
-            None
+            // the limit is applied to the whole message
+            max_payload_size.saturating_sub(assembled_size)
         };
+        if len > limit {
+            return Err(FrameDecodeError::TooBig(format!(
+                "Payload of {} bytes exceeds the limit of {} bytes",
+                len, limit
+            )));
+        }
 
-        let mut data = if let Some(mask_key) = mask_key_opt {
-            let mut buf = vec![0u8; len];
-            reader
-                .read_exact(&mut buf)
-                .await
-                .context("Failed to read paylod")?;
-            Self::unmask(buf, mask_key)
+        let header_len = 2 + len_size + if is_masked { 4 } else { 0 };
+        if buf.len() < header_len {
+            return Ok(Err(header_len - buf.len()));
+        }
+        let mask_key = if is_masked {
+            let pos = 2 + len_size;
+            Some(buf[pos..(pos + 4)].try_into().unwrap())
         } else {
-            let mut buf = vec![0u8; len];
-            reader
-                .read_exact(&mut buf)
-                .await
-                .context("Failed to read payload")?;
-            buf
+            None
         };
+        Ok(Ok(FrameHeader {
+            fin: (metadata & 0x80) != 0,
+            rsv1: (metadata & 0x40) != 0,
+            opcode,
+            mask_key,
+            header_len,
+            payload_len: len,
+        }))
+    }
 
-        match metadata & 0x0f {
+    fn is_control_opcode(opcode: u8) -> bool {
+        opcode & 0x08 != 0
+    }
+
+    /// Build a frame from opcode and the whole payload
+    fn from_payload(opcode: u8, mut data: Vec<u8>) -> Result<Frame, FrameDecodeError> {
+        match opcode {
             0x1 => {
                 // Text
                 Ok(Self::Text {
-                    message: String::from_utf8(data)
-                        .context("Received text frame but cannot interpret as UTF-8 string")?,
+                    message: String::from_utf8(data).map_err(|_| {
+                        FrameDecodeError::InvalidUtf8("Text message is not valid UTF-8".to_string())
+                    })?,
                 })
             }
             0x2 => {
@@ -144,13 +300,20 @@ impl Frame {
                         message: vec![],
                     })
                 } else {
-                    let status_code = u16::from_be_bytes(
-                        data.drain(..2)
-                            .as_slice()
-                            .try_into()
-                            .context("Failed to read status_code in Close frame")?,
-                    );
-                    let message = data;
+                    let status_code = u16::from_be_bytes([data[0], data[1]]);
+                    let message = data.split_off(2);
+                    if !is_valid_close_code(status_code) {
+                        return Err(FrameDecodeError::Protocol(format!(
+                            "Illegal status code of Close: {}",
+                            status_code
+                        )));
+                    }
+                    // reason must be UTF-8 (RFC 6455 5.5.1)
+                    if std::str::from_utf8(&message).is_err() {
+                        return Err(FrameDecodeError::InvalidUtf8(
+                            "Close reason is not valid UTF-8".to_string(),
+                        ));
+                    }
                     Ok(Self::Close {
                         status_code: Some(status_code),
                         message,
@@ -165,9 +328,7 @@ impl Frame {
                 // Pong
                 Ok(Self::Pong { data })
             }
-            opcode => {
-                bail!("Unknown opcode: 0x{:02x}", opcode);
-            }
+            opcode => Err(FrameDecodeError::InvalidOpcode(opcode)),
         }
     }
 
@@ -192,21 +353,102 @@ impl Frame {
     }
 
     pub fn encode(&self) -> Result<Vec<u8>> {
-        // TODO: Handle fragmentation
+        Self::encode_raw(true, self.opcode(), &self.get_data())
+    }
 
-        let mut res = vec![];
+    /// Encode the frame as a client sends it, masked with a random key (RFC 6455 5.3)
+    pub fn encode_client(&self) -> Result<Vec<u8>> {
+        let random = Uuid::new_v4();
+        let mask_key = random.as_bytes()[..4].try_into().unwrap();
+        self.encode_client_with_mask(mask_key)
+    }
+
+    /// Encode the frame as a client sends it, masked with `mask_key`
+    pub fn encode_client_with_mask(&self, mask_key: [u8; 4]) -> Result<Vec<u8>> {
+        let mut data = self.get_data();
+        let encoded = Self::encode_raw(true, self.opcode(), &data)?;
+        let header_len = encoded.len() - data.len();
+        // masking is the same XOR as unmasking
+        Self::unmask(&mut data, mask_key);
+
+        let mut res = encoded[..header_len].to_vec();
+        res[1] |= 0x80;
+        res.extend(mask_key);
+        res.extend(data);
+        Ok(res)
+    }
+
+    /// Encode Text or Binary frame into frames whose payload is at most `max_frame_size` bytes.
+    /// The first frame has the opcode of the frame and the rest are continuation frames.
+    pub fn encode_fragmented(&self, max_frame_size: usize) -> Result<Vec<Vec<u8>>> {
+        if !matches!(self, Self::Text { .. } | Self::Binary { .. }) {
+            bail!("Control frame must not be fragmented: {}", self.name());
+        }
+        if max_frame_size == 0 {
+            bail!("Frame size must be positive");
+        }
 
-        let opcode = match self {
+        Self::encode_message(self.opcode(), &self.get_data(), false, max_frame_size)
+    }
+
+    /// Encode Text or Binary frame with payload compressed by permessage-deflate,
+    /// which is fragmented by `max_frame_size` if set
+    pub fn encode_deflated(
+        &self,
+        deflater: &mut Deflater,
+        max_frame_size: Option<usize>,
+    ) -> Result<Vec<Vec<u8>>> {
+        if !matches!(self, Self::Text { .. } | Self::Binary { .. }) {
+            bail!("Control frame must not be compressed: {}", self.name());
+        }
+        let data = deflater.deflate(&self.get_data())?;
+        let max_frame_size = max_frame_size.unwrap_or(data.len()).max(1);
+        Self::encode_message(self.opcode(), &data, true, max_frame_size)
+    }
+
+    /// Encode payload of a message into frames. RSV1 is set on the first frame if `compressed`.
+    fn encode_message(
+        opcode: u8,
+        data: &[u8],
+        compressed: bool,
+        max_frame_size: usize,
+    ) -> Result<Vec<Vec<u8>>> {
+        let count = data.len().div_ceil(max_frame_size).max(1);
+        let chunks: Vec<&[u8]> = if data.is_empty() {
+            vec![data]
+        } else {
+            data.chunks(max_frame_size).collect()
+        };
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(i, chunk)| {
+                let opcode = if i == 0 { opcode } else { 0x0 };
+                let mut encoded = Self::encode_raw(i == count - 1, opcode, chunk)?;
+                if i == 0 && compressed {
+                    // RSV1 (RFC 7692 6)
+                    encoded[0] |= 0x40;
+                }
+                Ok(encoded)
+            })
+            .collect()
+    }
+
+    fn opcode(&self) -> u8 {
+        match self {
             Self::Text { .. } => 0x1u8,
             Self::Binary { .. } => 0x2u8,
             Self::Close { .. } => 0x8u8,
             Self::Ping { .. } => 0x9u8,
             Self::Pong { .. } => 0xau8,
-        };
-        // always FIN for now
-        res.push(0x80 | opcode);
+        }
+    }
+
+    fn encode_raw(fin: bool, opcode: u8, data: &[u8]) -> Result<Vec<u8>> {
+        let mut res = vec![];
 
-        let data = self.get_data();
+        let fin_bit = if fin { 0x80 } else { 0x00 };
+        res.push(fin_bit | opcode);
 
         match data.len() {
             l if l < (1 << 7) => res.push(l as u8),
@@ -228,19 +470,405 @@ impl Frame {
         Ok(res)
     }
 
-    fn unmask(data: Vec<u8>, mask_key: [u8; 4]) -> Vec<u8> {
-        // RFC 6455 5.3
-        data.into_iter()
-            .enumerate()
-            .map(|(i, b)| b ^ mask_key[i % 4])
-            .collect()
+    /// Encode the frame as it's sent, where messages are compressed by `deflater`
+    /// and fragmented by `max_frame_size` if they're set
+    pub fn encode_with(
+        &self,
+        deflater: Option<&mut Deflater>,
+        max_frame_size: Option<usize>,
+    ) -> Result<Vec<Vec<u8>>> {
+        let is_message = matches!(self, Self::Text { .. } | Self::Binary { .. });
+        match (max_frame_size, deflater) {
+            (max_frame_size, Some(deflater)) if is_message => {
+                self.encode_deflated(deflater, max_frame_size)
+            }
+            (Some(size), _) if is_message => self.encode_fragmented(size),
+            _ => Ok(vec![self.encode()?]),
+        }
+    }
+
+    /// Return Close frame sent to the peer when the connection fails with the error.
+    /// Errors not caused by the peer are reported with status code 1011.
+    pub fn close_for_error(err: &anyhow::Error) -> Frame {
+        match err.downcast_ref::<FrameDecodeError>() {
+            Some(err) => Self::Close {
+                status_code: Some(err.close_code()),
+                message: err.close_reason().as_bytes().to_vec(),
+            },
+            None => Self::Close {
+                status_code: Some(CLOSE_INTERNAL_ERROR),
+                message: vec![],
+            },
+        }
+    }
+
+    /// Return reason of Close frame, which is validated as UTF-8 on decode
+    #[allow(dead_code)]
+    pub fn close_reason(&self) -> Option<&str> {
+        match self {
+            Self::Close { message, .. } => std::str::from_utf8(message).ok(),
+            _ => None,
+        }
+    }
+
+    /// Return CRC32 of payload
+    pub fn checksum(&self) -> u32 {
+        crc32fast::hash(&self.get_data())
+    }
+
+    /// Return summary of the frame for logging, which shows at most `preview_size` bytes of payload
+    pub fn summary(&self, preview_size: usize) -> FrameSummary<'_> {
+        FrameSummary {
+            frame: self,
+            preview_size,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Text { .. } => "Text",
+            Self::Binary { .. } => "Binary",
+            Self::Close { .. } => "Close",
+            Self::Ping { .. } => "Ping",
+            Self::Pong { .. } => "Pong",
+        }
+    }
+
+    /// Unmask payload in place (RFC 6455 5.3), XORing 8 bytes at a time with the repeated key
+    fn unmask(data: &mut [u8], mask_key: [u8; 4]) {
+        let mut repeated = [0u8; 8];
+        repeated[..4].copy_from_slice(&mask_key);
+        repeated[4..].copy_from_slice(&mask_key);
+        let key = u64::from_ne_bytes(repeated);
+
+        let mut chunks = data.chunks_exact_mut(8);
+        for chunk in &mut chunks {
+            let word = u64::from_ne_bytes(chunk.try_into().unwrap()) ^ key;
+            chunk.copy_from_slice(&word.to_ne_bytes());
+        }
+        // the remainder starts at a multiple of 8, where the key starts again
+        for (i, b) in chunks.into_remainder().iter_mut().enumerate() {
+            *b ^= mask_key[i % 4];
+        }
+    }
+}
+
+/// Header of a frame as it is on the wire (RFC 6455 5.2)
+#[derive(Debug)]
+struct FrameHeader {
+    fin: bool,
+    rsv1: bool,
+    opcode: u8,
+    mask_key: Option<[u8; 4]>,
+    // bytes of the header itself, which is followed by payload
+    header_len: usize,
+    payload_len: usize,
+}
+
+/// Result of decoding bytes buffered so far
+#[derive(Debug)]
+enum Decoded {
+    /// At least this number of bytes are needed to decode the next frame
+    Incomplete(usize),
+    /// A frame of this number of bytes is consumed, which completes a frame
+    /// unless it's a fragment of a message
+    Consumed(usize, Option<Frame>),
+}
+
+/// Decoder of frames from a connection, which keeps fragments of a message (RFC 6455 5.4)
+/// so that control frames interleaved between them are returned in the middle of the message.
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    // opcode of the first fragment, whether it's compressed, and payload assembled so far
+    fragments: Option<(u8, bool, Vec<u8>)>,
+    mask_required: bool,
+    // set if permessage-deflate is negotiated
+    inflater: Option<Inflater>,
+}
+
+impl FrameDecoder {
+    pub fn new() -> FrameDecoder {
+        FrameDecoder::default()
+    }
+
+    /// Reject unmasked frames if `mask_required` is set
+    pub fn with_mask_required(mut self, mask_required: bool) -> FrameDecoder {
+        self.mask_required = mask_required;
+        self
+    }
+
+    /// Inflate messages setting RSV1 by `inflater`
+    pub fn with_inflater(mut self, inflater: Option<Inflater>) -> FrameDecoder {
+        self.inflater = inflater;
+        self
+    }
+
+    /// Decode the next control frame or the next whole message
+    pub async fn decode<T: AsyncRead + Unpin>(
+        &mut self,
+        reader: &mut T,
+        max_payload_size: usize,
+    ) -> Result<Frame, FrameDecodeError> {
+        self.decode_into(reader, max_payload_size, &mut vec![])
+            .await
+    }
+
+    /// Decode like `decode`, reading bytes of each frame into `buf`.
+    /// Passing the same `buf` for frames of a connection reuses its capacity,
+    /// so only the payload of the returned frame is allocated.
+    /// Bytes are read just to the end of the frame, so `reader` can be passed to others after it.
+    pub async fn decode_into<T: AsyncRead + Unpin>(
+        &mut self,
+        reader: &mut T,
+        max_payload_size: usize,
+        buf: &mut Vec<u8>,
+    ) -> Result<Frame, FrameDecodeError> {
+        buf.clear();
+        loop {
+            match self.decode_buf(buf, max_payload_size)? {
+                Decoded::Incomplete(missing) => {
+                    let start = buf.len();
+                    buf.resize(start + missing, 0);
+                    reader.read_exact(&mut buf[start..]).await?;
+                }
+                Decoded::Consumed(_, Some(frame)) => return Ok(frame),
+                Decoded::Consumed(_, None) => buf.clear(),
+            }
+        }
+    }
+
+    /// Decode a frame at the head of `buf`, whose payload is unmasked in place
+    fn decode_buf(
+        &mut self,
+        buf: &mut [u8],
+        max_payload_size: usize,
+    ) -> Result<Decoded, FrameDecodeError> {
+        let assembled_size = self.fragments.as_ref().map_or(0, |(_, _, data)| data.len());
+        let header = match Frame::parse_header(
+            buf,
+            max_payload_size,
+            assembled_size,
+            self.mask_required,
+            self.inflater.is_some(),
+        )? {
+            Ok(header) => header,
+            Err(missing) => return Ok(Decoded::Incomplete(missing)),
+        };
+        let frame_len = header.header_len + header.payload_len;
+        if buf.len() < frame_len {
+            return Ok(Decoded::Incomplete(frame_len - buf.len()));
+        }
+
+        let payload = &mut buf[header.header_len..frame_len];
+        if let Some(mask_key) = header.mask_key {
+            Frame::unmask(payload, mask_key);
+        }
+        let frame = self.assemble(&header, payload, max_payload_size)?;
+        Ok(Decoded::Consumed(frame_len, frame))
+    }
+
+    /// Return the frame completed by `payload` of the frame of `header`,
+    /// or None if it's a fragment to be assembled into a message
+    fn assemble(
+        &mut self,
+        header: &FrameHeader,
+        payload: &[u8],
+        max_payload_size: usize,
+    ) -> Result<Option<Frame>, FrameDecodeError> {
+        let FrameHeader {
+            fin, rsv1, opcode, ..
+        } = *header;
+        if Frame::is_control_opcode(opcode) {
+            if !fin {
+                return Err(FrameDecodeError::Protocol(
+                    "Control frame must not be fragmented".to_string(),
+                ));
+            }
+            if rsv1 {
+                return Err(FrameDecodeError::Protocol(
+                    "Control frame must not be compressed".to_string(),
+                ));
+            }
+            return Frame::from_payload(opcode, payload.to_vec()).map(Some);
+        }
+
+        // data is None if the payload is only in `payload`
+        let (opcode, compressed, data) = match (opcode, self.fragments.take()) {
+            (0x0, Some((first_opcode, compressed, mut assembled))) => {
+                if rsv1 {
+                    return Err(FrameDecodeError::Protocol(
+                        "RSV1 is set on continuation frame".to_string(),
+                    ));
+                }
+                assembled.extend_from_slice(payload);
+                (first_opcode, compressed, Some(assembled))
+            }
+            (0x0, None) => {
+                return Err(FrameDecodeError::Protocol(
+                    "Continuation frame without first fragment".to_string(),
+                ));
+            }
+            (_, Some(_)) => {
+                return Err(FrameDecodeError::Protocol(
+                    "Expected continuation frame".to_string(),
+                ));
+            }
+            (opcode, None) => (opcode, rsv1, None),
+        };
+        if fin {
+            let data = match self.inflater.as_mut() {
+                Some(inflater) if compressed => {
+                    inflater.inflate(data.as_deref().unwrap_or(payload), max_payload_size)?
+                }
+                _ => data.unwrap_or_else(|| payload.to_vec()),
+            };
+            return Frame::from_payload(opcode, data).map(Some);
+        }
+        if !matches!(opcode, 0x1 | 0x2) {
+            return Err(FrameDecodeError::InvalidOpcode(opcode));
+        }
+        self.fragments = Some((opcode, compressed, data.unwrap_or_else(|| payload.to_vec())));
+        Ok(None)
+    }
+}
+
+pub struct FrameSummary<'a> {
+    frame: &'a Frame,
+    preview_size: usize,
+}
+
+impl fmt::Display for FrameSummary<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let data = self.frame.get_data();
+        let preview = data
+            .iter()
+            .take(self.preview_size)
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let ellipsis = if data.len() > self.preview_size {
+            " ..."
+        } else {
+            ""
+        };
+        f.write_fmt(format_args!(
+            "{} (len: {}) [{}{}]",
+            self.frame.name(),
+            data.len(),
+            preview,
+            ellipsis
+        ))
     }
 }
 
-pub struct WebSocketHandler;
+/// Function applied to every frame before it's written to the socket
+pub type FrameHook = Arc<dyn Fn(Frame) -> Frame + Send + Sync>;
+
+/// Function returning extra headers of the 101 response for the handshake request
+pub type HandshakeHeadersHook = Arc<dyn Fn(&Request) -> Vec<(String, String)> + Send + Sync>;
+
+// headers of the 101 response which cannot be overwritten by applications
+const HANDSHAKE_HEADERS: [&str; 6] = [
+    "Date",
+    "Upgrade",
+    "Connection",
+    "Sec-WebSocket-Accept",
+    "Sec-WebSocket-Protocol",
+    "Sec-WebSocket-Extensions",
+];
+
+pub struct WebSocketHandler {
+    app: Arc<dyn WebSocketApp>,
+    outgoing_frame_hook: Option<FrameHook>,
+    handshake_headers_hook: Option<HandshakeHeadersHook>,
+    // joined by sessions if `ws.enable_rooms` is set
+    rooms: Rooms,
+    // text messages with id of the sender session, subscribed if `ws.broadcast_all` is set
+    everyone: OnceCell<broadcast::Sender<(Uuid, String)>>,
+    // sessions are closed with status code 1001 when it's triggered
+    shutdown: Option<Shutdown>,
+}
+
+impl Default for WebSocketHandler {
+    fn default() -> Self {
+        Self {
+            app: Arc::new(EchoApp),
+            outgoing_frame_hook: None,
+            handshake_headers_hook: None,
+            rooms: Rooms::default(),
+            everyone: OnceCell::new(),
+            shutdown: None,
+        }
+    }
+}
 
 impl WebSocketHandler {
-    fn handshake(&self, request: &Request) -> Result<Response, RequestParseError> {
+    /// Create handler echoing messages back
+    pub fn new() -> WebSocketHandler {
+        WebSocketHandler::default()
+    }
+
+    /// Create handler passing messages to `app`
+    pub fn with_app(app: Arc<dyn WebSocketApp>) -> WebSocketHandler {
+        WebSocketHandler {
+            app,
+            ..WebSocketHandler::default()
+        }
+    }
+
+    /// Transform outgoing frames by `hook`, e.g. for logging or rewriting them
+    #[allow(dead_code)]
+    pub fn with_outgoing_frame_hook(
+        mut self,
+        hook: impl Fn(Frame) -> Frame + Send + Sync + 'static,
+    ) -> WebSocketHandler {
+        self.outgoing_frame_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Add headers returned by `hook` to the 101 response, e.g. `Set-Cookie`.
+    /// Headers required for the upgrade are not overwritten.
+    #[allow(dead_code)]
+    pub fn with_handshake_headers(
+        mut self,
+        hook: impl Fn(&Request) -> Vec<(String, String)> + Send + Sync + 'static,
+    ) -> WebSocketHandler {
+        self.handshake_headers_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Close sessions with status code 1001 when `shutdown` is triggered
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> WebSocketHandler {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Return channel of messages to all sessions, which is created with capacity
+    /// of `ws.max_pending_frames` in the settings of the first session
+    fn everyone(&self, settings: &Settings) -> &broadcast::Sender<(Uuid, String)> {
+        self.everyone.get_or_init(|| {
+            let (sender, _) = broadcast::channel((*settings.ws().max_pending_frames()).max(1));
+            sender
+        })
+    }
+
+    /// Return parameters of permessage-deflate if it's enabled and offered by the client
+    fn negotiate_deflate(request: &Request, settings: &Settings) -> Option<DeflateParams> {
+        if !*settings.ws().enable_permessage_deflate() {
+            return None;
+        }
+        let offers = request
+            .get_headers()
+            .get(&headers::SEC_WEBSOCKET_EXTENSIONS)
+            .unwrap_or_default();
+        DeflateParams::negotiate(&offers)
+    }
+
+    fn handshake(
+        &self,
+        request: &Request,
+        settings: &Settings,
+    ) -> Result<Response, RequestParseError> {
         fn check_header(request: &Request, key: &str, expected: &str) -> bool {
             matches!(request.get_header_lc(key).as_deref(), Some(s) if s == expected)
         }
@@ -261,10 +889,35 @@ impl WebSocketHandler {
         if request.get_method() != &RequestMethod::GET {
             return Err(client_error("Illegal request method"));
         }
+        // the handshake requires HTTP/1.1 or higher (RFC 6455 4.1)
+        if request.get_version() == &HTTPVersion::V1_0 {
+            return Err(client_error("Illegal HTTP version"));
+        }
+
+        // only permessage-deflate is supported
+        let deflate_enabled = *settings.ws().enable_permessage_deflate();
+        let unsupported_extensions = request
+            .get_headers()
+            .get(&headers::SEC_WEBSOCKET_EXTENSIONS)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|ext| !(deflate_enabled && ext.name == PERMESSAGE_DEFLATE))
+            .collect::<Vec<_>>();
+        if !unsupported_extensions.is_empty() {
+            debug!("Ignore offered extensions: {:?}", unsupported_extensions);
+            if *settings.ws().reject_unsupported_extensions() {
+                return Err(client_error("Unsupported WebSocket extension"));
+            }
+        }
 
         let sec_ws_key = request
             .get_header("Sec-WebSocket-Key")
             .ok_or_else(|| client_error("Missing Sec-WebSocket-Key header"))?;
+        // the key is base64 of 16 random bytes (RFC 6455 4.1), which is 24 characters with padding
+        match base64::decode(sec_ws_key) {
+            Ok(key) if key.len() == 16 && sec_ws_key.len() == 24 => {}
+            _ => return Err(client_error("Illegal Sec-WebSocket-Key header")),
+        }
 
         let bs: Vec<u8> = sec_ws_key.bytes().chain(WS_ACCEPT_STR.bytes()).collect();
         let mut hasher = Sha1::new();
@@ -273,20 +926,70 @@ impl WebSocketHandler {
 
         let sec_ws_accept = base64::encode(hashed);
 
+        let mut headers = ResponseHeaders::from([
+            ("Date", IMFDateTime::now().to_string()),
+            ("Upgrade", "websocket".to_string()),
+            ("Connection", "Upgrade".to_string()),
+            ("Sec-WebSocket-Accept", sec_ws_accept),
+        ]);
+
+        if let Some(deflate) = Self::negotiate_deflate(request, settings) {
+            headers.insert(
+                "Sec-WebSocket-Extensions".to_string(),
+                deflate.to_header_value(),
+            );
+        }
+
+        // the handshake succeeds without subprotocol if no offered one is supported
+        let offered_protocols = request
+            .get_headers()
+            .get(&headers::SEC_WEBSOCKET_PROTOCOL)
+            .unwrap_or_default();
+        if let Some(protocol) = offered_protocols
+            .into_iter()
+            .find(|protocol| settings.ws().subprotocols().contains(protocol))
+        {
+            headers.insert("Sec-WebSocket-Protocol".to_string(), protocol);
+        }
+        if let Some(hook) = self.handshake_headers_hook.as_ref() {
+            for (key, value) in hook(request) {
+                if HANDSHAKE_HEADERS
+                    .iter()
+                    .any(|required| required.eq_ignore_ascii_case(&key))
+                {
+                    debug!("Ignore handshake header conflicting with upgrade: {}", key);
+                    continue;
+                }
+                headers.insert(key, value);
+            }
+        }
+
         let response = Response::new(
             StatusLine::new(HTTPVersion::V1_1, ResponseStatus::SwitchingProtocol),
-            ResponseHeaders::from([
-                ("Date", IMFDateTime::now().to_string()),
-                ("Upgrade", "websocket".to_string()),
-                ("Connection", "Upgrade".to_string()),
-                ("Sec-WebSocket-Accept", sec_ws_accept),
-            ]),
+            headers,
             ResponseBody::new(vec![]),
         );
         Ok(response)
     }
 }
 
+/// Build an upgrade request as a client sends it, with a random Sec-WebSocket-Key
+/// which is base64 of 16 bytes (RFC 6455 4.1)
+pub fn build_client_handshake(path: &str, host: &str) -> Request {
+    let key = base64::encode(Uuid::new_v4().as_bytes());
+    Request::new(
+        RequestLine::new(RequestMethod::GET, path, HTTPVersion::V1_1),
+        RequestHeaders::from([
+            ("Host", host),
+            ("Upgrade", "websocket"),
+            ("Connection", "Upgrade"),
+            ("Sec-WebSocket-Version", WS_VERSION),
+            ("Sec-WebSocket-Key", &key),
+        ]),
+        RequestBody::new(vec![]),
+    )
+}
+
 #[async_trait]
 impl Handler for WebSocketHandler {
     fn accepts(
@@ -295,26 +998,38 @@ impl Handler for WebSocketHandler {
         _client_addr: SocketAddr,
         _settings: Arc<Settings>,
     ) -> bool {
-        matches!(
-            request.get_header_lc("Upgrade").as_deref(),
-            Some("websocket")
-        )
+        request.is_websocket_upgrade()
+    }
+
+    fn allowed_methods(&self) -> Vec<RequestMethod> {
+        vec![RequestMethod::GET]
     }
 
     async fn handle(
         &self,
         request: Request,
-        mut stream: TcpStream,
+        mut stream: ClientStream,
         client_addr: SocketAddr,
         settings: Arc<Settings>,
-    ) -> Result<()> {
-        match self.handshake(&request) {
-            Ok(res) => {
-                stream
-                    .write(&res.encode())
-                    .await
-                    .context("Failed to write response")?;
-            }
+    ) -> Result<HandleOutcome> {
+        self.upgrade(request, &mut stream, client_addr, settings)
+            .await
+    }
+}
+
+impl WebSocketHandler {
+    /// Respond to the handshake and run session if it succeeded.
+    /// The outcome is of the handshake, so frames of the session are not counted in it.
+    async fn upgrade<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        request: Request,
+        stream: &mut S,
+        client_addr: SocketAddr,
+        settings: Arc<Settings>,
+    ) -> Result<HandleOutcome> {
+        let outcome = match self.handshake(&request, &settings) {
+            // the client doesn't send frames until it receives 101
+            Ok(res) => respond(&res, stream).await?,
             Err(err) => {
                 if err.get_status().is_server_error() {
                     error!(
@@ -322,86 +1037,432 @@ impl Handler for WebSocketHandler {
                         client_addr, err
                     );
                 }
-                let res = Response::new(
-                    StatusLine::new(HTTPVersion::V1_1, err.get_status().clone()),
-                    ResponseHeaders::from([
-                        ("Date", IMFDateTime::now().to_string().as_str()),
-                        ("Connection", "close"),
-                        ("Content-Length", "0"),
-                    ]),
-                    ResponseBody::new(vec![]),
+                let mut res = error_response(
+                    err.get_status(),
+                    request.get_header("Accept"),
+                    settings.http().error_page_template(),
                 );
-                stream
-                    .write(&res.encode())
-                    .await
-                    .context("Failed to write response")?;
-                return Ok(());
+                res.add_headers(request.get_response_headers());
+                return respond(&res, stream).await;
             }
         };
 
-        async fn handle_frame(stream: &mut TcpStream, settings: Arc<Settings>) -> Result<()> {
-            // continue when handshake succeeded
-            let max_payload_size: usize =
-                settings.as_ref().ws().max_payload_size().to_bytes() as usize;
-            loop {
-                let request_frame = Frame::decode(stream, max_payload_size)
-                    .await
-                    .context("Failed to decode frame")?;
-                debug!("Decode websocket frame: {:?}", request_frame);
+        let deflate = Self::negotiate_deflate(&request, &settings);
+        run_session(stream, settings, self, deflate).await?;
+        Ok(outcome)
+    }
+}
 
-                match request_frame {
-                    frame @ Frame::Text { .. } => {
-                        // echo back
-                        stream.write_all(&frame.encode()?).await?;
-                    }
-                    frame @ Frame::Binary { .. } => {
-                        // echo back
-                        stream.write_all(&frame.encode()?).await?;
-                    }
-                    Frame::Ping { data } => {
-                        let response_frame = Frame::Pong { data };
-                        stream.write_all(&response_frame.encode()?).await?;
-                    }
-                    Frame::Pong { .. } => {}
-                    frame @ Frame::Close { .. } => {
-                        // send back Close to show we accept it
-                        stream.write_all(&frame.encode()?).await?;
-                        break;
-                    }
-                }
-            }
-            Ok(())
-        }
+/// Handle frames after handshake succeeded until the connection is closed.
+/// Messages are compressed if permessage-deflate is negotiated as `deflate`.
+async fn run_session<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    settings: Arc<Settings>,
+    handler: &WebSocketHandler,
+    deflate: Option<DeflateParams>,
+) -> Result<()> {
+    let _active = metrics::ActiveWebSocket::start();
+    let hook = handler.outgoing_frame_hook.as_ref();
+    let mut message_sizes = SizeHistogram::new();
+    let res = handle_frames(stream, &settings, handler, deflate, &mut message_sizes).await;
+    log_message_sizes(&message_sizes);
 
-        let res = handle_frame(&mut stream, settings).await;
-        if let Err(err) = res {
-            error!("Failed to handle frame: {:?}", err);
-            // send Close because of error
-            let frame = Frame::Close {
-                status_code: None,
-                message: vec![],
-            };
-            stream.write_all(&frame.encode()?).await?;
+    if let Err(err) = res {
+        error!("Failed to handle frame: {:?}", err);
+        // send Close because of error, which fails if the connection is already broken
+        let frame = Frame::close_for_error(&err);
+        let mut sink = FramedWrite::new(&mut *stream, session_codec(&settings, None));
+        if let Err(err) = write_frame(&mut sink, frame, &settings, hook).await {
+            debug!("Failed to send Close for error: {:?}", err);
         }
-
-        Ok(())
     }
+
+    Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::http::request::{RequestBody, RequestHeaders, RequestLine};
+async fn handle_frames<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    settings: &Settings,
+    handler: &WebSocketHandler,
+    deflate: Option<DeflateParams>,
+    message_sizes: &mut SizeHistogram,
+) -> Result<()> {
+    // replies to Ping and Close are queued after frames from the app to keep the order
+    let connection = WebSocketConnection::new(stream, session_codec(settings, deflate))
+        .with_auto_pong(false)
+        .with_auto_close(false);
+    // frames are written by the other half so that messages from rooms can be sent any time
+    let (mut sink, mut frames) = connection.split();
+    let (sender, mut receiver) = outgoing::channel(
+        *settings.ws().max_pending_frames(),
+        *settings.ws().outgoing_overflow_policy(),
+    );
 
-    fn create_ws_request() -> Request {
-        Request::new(
-            RequestLine::new(RequestMethod::GET, "/", HTTPVersion::V1_1),
-            RequestHeaders::from([
-                ("Host", "localhost:80"),
-                ("Upgrade", "websocket"),
-                ("Connection", "Upgrade"),
-                ("Sec-WebSocket-Version", "13"),
-                // the value comes from RFC 6455 1.3
+    let reading = async {
+        let id = Uuid::new_v4();
+        let mut joined = vec![];
+        let last_pong = Mutex::new(Instant::now());
+        let mut broadcasts = if *settings.ws().broadcast_all() {
+            Some(handler.everyone(settings).subscribe())
+        } else {
+            None
+        };
+        let relaying = async {
+            match broadcasts.as_mut() {
+                Some(broadcasts) => relay_broadcasts(broadcasts, id, &sender).await,
+                None => futures::future::pending().await,
+            }
+        };
+        let keeping_alive = async {
+            match *settings.ws().ping_interval() {
+                Some(interval) => {
+                    keep_alive(&sender, &last_pong, interval, *settings.ws().pong_timeout()).await
+                }
+                None => futures::future::pending().await,
+            }
+        };
+        let expiring = async {
+            match *settings.ws().max_session_duration() {
+                Some(duration) => close_after(duration, &sender).await,
+                None => futures::future::pending().await,
+            }
+        };
+        let mut shutdown = handler.shutdown.clone();
+        let shutting_down = async {
+            match shutdown.as_mut() {
+                Some(shutdown) => close_on_shutdown(shutdown, &sender).await,
+                None => futures::future::pending().await,
+            }
+        };
+        let res = tokio::select! {
+            res = read_frames(
+                &mut frames,
+                settings,
+                handler,
+                id,
+                &sender,
+                &mut joined,
+                message_sizes,
+                &last_pong,
+            ) => res,
+            res = keeping_alive => res,
+            res = relaying => res,
+            res = shutting_down => res,
+            res = expiring => res,
+        };
+        for name in joined.iter() {
+            handler.rooms.leave(name, &id);
+        }
+        // let the writer finish after sending the remaining frames
+        drop(sender);
+        Ok::<_, anyhow::Error>(res)
+    };
+    let writing = async {
+        while let Some(frame) = receiver.recv().await {
+            let is_close = matches!(frame, Frame::Close { .. });
+            write_frame(
+                &mut sink,
+                frame,
+                settings,
+                handler.outgoing_frame_hook.as_ref(),
+            )
+            .await?;
+            if is_close {
+                break;
+            }
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+
+    let (res, ()) = futures::future::try_join(reading, writing).await?;
+    res
+}
+
+/// Send Ping every `interval` and send Close with status code 1001
+/// if Pong is not received in `pong_timeout` after Ping
+async fn keep_alive(
+    sender: &OutgoingSender,
+    last_pong: &Mutex<Instant>,
+    interval: Duration,
+    pong_timeout: Duration,
+) -> Result<()> {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // the first tick completes immediately
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let sent_at = Instant::now();
+        sender
+            .send(Frame::ping(vec![])?)
+            .await
+            .map_err(|_| anyhow::anyhow!("Writer of the session has finished"))?;
+        tokio::time::sleep(pong_timeout).await;
+        if *last_pong.lock().unwrap() < sent_at {
+            debug!("Close session not responding Pong in {:?}", pong_timeout);
+            let frame = Frame::Close {
+                status_code: Some(CLOSE_GOING_AWAY),
+                message: vec![],
+            };
+            sender
+                .send(frame)
+                .await
+                .map_err(|_| anyhow::anyhow!("Writer of the session has finished"))?;
+            return Ok(());
+        }
+    }
+}
+
+/// Send Close with status code 1001 when the session lasts for `duration`
+async fn close_after(duration: Duration, sender: &OutgoingSender) -> Result<()> {
+    tokio::time::sleep(duration).await;
+    debug!("Close session exceeding {:?}", duration);
+    let frame = Frame::Close {
+        status_code: Some(CLOSE_GOING_AWAY),
+        message: vec![],
+    };
+    sender
+        .send(frame)
+        .await
+        .map_err(|_| anyhow::anyhow!("Writer of the session has finished"))?;
+    Ok(())
+}
+
+/// Send Close with status code 1001 when the server is shutting down
+async fn close_on_shutdown(shutdown: &mut Shutdown, sender: &OutgoingSender) -> Result<()> {
+    shutdown.wait().await;
+    debug!("Close session since the server is shutting down");
+    let frame = Frame::Close {
+        status_code: Some(CLOSE_GOING_AWAY),
+        message: vec![],
+    };
+    sender
+        .send(frame)
+        .await
+        .map_err(|_| anyhow::anyhow!("Writer of the session has finished"))?;
+    Ok(())
+}
+
+/// Pass messages from other sessions to `sender` as Text frames.
+/// Send Close with status code 1011 if the session cannot keep up with them.
+async fn relay_broadcasts(
+    broadcasts: &mut broadcast::Receiver<(Uuid, String)>,
+    id: Uuid,
+    sender: &OutgoingSender,
+) -> Result<()> {
+    loop {
+        let frame = match broadcasts.recv().await {
+            Ok((from, _)) if from == id => continue,
+            Ok((_, message)) => Frame::Text { message },
+            Err(broadcast::error::RecvError::Lagged(count)) => {
+                debug!("Close session missing {} broadcast messages", count);
+                Frame::Close {
+                    status_code: Some(CLOSE_INTERNAL_ERROR),
+                    message: vec![],
+                }
+            }
+            // never happens since the handler keeps the sender
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        };
+        let is_close = matches!(frame, Frame::Close { .. });
+        sender
+            .send(frame)
+            .await
+            .map_err(|_| anyhow::anyhow!("Writer of the session has finished"))?;
+        if is_close {
+            return Ok(());
+        }
+    }
+}
+
+/// Read frames and pass frames to be sent to `sender` until Close is received.
+/// Messages are handled by the app of `handler` unless they're for rooms.
+#[allow(clippy::too_many_arguments)]
+async fn read_frames<F: Stream<Item = Result<Frame, FrameDecodeError>> + Unpin>(
+    frames: &mut F,
+    settings: &Settings,
+    handler: &WebSocketHandler,
+    id: Uuid,
+    sender: &OutgoingSender,
+    joined: &mut Vec<String>,
+    message_sizes: &mut SizeHistogram,
+    last_pong: &Mutex<Instant>,
+) -> Result<()> {
+    async fn send(sender: &OutgoingSender, frame: Frame) -> Result<()> {
+        sender
+            .send(frame)
+            .await
+            .map_err(|_| anyhow::anyhow!("Writer of the session has finished"))
+    }
+
+    async fn send_all(sender: &OutgoingSender, frames: Vec<Frame>) -> Result<()> {
+        for frame in frames {
+            send(sender, frame).await?;
+        }
+        Ok(())
+    }
+
+    // sent to all other sessions in broadcast mode, or handled by the app
+    async fn on_text(
+        handler: &WebSocketHandler,
+        settings: &Settings,
+        id: Uuid,
+        sender: &OutgoingSender,
+        message: String,
+    ) -> Result<()> {
+        if *settings.ws().broadcast_all() {
+            // fails only if no session subscribes
+            let _ = handler.everyone(settings).send((id, message));
+            return Ok(());
+        }
+        send_all(sender, handler.app.on_text(id, message).await).await
+    }
+
+    let rooms = &handler.rooms;
+    let app = handler.app.as_ref();
+    send_all(sender, app.on_open(id).await).await?;
+
+    loop {
+        let decoding = frames.next();
+        let decoded = match *settings.ws().idle_timeout() {
+            Some(idle_timeout) => match timeout(idle_timeout, decoding).await {
+                Ok(decoded) => decoded,
+                Err(_) => {
+                    debug!("Close session idle for {:?}", idle_timeout);
+                    let frame = Frame::Close {
+                        status_code: Some(CLOSE_GOING_AWAY),
+                        message: vec![],
+                    };
+                    send(sender, frame).await?;
+                    break;
+                }
+            },
+            None => decoding.await,
+        };
+        let request_frame = match decoded {
+            Some(decoded) => decoded.context("Failed to decode frame")?,
+            None => bail!("Connection closed without Close frame"),
+        };
+        debug!(
+            "Decode websocket frame: {}",
+            request_frame.summary(*settings.ws().log_preview_size())
+        );
+        if *settings.ws().log_checksums() {
+            debug!(
+                "Checksum of decoded frame: {:08x}",
+                request_frame.checksum()
+            );
+        }
+
+        let size = request_frame.get_data().len();
+        metrics::record_decoded_frame(request_frame.name(), size);
+        if matches!(request_frame, Frame::Text { .. } | Frame::Binary { .. }) {
+            message_sizes.record(size);
+            metrics::record_message_size(size);
+        }
+
+        match request_frame {
+            Frame::Text { message } if *settings.ws().enable_rooms() => {
+                match RoomCommand::parse(&message) {
+                    Some(RoomCommand::Join(name)) => {
+                        rooms.join(&name, id, sender.clone());
+                        if !joined.contains(&name) {
+                            joined.push(name);
+                        }
+                    }
+                    Some(RoomCommand::Leave(name)) => {
+                        rooms.leave(&name, &id);
+                        joined.retain(|x| x != &name);
+                    }
+                    None if !joined.is_empty() => {
+                        for name in joined.iter() {
+                            rooms.broadcast(name, &message).await;
+                        }
+                    }
+                    None => {
+                        on_text(handler, settings, id, sender, message).await?;
+                    }
+                }
+            }
+            Frame::Text { message } => {
+                on_text(handler, settings, id, sender, message).await?;
+            }
+            Frame::Binary { data } => {
+                send_all(sender, app.on_binary(id, data).await).await?;
+            }
+            Frame::Ping { data } => {
+                send(sender, Frame::pong(data)?).await?;
+            }
+            Frame::Pong { .. } => {
+                *last_pong.lock().unwrap() = Instant::now();
+            }
+            frame @ Frame::Close { status_code, .. } => {
+                app.on_close(id, status_code).await;
+                // send back Close to show we accept it
+                send(sender, frame).await?;
+                // stop reading here since the peer may half-close the connection after Close,
+                // in which case the next read would fail with EOF
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn log_message_sizes(message_sizes: &SizeHistogram) {
+    debug!(
+        "Received {} messages ({} bytes) in session: {:?}",
+        message_sizes.count(),
+        message_sizes.sum(),
+        message_sizes.buckets()
+    );
+}
+
+/// Return codec of frames in a session, which compresses messages if `deflate` is negotiated
+fn session_codec(settings: &Settings, deflate: Option<DeflateParams>) -> WsFrameCodec {
+    let max_frame_size = settings
+        .ws()
+        .max_frame_size()
+        .as_ref()
+        .map(|size| size.to_bytes() as usize);
+    WsFrameCodec::new(settings.ws().max_payload_size().to_bytes() as usize)
+        .with_mask_required(*settings.ws().require_masked_frames())
+        .with_inflater(deflate.map(|deflate| deflate.inflater()))
+        .with_deflater(deflate.map(|deflate| deflate.deflater()))
+        .with_max_frame_size(max_frame_size)
+}
+
+/// Write the frame transformed by `hook` to `sink`
+async fn write_frame<S: Sink<Frame, Error = anyhow::Error> + Unpin>(
+    sink: &mut S,
+    frame: Frame,
+    settings: &Settings,
+    hook: Option<&FrameHook>,
+) -> Result<()> {
+    let frame = match hook {
+        Some(hook) => hook(frame),
+        None => frame,
+    };
+    if *settings.ws().log_checksums() {
+        debug!("Checksum of encoded frame: {:08x}", frame.checksum());
+    }
+    sink.send(frame).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    fn create_ws_request() -> Request {
+        Request::new(
+            RequestLine::new(RequestMethod::GET, "/", HTTPVersion::V1_1),
+            RequestHeaders::from([
+                ("Host", "localhost:80"),
+                ("Upgrade", "websocket"),
+                ("Connection", "Upgrade"),
+                ("Sec-WebSocket-Version", "13"),
+                // the value comes from RFC 6455 1.3
                 ("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="),
             ]),
             RequestBody::new(vec![]),
@@ -411,7 +1472,7 @@ mod tests {
     #[test]
     fn test_websocket_handler_handshake() {
         let req = create_ws_request();
-        let res = WebSocketHandler.handshake(&req);
+        let res = WebSocketHandler::new().handshake(&req, &Settings::default());
         assert!(res.is_ok());
         let res = res.unwrap();
         assert_eq!(res.get_status(), &ResponseStatus::SwitchingProtocol);
@@ -423,6 +1484,22 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_websocket_handler_handshake_for_client_request() {
+        let req = build_client_handshake("/chat?room=a", "localhost:8080");
+        let key = base64::decode(req.get_header("Sec-WebSocket-Key").unwrap()).unwrap();
+        assert_eq!(key.len(), 16);
+
+        // the request is received as it's encoded
+        let encoded = req.encode();
+        let received = Request::parse(&mut encoded.as_slice()).await.unwrap();
+        assert_eq!(received.get_path(), "/chat");
+        let res = WebSocketHandler::new()
+            .handshake(&received, &Settings::default())
+            .unwrap();
+        assert_eq!(res.get_status(), &ResponseStatus::SwitchingProtocol);
+    }
+
     #[test]
     fn test_websocket_handler_handshake_for_missing_headers() {
         let original_req = create_ws_request();
@@ -434,21 +1511,174 @@ mod tests {
         ] {
             let mut req = original_req.clone();
             req.remove_header(header);
-            let res = WebSocketHandler.handshake(&req);
+            let res = WebSocketHandler::new().handshake(&req, &Settings::default());
             assert!(res.is_err(), "Should require \"{}\" in header", header);
             assert_eq!(res.unwrap_err().get_status(), &ResponseStatus::BadRequest);
         }
     }
 
+    #[test]
+    fn test_websocket_handler_handshake_for_illegal_key() {
+        let mut req = create_ws_request();
+        // 16 bytes of base64 is accepted as well as the one in create_ws_request
+        req.insert_header(
+            "Sec-WebSocket-Key".to_string(),
+            "AAECAwQFBgcICQoLDA0ODw==".to_string(),
+        );
+        let res = WebSocketHandler::new()
+            .handshake(&req, &Settings::default())
+            .unwrap();
+        assert_eq!(
+            res.get_header("Sec-WebSocket-Accept"),
+            Some("Bz3qJYTGdOe8gUSpLosEdiLKDrk=")
+        );
+
+        // 15 bytes, 17 bytes, 16 bytes without padding and not base64
+        for key in [
+            "AAECAwQFBgcICQoLDA0O",
+            "AAECAwQFBgcICQoLDA0ODxA=",
+            "dGhlIHNhbXBsZSBub25jZQ",
+            "not a base64 key!",
+        ] {
+            req.insert_header("Sec-WebSocket-Key".to_string(), key.to_string());
+            let res = WebSocketHandler::new().handshake(&req, &Settings::default());
+            assert_eq!(
+                res.unwrap_err().get_status(),
+                &ResponseStatus::BadRequest,
+                "key: {}",
+                key
+            );
+        }
+    }
+
     #[test]
     fn test_websocket_handler_handshake_for_illegal_connection() {
         let mut req = create_ws_request();
         req.insert_header("Connection".to_string(), "foo".to_string());
-        let res = WebSocketHandler.handshake(&req);
+        let res = WebSocketHandler::new().handshake(&req, &Settings::default());
         assert!(res.is_err());
         assert_eq!(res.unwrap_err().get_status(), &ResponseStatus::BadRequest);
     }
 
+    #[test]
+    fn test_websocket_handler_handshake_with_unsupported_extension() {
+        let mut req = create_ws_request();
+        req.insert_header(
+            "Sec-WebSocket-Extensions".to_string(),
+            "permessage-deflate".to_string(),
+        );
+
+        // ignore the extension by default
+        let res = WebSocketHandler::new().handshake(&req, &Settings::default());
+        assert!(res.is_ok());
+        assert_eq!(res.unwrap().get_header("Sec-WebSocket-Extensions"), None);
+
+        let settings: Settings =
+            serde_json::from_str(r#"{"http": {}, "ws": {"reject_unsupported_extensions": true}}"#)
+                .unwrap();
+        let res = WebSocketHandler::new().handshake(&req, &settings);
+        assert_eq!(res.unwrap_err().get_status(), &ResponseStatus::BadRequest);
+    }
+
+    #[test]
+    fn test_websocket_handler_negotiate_subprotocol() {
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "http": {},
+            "ws": {"subprotocols": ["chat", "superchat"]},
+        }))
+        .unwrap();
+        let handshake = |offered: Option<&str>| {
+            let mut req = create_ws_request();
+            if let Some(offered) = offered {
+                req.insert_header("Sec-WebSocket-Protocol".to_string(), offered.to_string());
+            }
+            WebSocketHandler::new()
+                .handshake(&req, &settings)
+                .unwrap()
+                .get_header("Sec-WebSocket-Protocol")
+                .map(|x| x.to_string())
+        };
+
+        assert_eq!(
+            handshake(Some("superchat, chat")),
+            Some("superchat".to_string())
+        );
+        assert_eq!(handshake(Some("mqtt")), None);
+        assert_eq!(handshake(None), None);
+    }
+
+    #[test]
+    fn test_websocket_handler_negotiate_permessage_deflate() {
+        let mut req = create_ws_request();
+        req.insert_header(
+            "Sec-WebSocket-Extensions".to_string(),
+            "permessage-deflate; server_no_context_takeover".to_string(),
+        );
+
+        let res = WebSocketHandler::new()
+            .handshake(&req, &Settings::default())
+            .unwrap();
+        assert_eq!(res.get_header("Sec-WebSocket-Extensions"), None);
+
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "http": {},
+            "ws": {"enable_permessage_deflate": true},
+        }))
+        .unwrap();
+        let res = WebSocketHandler::new().handshake(&req, &settings).unwrap();
+        assert_eq!(
+            res.get_header("Sec-WebSocket-Extensions"),
+            Some("permessage-deflate; server_no_context_takeover")
+        );
+    }
+
+    #[test]
+    fn test_websocket_handler_handshake_with_extra_headers() {
+        let handler = WebSocketHandler::new().with_handshake_headers(|_| {
+            vec![
+                ("Set-Cookie".to_string(), "session=abc".to_string()),
+                ("connection".to_string(), "close".to_string()),
+            ]
+        });
+        let res = handler
+            .handshake(&create_ws_request(), &Settings::default())
+            .unwrap();
+        assert_eq!(res.get_status(), &ResponseStatus::SwitchingProtocol);
+        assert_eq!(res.get_header("Set-Cookie"), Some("session=abc"));
+        // headers required by the handshake are not overridden
+        let head = String::from_utf8(res.encode()).unwrap().to_lowercase();
+        assert_eq!(head.matches("connection:").count(), 1);
+        assert!(head.contains("connection: upgrade\r\n"));
+    }
+
+    #[test]
+    fn test_encode_fragmented_binary_frame() {
+        let frame = Frame::Binary {
+            data: vec![1, 2, 3, 4, 5],
+        };
+        let frames = frame.encode_fragmented(2).unwrap();
+        assert_eq!(
+            frames,
+            vec![
+                vec![0x02, 0x02, 1, 2],
+                vec![0x00, 0x02, 3, 4],
+                vec![0x80, 0x01, 5],
+            ]
+        );
+
+        assert!(Frame::ping("hello").unwrap().encode_fragmented(2).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_decode_encoded_fragmented_frame() {
+        let frame = Frame::Text {
+            message: "hello".to_string(),
+        };
+        let raw_data = frame.encode_fragmented(2).unwrap().concat();
+        let decoded = Frame::decode(&mut raw_data.as_slice(), 1024).await.unwrap();
+        assert_eq!(decoded, frame);
+    }
+
     #[tokio::test]
     async fn test_decode_ping_frame() {
         // ping frame with "hello" payload
@@ -473,6 +1703,18 @@ mod tests {
         assert_eq!(frame.encode().unwrap(), expected);
     }
 
+    #[test]
+    fn test_construct_ping_frame_with_max_control_payload() {
+        let frame = Frame::ping(vec![0u8; 125]);
+        assert!(matches!(frame, Ok(Frame::Ping { data }) if data.len() == 125));
+    }
+
+    #[test]
+    fn test_failed_to_construct_too_big_ping_frame() {
+        assert!(Frame::ping(vec![0u8; 126]).is_err());
+        assert!(Frame::pong(vec![0u8; 126]).is_err());
+    }
+
     #[tokio::test]
     async fn test_decode_close_frame() {
         let raw_data = vec![0x88, 0x80, 0x1e, 0x04, 0x7d, 0x84];
@@ -488,12 +1730,63 @@ mod tests {
         // status_code: 1002, message: hi
         let raw_data = vec![0x88, 0x84, 0x1e, 0x04, 0x7d, 0x84, 0x1d, 0xee, 0x15, 0xed];
         let frame = Frame::decode(&mut raw_data.as_slice(), 1024).await.unwrap();
+        assert_eq!(frame.close_reason(), Some("hi"));
         assert!(matches!(
             frame,
             Frame::Close { status_code: Some(1002), message } if message == vec![b'h', b'i']
         ));
     }
 
+    #[tokio::test]
+    async fn test_decode_close_frame_with_invalid_utf8_reason() {
+        // status_code: 1000, message: [0xff, 0xfe]
+        let raw_data = vec![0x88, 0x04, 0x03, 0xe8, 0xff, 0xfe];
+        let err = Frame::decode(&mut raw_data.as_slice(), 1024)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, FrameDecodeError::InvalidUtf8(_)));
+        assert!(matches!(
+            Frame::close_for_error(&err.into()),
+            Frame::Close {
+                status_code: Some(1007),
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_decode_close_frame_with_reserved_status_code() {
+        // 1005, 1006 and 1015 are only for reporting and never sent on the wire
+        for status_code in [999u16, 1004, 1005, 1006, 1015, 2000] {
+            let mut raw_data = vec![0x88, 0x02];
+            raw_data.extend(status_code.to_be_bytes());
+            let err = Frame::decode(&mut raw_data.as_slice(), 1024)
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(
+                    Frame::close_for_error(&err.into()),
+                    Frame::Close {
+                        status_code: Some(1002),
+                        ..
+                    }
+                ),
+                "{} should be rejected",
+                status_code
+            );
+        }
+
+        for status_code in [1000u16, 3000, 4000] {
+            let mut raw_data = vec![0x88, 0x02];
+            raw_data.extend(status_code.to_be_bytes());
+            let frame = Frame::decode(&mut raw_data.as_slice(), 1024).await.unwrap();
+            assert!(matches!(
+                frame,
+                Frame::Close { status_code: Some(code), .. } if code == status_code
+            ));
+        }
+    }
+
     #[test]
     fn test_encode_close_frame() {
         let frame = Frame::Close {
@@ -558,12 +1851,28 @@ mod tests {
         assert_eq!(frame.encode().unwrap(), expected);
     }
 
+    #[tokio::test]
+    async fn test_zero_length_text_frame_round_trip() {
+        let raw_data = vec![0x81u8, 0x80, 0x36, 0x80, 0xd6, 0x47];
+        let frame = Frame::decode(&mut raw_data.as_slice(), 1024).await.unwrap();
+        assert!(matches!(&frame, Frame::Text { message } if message.is_empty()));
+        assert_eq!(frame.encode().unwrap(), vec![0x81, 0x00]);
+    }
+
+    #[tokio::test]
+    async fn test_zero_length_binary_frame_round_trip() {
+        let raw_data = vec![0x82u8, 0x00];
+        let frame = Frame::decode(&mut raw_data.as_slice(), 1024).await.unwrap();
+        assert!(matches!(&frame, Frame::Binary { data } if data.is_empty()));
+        assert_eq!(frame.encode().unwrap(), raw_data);
+    }
+
     #[tokio::test]
     async fn test_decode_data_frame_with_middle_size_data() {
         // payload is "a" repeating `len` times
         let len = 128;
         let mut raw_data = vec![0x81u8, 0xfe, 0x00, 0x80, 0x61, 0xfc, 0xfd, 0x86];
-        raw_data.extend(vec![0x00, 0x9d, 0x9c, 0xe7].repeat(len / 4).iter());
+        raw_data.extend([0x00, 0x9d, 0x9c, 0xe7].repeat(len / 4).iter());
         let frame = Frame::decode(&mut raw_data.as_slice(), 1024).await.unwrap();
         if let Frame::Text { message } = frame {
             assert_eq!(message.len(), len,);
@@ -579,7 +1888,7 @@ mod tests {
         let mut raw_data = vec![
             0x81u8, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x04, 0x61, 0xfc, 0xfd, 0x86,
         ];
-        raw_data.extend(vec![0x00, 0x9d, 0x9c, 0xe7].repeat(len / 4).iter());
+        raw_data.extend([0x00, 0x9d, 0x9c, 0xe7].repeat(len / 4).iter());
         let frame = Frame::decode(&mut raw_data.as_slice(), len).await.unwrap();
         if let Frame::Text { message } = frame {
             assert_eq!(message.len(), len,);
@@ -618,6 +1927,667 @@ mod tests {
         assert_eq!(frame.encode().unwrap(), expected);
     }
 
+    #[tokio::test]
+    async fn test_checksum_of_decoded_text_frame() {
+        let raw_data = vec![
+            0x81u8, 0x85, 0x36, 0x80, 0xd6, 0x47, 0x5e, 0xe5, 0xba, 0x2b, 0x59,
+        ];
+        let frame = Frame::decode(&mut raw_data.as_slice(), 1024).await.unwrap();
+        // CRC32 of "hello"
+        assert_eq!(frame.checksum(), 0x3610a686);
+    }
+
+    #[test]
+    fn test_frame_summary() {
+        let frame = Frame::Text {
+            message: "hello".to_owned(),
+        };
+        assert_eq!(
+            frame.summary(16).to_string(),
+            "Text (len: 5) [68 65 6c 6c 6f]"
+        );
+        assert_eq!(frame.summary(2).to_string(), "Text (len: 5) [68 65 ...]");
+    }
+
+    #[test]
+    fn test_frame_summary_is_bounded_for_big_frame() {
+        let frame = Frame::Binary {
+            data: vec![0xff; 1024 * 1024],
+        };
+        let summary = frame.summary(16).to_string();
+        assert!(summary.starts_with("Binary (len: 1048576) [ff ff"));
+        assert!(summary.len() < 100);
+    }
+
+    #[tokio::test]
+    async fn test_session_is_closed_after_max_session_duration() {
+        let settings: Settings =
+            serde_json::from_str(r#"{"http": {}, "ws": {"max_session_duration": "100ms"}}"#)
+                .unwrap();
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let session = tokio::spawn(async move {
+            run_session(
+                &mut server,
+                Arc::new(settings),
+                &WebSocketHandler::new(),
+                None,
+            )
+            .await
+            .unwrap();
+        });
+
+        // keep sending ping (masked with zero key)
+        let started = tokio::time::Instant::now();
+        let ping = vec![0x89u8, 0x80, 0x00, 0x00, 0x00, 0x00];
+        let closed = loop {
+            // the server may have closed the session after the last Pong
+            let _ = client.write_all(&ping).await;
+            let frame = Frame::decode(&mut client, 1024).await.unwrap();
+            if let frame @ Frame::Close { .. } = frame {
+                break frame;
+            }
+            assert!(matches!(frame, Frame::Pong { .. }));
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+
+        assert!(matches!(
+            closed,
+            Frame::Close {
+                status_code: Some(1001),
+                ..
+            }
+        ));
+        let elapsed = started.elapsed();
+        assert!(elapsed >= std::time::Duration::from_millis(100));
+        assert!(elapsed < std::time::Duration::from_secs(1));
+        session.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_record_message_sizes_in_session() {
+        // binary frame masked with zero key
+        fn binary_frame(len: usize) -> Vec<u8> {
+            let mut buf = vec![0x82u8];
+            if len <= 0x7d {
+                buf.push(0x80 | len as u8);
+            } else {
+                buf.push(0xfe);
+                buf.extend((len as u16).to_be_bytes());
+            }
+            buf.extend([0x00; 4]);
+            buf.extend(vec![0x61; len]);
+            buf
+        }
+
+        let settings = Settings::default();
+        let (mut client, mut server) = tokio::io::duplex(64 * 1024);
+        let global_count = metrics::message_sizes().count();
+        let session = tokio::spawn(async move {
+            let mut message_sizes = SizeHistogram::new();
+            handle_frames(
+                &mut server,
+                &settings,
+                &WebSocketHandler::new(),
+                None,
+                &mut message_sizes,
+            )
+            .await
+            .unwrap();
+            message_sizes
+        });
+
+        for len in [10, 100, 300, 2000] {
+            client.write_all(&binary_frame(len)).await.unwrap();
+            let frame = Frame::decode(&mut client, 64 * 1024).await.unwrap();
+            assert!(matches!(frame, Frame::Binary { data } if data.len() == len));
+        }
+        // ping is not counted as a message
+        client
+            .write_all(&[0x89, 0x80, 0x00, 0x00, 0x00, 0x00])
+            .await
+            .unwrap();
+        client
+            .write_all(&[0x88, 0x80, 0x00, 0x00, 0x00, 0x00])
+            .await
+            .unwrap();
+
+        let message_sizes = session.await.unwrap();
+        assert_eq!(
+            &message_sizes.buckets()[..4],
+            &[
+                (Some(64), 1),
+                (Some(256), 1),
+                (Some(1024), 1),
+                (Some(4096), 1)
+            ]
+        );
+        assert_eq!(message_sizes.count(), 4);
+        assert_eq!(message_sizes.sum(), 10 + 100 + 300 + 2000);
+        // other sessions may record concurrently
+        assert!(metrics::message_sizes().count() >= global_count + 4);
+    }
+
+    #[tokio::test]
+    async fn test_outgoing_frame_hook_transforms_frames() {
+        let handler = WebSocketHandler::new().with_outgoing_frame_hook(|frame| match frame {
+            Frame::Text { message } => Frame::Text {
+                message: format!("echo: {}", message),
+            },
+            frame => frame,
+        });
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let session = tokio::spawn(async move {
+            run_session(&mut server, Arc::new(Settings::default()), &handler, None)
+                .await
+                .unwrap();
+        });
+
+        // text frame with "hello" and Close frame
+        client
+            .write_all(&[
+                0x81u8, 0x85, 0x36, 0x80, 0xd6, 0x47, 0x5e, 0xe5, 0xba, 0x2b, 0x59,
+            ])
+            .await
+            .unwrap();
+        client
+            .write_all(&[0x88, 0x80, 0x00, 0x00, 0x00, 0x00])
+            .await
+            .unwrap();
+
+        let mut expected = vec![0x81, 0x0b];
+        expected.extend(b"echo: hello");
+        expected.extend([0x88, 0x00]);
+        let mut actual = vec![];
+        client.read_to_end(&mut actual).await.unwrap();
+        assert_eq!(actual, expected);
+        session.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_half_closes_after_close_frame() {
+        let (client, mut server) = tokio::io::duplex(1024);
+        let session = tokio::spawn(async move {
+            run_session(
+                &mut server,
+                Arc::new(Settings::default()),
+                &WebSocketHandler::new(),
+                None,
+            )
+            .await
+        });
+
+        // Close frame followed by shutdown of the write side
+        let (mut client_reader, mut client_writer) = tokio::io::split(client);
+        client_writer
+            .write_all(&[0x88, 0x82, 0x00, 0x00, 0x00, 0x00, 0x03, 0xe8])
+            .await
+            .unwrap();
+        client_writer.shutdown().await.unwrap();
+
+        // only the echo of Close is received, not Close for an error
+        let mut actual = vec![];
+        client_reader.read_to_end(&mut actual).await.unwrap();
+        assert_eq!(actual, vec![0x88, 0x02, 0x03, 0xe8]);
+        assert!(session.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_respond_protocol_error_to_close_with_invalid_code() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let session = tokio::spawn(async move {
+            run_session(
+                &mut server,
+                Arc::new(Settings::default()),
+                &WebSocketHandler::new(),
+                None,
+            )
+            .await
+            .unwrap();
+        });
+
+        // Close frame with 1005, which is not echoed back
+        client
+            .write_all(&[0x88, 0x82, 0x00, 0x00, 0x00, 0x00, 0x03, 0xed])
+            .await
+            .unwrap();
+        let mut actual = vec![];
+        client.read_to_end(&mut actual).await.unwrap();
+        assert_eq!(actual, vec![0x88, 0x02, 0x03, 0xea]);
+        session.await.unwrap();
+    }
+
+    /// Send `raw_frame` to a session and return bytes received until the session finishes
+    async fn respond_to_raw_frame(settings: Settings, raw_frame: &[u8]) -> Vec<u8> {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let session = tokio::spawn(async move {
+            run_session(
+                &mut server,
+                Arc::new(settings),
+                &WebSocketHandler::new(),
+                None,
+            )
+            .await
+        });
+
+        client.write_all(raw_frame).await.unwrap();
+        let mut actual = vec![];
+        client.read_to_end(&mut actual).await.unwrap();
+        assert!(session.await.unwrap().is_ok());
+        actual
+    }
+
+    #[tokio::test]
+    async fn test_respond_close_with_code_for_decode_error() {
+        // text frame of 200 bytes, whose payload is not sent
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "http": {},
+            "ws": {"max_payload_size": "100B"},
+        }))
+        .unwrap();
+        let actual = respond_to_raw_frame(settings, &[0x81, 0xfe, 0x00, 0xc8]).await;
+        // 1009 (Message Too Big) with reason
+        let mut expected = vec![0x88, 0x11, 0x03, 0xf1];
+        expected.extend(b"Message too big");
+        assert_eq!(actual, expected);
+
+        // frame with reserved opcode 0x3
+        let actual =
+            respond_to_raw_frame(Settings::default(), &[0x83, 0x80, 0x00, 0x00, 0x00, 0x00]).await;
+        // 1002 (Protocol Error)
+        assert_eq!(actual, vec![0x88, 0x02, 0x03, 0xea]);
+    }
+
+    #[tokio::test]
+    async fn test_close_session_not_responding_pong() {
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "http": {},
+            "ws": {"ping_interval": "50ms", "pong_timeout": "50ms"},
+        }))
+        .unwrap();
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let session = tokio::spawn(async move {
+            run_session(
+                &mut server,
+                Arc::new(settings),
+                &WebSocketHandler::new(),
+                None,
+            )
+            .await
+            .unwrap();
+        });
+
+        // respond to the first Ping
+        let frame = Frame::decode(&mut client, 1024).await.unwrap();
+        assert_eq!(frame, Frame::Ping { data: vec![] });
+        client
+            .write_all(&[0x8a, 0x80, 0x00, 0x00, 0x00, 0x00])
+            .await
+            .unwrap();
+
+        // ignore the second Ping
+        let frame = Frame::decode(&mut client, 1024).await.unwrap();
+        assert_eq!(frame, Frame::Ping { data: vec![] });
+        let frame = Frame::decode(&mut client, 1024).await.unwrap();
+        assert_eq!(
+            frame,
+            Frame::Close {
+                status_code: Some(CLOSE_GOING_AWAY),
+                message: vec![],
+            }
+        );
+        session.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_close_idle_session() {
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "http": {},
+            "ws": {"idle_timeout": "50ms"},
+        }))
+        .unwrap();
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let session = tokio::spawn(async move {
+            run_session(
+                &mut server,
+                Arc::new(settings),
+                &WebSocketHandler::new(),
+                None,
+            )
+            .await
+            .unwrap();
+        });
+
+        // the client sends nothing
+        let mut actual = vec![];
+        tokio::time::timeout(
+            std::time::Duration::from_secs(1),
+            client.read_to_end(&mut actual),
+        )
+        .await
+        .expect("session should be closed")
+        .unwrap();
+        assert_eq!(actual, vec![0x88, 0x02, 0x03, 0xe9]);
+        session.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_custom_websocket_app() {
+        struct UppercaseApp;
+
+        #[async_trait]
+        impl WebSocketApp for UppercaseApp {
+            async fn on_open(&self, _id: Uuid) -> Vec<Frame> {
+                vec![Frame::Text {
+                    message: "WELCOME".to_string(),
+                }]
+            }
+
+            async fn on_text(&self, _id: Uuid, message: String) -> Vec<Frame> {
+                vec![Frame::Text {
+                    message: message.to_uppercase(),
+                }]
+            }
+
+            async fn on_binary(&self, _id: Uuid, _data: Vec<u8>) -> Vec<Frame> {
+                vec![]
+            }
+        }
+
+        let handler = WebSocketHandler::with_app(Arc::new(UppercaseApp));
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let session = tokio::spawn(async move {
+            run_session(&mut server, Arc::new(Settings::default()), &handler, None)
+                .await
+                .unwrap();
+        });
+
+        client.write_all(&masked_text_frame("hello")).await.unwrap();
+        client
+            .write_all(&[0x82, 0x81, 0x00, 0x00, 0x00, 0x00, 0x01])
+            .await
+            .unwrap();
+        client
+            .write_all(&[0x88, 0x80, 0x00, 0x00, 0x00, 0x00])
+            .await
+            .unwrap();
+
+        let mut expected = vec![0x81, 0x07];
+        expected.extend(b"WELCOME");
+        expected.extend([0x81, 0x05]);
+        expected.extend(b"HELLO");
+        expected.extend([0x88, 0x00]);
+        let mut actual = vec![];
+        client.read_to_end(&mut actual).await.unwrap();
+        assert_eq!(actual, expected);
+        session.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reject_unmasked_frame_if_mask_is_required() {
+        async fn run(mask_required: bool) -> Vec<u8> {
+            let settings: Settings = serde_json::from_value(serde_json::json!({
+                "http": {},
+                "ws": {"require_masked_frames": mask_required},
+            }))
+            .unwrap();
+            let (mut client, mut server) = tokio::io::duplex(1024);
+            let session = tokio::spawn(async move {
+                run_session(
+                    &mut server,
+                    Arc::new(settings),
+                    &WebSocketHandler::new(),
+                    None,
+                )
+                .await
+                .unwrap();
+            });
+
+            // unmasked text frame with "hi" and Close frame
+            client
+                .write_all(&[0x81, 0x02, b'h', b'i', 0x88, 0x80, 0x00, 0x00, 0x00, 0x00])
+                .await
+                .unwrap();
+            let mut actual = vec![];
+            client.read_to_end(&mut actual).await.unwrap();
+            session.await.unwrap();
+            actual
+        }
+
+        assert_eq!(run(false).await, vec![0x81, 0x02, b'h', b'i', 0x88, 0x00]);
+        assert_eq!(run(true).await, vec![0x88, 0x02, 0x03, 0xea]);
+    }
+
+    #[test]
+    fn test_unmask_large_payload() {
+        let mask_key = [0x37, 0xfa, 0x21, 0x3d];
+        // 1 MB and a few bytes not fitting in 8-byte chunks
+        let payload: Vec<u8> = (0..1024 * 1024 + 5).map(|i| (i % 251) as u8).collect();
+        let mut data: Vec<u8> = payload
+            .iter()
+            .enumerate()
+            .map(|(i, b)| b ^ mask_key[i % 4])
+            .collect();
+        Frame::unmask(&mut data, mask_key);
+        assert_eq!(data, payload);
+    }
+
+    #[tokio::test]
+    async fn test_decode_into_reuses_buffer() {
+        let mut raw_data = masked_text_frame("hello");
+        raw_data.extend(masked_text_frame("hi"));
+        let mut reader = raw_data.as_slice();
+        let mut buf = Vec::with_capacity(1024);
+        let ptr = buf.as_ptr();
+
+        for expected in ["hello", "hi"] {
+            let frame = Frame::decode_into(&mut reader, 1024, &mut buf)
+                .await
+                .unwrap();
+            assert_eq!(
+                frame,
+                Frame::Text {
+                    message: expected.to_string()
+                }
+            );
+            assert_eq!(buf.as_ptr(), ptr);
+            assert_eq!(buf.capacity(), 1024);
+        }
+    }
+
+    /// Encode text frame masked with zero key as clients do
+    fn masked_text_frame(message: &str) -> Vec<u8> {
+        let mut buf = vec![0x81u8, 0x80 | message.len() as u8, 0x00, 0x00, 0x00, 0x00];
+        buf.extend(message.bytes());
+        buf
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_message_to_room() {
+        let settings: Settings =
+            serde_json::from_str(r#"{"http": {}, "ws": {"enable_rooms": true}}"#).unwrap();
+        let settings = Arc::new(settings);
+        let handler = Arc::new(WebSocketHandler::new());
+
+        let mut clients = vec![];
+        for room in ["lobby", "lobby", "other"] {
+            let (mut client, mut server) = tokio::io::duplex(1024);
+            let settings = Arc::clone(&settings);
+            let handler = Arc::clone(&handler);
+            tokio::spawn(async move {
+                run_session(&mut server, settings, &handler, None)
+                    .await
+                    .unwrap();
+            });
+            client
+                .write_all(&masked_text_frame(&format!("/join {}", room)))
+                .await
+                .unwrap();
+            // the join has been handled when Pong is received
+            client
+                .write_all(&[0x89, 0x80, 0x00, 0x00, 0x00, 0x00])
+                .await
+                .unwrap();
+            let frame = Frame::decode(&mut client, 1024).await.unwrap();
+            assert!(matches!(frame, Frame::Pong { .. }));
+            clients.push(client);
+        }
+
+        clients[0]
+            .write_all(&masked_text_frame("hello"))
+            .await
+            .unwrap();
+        for client in clients[..2].iter_mut() {
+            let frame = Frame::decode(client, 1024).await.unwrap();
+            assert!(matches!(frame, Frame::Text { message } if message == "hello"));
+        }
+        let received = tokio::time::timeout(
+            std::time::Duration::from_millis(100),
+            Frame::decode(&mut clients[2], 1024),
+        )
+        .await;
+        assert!(received.is_err(), "Client in other room should not receive");
+    }
+
+    #[tokio::test]
+    async fn test_leave_rooms_after_max_session_duration() {
+        let settings: Settings = serde_json::from_str(
+            r#"{"http": {}, "ws": {"enable_rooms": true, "max_session_duration": "100ms"}}"#,
+        )
+        .unwrap();
+        let handler = Arc::new(WebSocketHandler::new());
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let session = {
+            let handler = Arc::clone(&handler);
+            tokio::spawn(async move {
+                run_session(&mut server, Arc::new(settings), &handler, None)
+                    .await
+                    .unwrap();
+            })
+        };
+        client
+            .write_all(&masked_text_frame("/join lobby"))
+            .await
+            .unwrap();
+        client
+            .write_all(&[0x89, 0x80, 0x00, 0x00, 0x00, 0x00])
+            .await
+            .unwrap();
+        let frame = Frame::decode(&mut client, 1024).await.unwrap();
+        assert!(matches!(frame, Frame::Pong { .. }));
+        assert!(!handler.rooms.is_empty());
+
+        // Close is the last frame written to the stream
+        let mut buf = vec![];
+        client.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, vec![0x88, 0x02, 0x03, 0xe9]);
+        session.await.unwrap();
+        assert!(handler.rooms.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_message_to_all_clients() {
+        let settings: Settings =
+            serde_json::from_str(r#"{"http": {}, "ws": {"broadcast_all": true}}"#).unwrap();
+        let settings = Arc::new(settings);
+        let handler = Arc::new(WebSocketHandler::new());
+
+        let mut clients = vec![];
+        for _ in 0..2 {
+            let (mut client, mut server) = tokio::io::duplex(1024);
+            let settings = Arc::clone(&settings);
+            let handler = Arc::clone(&handler);
+            tokio::spawn(async move {
+                run_session(&mut server, settings, &handler, None)
+                    .await
+                    .unwrap();
+            });
+            // the session has subscribed broadcasts when Pong is received
+            client
+                .write_all(&[0x89, 0x80, 0x00, 0x00, 0x00, 0x00])
+                .await
+                .unwrap();
+            let frame = Frame::decode(&mut client, 1024).await.unwrap();
+            assert!(matches!(frame, Frame::Pong { .. }));
+            clients.push(client);
+        }
+
+        clients[0]
+            .write_all(&masked_text_frame("hello"))
+            .await
+            .unwrap();
+        let frame = Frame::decode(&mut clients[1], 1024).await.unwrap();
+        assert!(matches!(frame, Frame::Text { message } if message == "hello"));
+        let received = tokio::time::timeout(
+            std::time::Duration::from_millis(100),
+            Frame::decode(&mut clients[0], 1024),
+        )
+        .await;
+        assert!(received.is_err(), "Sender should not receive its message");
+    }
+
+    #[tokio::test]
+    async fn test_close_session_lagging_behind_broadcasts() {
+        let (sender, mut receiver) = outgoing::channel(16, Default::default());
+        let (everyone, mut broadcasts) = broadcast::channel(2);
+        for message in ["a", "b", "c"] {
+            everyone
+                .send((Uuid::new_v4(), message.to_string()))
+                .unwrap();
+        }
+
+        relay_broadcasts(&mut broadcasts, Uuid::new_v4(), &sender)
+            .await
+            .unwrap();
+        assert_eq!(
+            receiver.recv().await,
+            Some(Frame::Close {
+                status_code: Some(CLOSE_INTERNAL_ERROR),
+                message: vec![],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_flush_buffered_writer_after_handshake() {
+        let (mut client, server) = tokio::io::duplex(1024);
+        let session = tokio::spawn(async move {
+            let mut server = tokio::io::BufWriter::new(server);
+            WebSocketHandler::new()
+                .upgrade(
+                    create_ws_request(),
+                    &mut server,
+                    "127.0.0.1:8888".parse().unwrap(),
+                    Arc::new(Settings::default()),
+                )
+                .await
+                .unwrap();
+        });
+
+        // the client waits for 101 before sending frames
+        let mut buf = vec![];
+        tokio::time::timeout(std::time::Duration::from_secs(1), async {
+            while !buf.ends_with(b"\r\n\r\n") {
+                buf.push(client.read_u8().await.unwrap());
+            }
+        })
+        .await
+        .expect("101 response should be flushed");
+        assert!(buf.starts_with(b"HTTP/1.1 101 "));
+
+        // ping and close, whose responses are also flushed
+        client
+            .write_all(&[0x89, 0x80, 0x00, 0x00, 0x00, 0x00])
+            .await
+            .unwrap();
+        let frame = Frame::decode(&mut client, 1024).await.unwrap();
+        assert!(matches!(frame, Frame::Pong { .. }));
+        client
+            .write_all(&[0x88, 0x80, 0x00, 0x00, 0x00, 0x00])
+            .await
+            .unwrap();
+        session.await.unwrap();
+    }
+
     #[tokio::test]
     async fn test_failed_to_decode_bigger_frame_than_limit() {
         let raw_data = vec![
@@ -626,4 +2596,208 @@ mod tests {
         let res = Frame::decode(&mut raw_data.as_slice(), 4).await;
         assert!(res.is_err());
     }
+
+    #[tokio::test]
+    async fn test_decode_fragmented_text_frame() {
+        // "hel" without FIN and "lo" in continuation frame with FIN
+        let raw_data = vec![
+            0x01, 0x83, 0x00, 0x00, 0x00, 0x00, b'h', b'e', b'l', 0x80, 0x82, 0x00, 0x00, 0x00,
+            0x00, b'l', b'o',
+        ];
+        let frame = Frame::decode(&mut raw_data.as_slice(), 1024).await.unwrap();
+        assert_eq!(
+            frame,
+            Frame::Text {
+                message: "hello".to_string()
+            }
+        );
+
+        // the limit is applied to the whole message
+        let res = Frame::decode(&mut raw_data.as_slice(), 4).await;
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_decode_ping_frame_between_fragments() {
+        let raw_data = vec![
+            0x01, 0x83, 0x00, 0x00, 0x00, 0x00, b'h', b'e', b'l', 0x89, 0x82, 0x00, 0x00, 0x00,
+            0x00, b'h', b'i', 0x80, 0x82, 0x00, 0x00, 0x00, 0x00, b'l', b'o',
+        ];
+        let mut reader = raw_data.as_slice();
+        let mut decoder = FrameDecoder::new();
+
+        let frame = decoder.decode(&mut reader, 1024).await.unwrap();
+        assert_eq!(
+            frame,
+            Frame::Ping {
+                data: b"hi".to_vec()
+            }
+        );
+        let frame = decoder.decode(&mut reader, 1024).await.unwrap();
+        assert_eq!(
+            frame,
+            Frame::Text {
+                message: "hello".to_string()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_to_decode_too_big_ping_frame() {
+        let mut raw_data = vec![0x89, 0xfe, 0x00, 0xc8, 0x00, 0x00, 0x00, 0x00];
+        raw_data.extend([0u8; 200]);
+        let err = Frame::decode(&mut raw_data.as_slice(), 1024)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, FrameDecodeError::Protocol(_)));
+    }
+
+    #[tokio::test]
+    async fn test_decode_frame_encoded_by_client() {
+        let frame = Frame::Text {
+            message: "Hello".to_string(),
+        };
+        let encoded = frame
+            .encode_client_with_mask([0x37, 0xfa, 0x21, 0x3d])
+            .unwrap();
+        // the example comes from RFC 6455 5.7
+        assert_eq!(
+            encoded,
+            vec![0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58]
+        );
+
+        let frames = [
+            frame,
+            Frame::Binary {
+                data: (0..=255).collect(),
+            },
+            Frame::Close {
+                status_code: Some(1000),
+                message: b"bye".to_vec(),
+            },
+        ];
+        for frame in frames {
+            let encoded = frame.encode_client().unwrap();
+            let decoded = FrameDecoder::new()
+                .with_mask_required(true)
+                .decode(&mut encoded.as_slice(), 1024)
+                .await
+                .unwrap();
+            assert_eq!(decoded, frame);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_to_decode_too_big_frame() {
+        // binary frame declaring 64-bit length of 2048 bytes
+        let raw_data = vec![0x82, 0xff, 0, 0, 0, 0, 0, 0, 0x08, 0x00];
+        let err = Frame::decode(&mut raw_data.as_slice(), 1024)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, FrameDecodeError::TooBig(_)));
+        assert_eq!(
+            Frame::close_for_error(&err.into()),
+            Frame::Close {
+                status_code: Some(CLOSE_MESSAGE_TOO_BIG),
+                message: b"Message too big".to_vec(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_to_decode_fragmented_close_frame() {
+        let raw_data = vec![0x08, 0x80, 0x00, 0x00, 0x00, 0x00];
+        let err = Frame::decode(&mut raw_data.as_slice(), 1024)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, FrameDecodeError::Protocol(_)));
+    }
+
+    #[tokio::test]
+    async fn test_failed_to_decode_frame_with_rsv_bit() {
+        // text frame with "hi" setting RSV1
+        let raw_data = vec![0xc1, 0x82, 0x00, 0x00, 0x00, 0x00, b'h', b'i'];
+        let err = Frame::decode(&mut raw_data.as_slice(), 1024)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, FrameDecodeError::Protocol(_)));
+        assert_eq!(
+            Frame::close_for_error(&err.into()),
+            Frame::Close {
+                status_code: Some(CLOSE_PROTOCOL_ERROR),
+                message: vec![],
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_decode_and_encode_deflated_frame() {
+        // "Hello" compressed in RFC 7692 7.2.3.1
+        let raw_data = vec![0xc1, 0x07, 0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00];
+        let mut decoder =
+            FrameDecoder::new().with_inflater(Some(DeflateParams::default().inflater()));
+        let frame = decoder
+            .decode(&mut raw_data.as_slice(), 1024)
+            .await
+            .unwrap();
+        assert_eq!(
+            frame,
+            Frame::Text {
+                message: "Hello".to_string()
+            }
+        );
+
+        let mut deflater = DeflateParams::default().deflater();
+        let encoded = frame.encode_deflated(&mut deflater, None).unwrap();
+        assert_eq!(encoded.len(), 1);
+        assert_eq!(encoded[0][0], 0xc1);
+        let mut decoder =
+            FrameDecoder::new().with_inflater(Some(DeflateParams::default().inflater()));
+        let decoded = decoder
+            .decode(&mut encoded[0].as_slice(), 1024)
+            .await
+            .unwrap();
+        assert_eq!(decoded, frame);
+
+        // RSV1 is not allowed without permessage-deflate
+        let raw_data = vec![0xc1, 0x07, 0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00];
+        assert!(Frame::decode(&mut raw_data.as_slice(), 1024).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_decode_error_variant_for_malformed_input() {
+        async fn decode_err(raw_data: &[u8]) -> FrameDecodeError {
+            Frame::decode(&mut &raw_data[..], 1024).await.unwrap_err()
+        }
+
+        // truncated in the middle of payload
+        let err = decode_err(&[0x81, 0x05, b'h', b'i']).await;
+        assert!(matches!(err, FrameDecodeError::Io(_)));
+        assert_eq!(err.close_code(), CLOSE_INTERNAL_ERROR);
+        // unmasked frame is accepted unless mask is required, but fragmented ping is not
+        let err = decode_err(&[0x09, 0x00]).await;
+        assert!(matches!(err, FrameDecodeError::Protocol(_)));
+        assert_eq!(err.close_code(), CLOSE_PROTOCOL_ERROR);
+        let err = decode_err(&[0x82, 0x7e, 0x08, 0x00]).await;
+        assert!(matches!(err, FrameDecodeError::TooBig(_)));
+        assert_eq!(err.close_code(), CLOSE_MESSAGE_TOO_BIG);
+        let err = decode_err(&[0x81, 0x02, 0xff, 0xfe]).await;
+        assert!(matches!(err, FrameDecodeError::InvalidUtf8(_)));
+        assert_eq!(err.close_code(), CLOSE_INVALID_PAYLOAD);
+        let err = decode_err(&[0x83, 0x00]).await;
+        assert!(matches!(err, FrameDecodeError::InvalidOpcode(0x3)));
+        assert_eq!(err.close_code(), CLOSE_PROTOCOL_ERROR);
+        // unknown opcode of the first fragment
+        let err = decode_err(&[0x03, 0x00]).await;
+        assert!(matches!(err, FrameDecodeError::InvalidOpcode(0x3)));
+    }
+
+    #[tokio::test]
+    async fn test_failed_to_decode_unexpected_continuation_frame() {
+        let raw_data = vec![0x80, 0x82, 0x00, 0x00, 0x00, 0x00, b'l', b'o'];
+        let err = Frame::decode(&mut raw_data.as_slice(), 1024)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, FrameDecodeError::Protocol(_)));
+    }
 }
@@ -0,0 +1,34 @@
+use crate::http::handler::websocket::Frame;
+use async_trait::async_trait;
+use uuid::Uuid;
+
+/// Application logic of WebSocket sessions.
+/// Each method receives id of the session and returns frames to be sent to the client.
+#[async_trait]
+pub trait WebSocketApp: Send + Sync {
+    /// Called when the handshake succeeded
+    async fn on_open(&self, _id: Uuid) -> Vec<Frame> {
+        vec![]
+    }
+
+    async fn on_text(&self, id: Uuid, message: String) -> Vec<Frame>;
+
+    async fn on_binary(&self, id: Uuid, data: Vec<u8>) -> Vec<Frame>;
+
+    /// Called when Close is received from the client, which is echoed back after this
+    async fn on_close(&self, _id: Uuid, _status_code: Option<u16>) {}
+}
+
+/// Send received messages back to the client
+pub struct EchoApp;
+
+#[async_trait]
+impl WebSocketApp for EchoApp {
+    async fn on_text(&self, _id: Uuid, message: String) -> Vec<Frame> {
+        vec![Frame::Text { message }]
+    }
+
+    async fn on_binary(&self, _id: Uuid, data: Vec<u8>) -> Vec<Frame> {
+        vec![Frame::Binary { data }]
+    }
+}
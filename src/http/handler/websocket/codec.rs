@@ -0,0 +1,216 @@
+use crate::http::deflate::{Deflater, Inflater};
+use crate::http::handler::websocket::{Decoded, Frame, FrameDecodeError, FrameDecoder};
+use crate::http::metrics;
+use bytes::{Buf, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Codec of WebSocket frames to read them from `FramedRead` as a stream
+/// and write them to `FramedWrite` as a sink, e.g.
+///
+/// ```ignore
+/// let mut frames = FramedRead::new(reader, WsFrameCodec::new(max_payload_size));
+/// while let Some(frame) = frames.next().await {
+///     // ...
+/// }
+/// ```
+///
+/// Fragments of a message are kept in the codec until the last one is decoded,
+/// and control frames interleaved between them are returned in the middle of the message.
+#[derive(Debug)]
+pub struct WsFrameCodec {
+    decoder: FrameDecoder,
+    max_payload_size: usize,
+    // set if permessage-deflate is negotiated
+    deflater: Option<Deflater>,
+    max_frame_size: Option<usize>,
+}
+
+impl WsFrameCodec {
+    pub fn new(max_payload_size: usize) -> WsFrameCodec {
+        WsFrameCodec {
+            decoder: FrameDecoder::new(),
+            max_payload_size,
+            deflater: None,
+            max_frame_size: None,
+        }
+    }
+
+    /// Reject unmasked frames if `mask_required` is set
+    pub fn with_mask_required(mut self, mask_required: bool) -> WsFrameCodec {
+        self.decoder = self.decoder.with_mask_required(mask_required);
+        self
+    }
+
+    /// Inflate messages setting RSV1 by `inflater`
+    pub fn with_inflater(mut self, inflater: Option<Inflater>) -> WsFrameCodec {
+        self.decoder = self.decoder.with_inflater(inflater);
+        self
+    }
+
+    /// Compress messages by `deflater`
+    pub fn with_deflater(mut self, deflater: Option<Deflater>) -> WsFrameCodec {
+        self.deflater = deflater;
+        self
+    }
+
+    /// Fragment messages into frames whose payload is at most `max_frame_size` bytes
+    pub fn with_max_frame_size(mut self, max_frame_size: Option<usize>) -> WsFrameCodec {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+}
+
+impl Decoder for WsFrameCodec {
+    type Item = Frame;
+    type Error = FrameDecodeError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Frame>, FrameDecodeError> {
+        loop {
+            match self.decoder.decode_buf(src, self.max_payload_size)? {
+                Decoded::Incomplete(missing) => {
+                    src.reserve(missing);
+                    return Ok(None);
+                }
+                Decoded::Consumed(len, frame) => {
+                    src.advance(len);
+                    if frame.is_some() {
+                        return Ok(frame);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Encoder<Frame> for WsFrameCodec {
+    type Error = anyhow::Error;
+
+    fn encode(&mut self, frame: Frame, dst: &mut BytesMut) -> anyhow::Result<()> {
+        for encoded in frame.encode_with(self.deflater.as_mut(), self.max_frame_size)? {
+            dst.extend_from_slice(&encoded);
+            metrics::record_sent_frame_bytes(encoded.len());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{SinkExt, StreamExt};
+    use tokio_test::io::Builder;
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    /// Encode a frame masked with zero key as clients do
+    fn masked_frame(metadata: u8, payload: &[u8]) -> Vec<u8> {
+        let mut buf = vec![metadata, 0x80 | payload.len() as u8, 0x00, 0x00, 0x00, 0x00];
+        buf.extend(payload);
+        buf
+    }
+
+    #[tokio::test]
+    async fn test_decode_frame_split_across_reads() {
+        let raw = masked_frame(0x81, b"hello");
+        // split in the header, in the mask key and in the payload
+        let reader = Builder::new()
+            .read(&raw[..1])
+            .read(&raw[1..4])
+            .read(&raw[4..8])
+            .read(&raw[8..])
+            .build();
+        let mut frames = FramedRead::new(reader, WsFrameCodec::new(1024).with_mask_required(true));
+
+        assert_eq!(
+            frames.next().await.unwrap().unwrap(),
+            Frame::Text {
+                message: "hello".to_string()
+            }
+        );
+        assert!(frames.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_decode_fragmented_message_across_reads() {
+        // "hel", Ping interleaved and "lo"
+        let mut raw = masked_frame(0x01, b"hel");
+        raw.extend(masked_frame(0x89, b"hi"));
+        raw.extend(masked_frame(0x80, b"lo"));
+        let reader = Builder::new()
+            .read(&raw[..5])
+            .read(&raw[5..12])
+            .read(&raw[12..])
+            .build();
+        let mut frames = FramedRead::new(reader, WsFrameCodec::new(1024));
+
+        assert_eq!(
+            frames.next().await.unwrap().unwrap(),
+            Frame::Ping {
+                data: b"hi".to_vec()
+            }
+        );
+        assert_eq!(
+            frames.next().await.unwrap().unwrap(),
+            Frame::Text {
+                message: "hello".to_string()
+            }
+        );
+        assert!(frames.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_reject_too_big_frame_before_payload_arrives() {
+        // binary frame declaring 2048 bytes, whose payload never arrives
+        let reader = Builder::new()
+            .read(&[0x82, 0xfe, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00])
+            .build();
+        let mut frames = FramedRead::new(reader, WsFrameCodec::new(1024));
+        assert!(matches!(
+            frames.next().await,
+            Some(Err(FrameDecodeError::TooBig(_)))
+        ));
+
+        // the limit is applied to the whole message
+        let mut raw = masked_frame(0x02, &[0u8; 100]);
+        raw.extend(masked_frame(0x80, &[0u8; 100]));
+        let reader = Builder::new().read(&raw).build();
+        let mut frames = FramedRead::new(reader, WsFrameCodec::new(150));
+        assert!(matches!(
+            frames.next().await,
+            Some(Err(FrameDecodeError::TooBig(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_fail_on_truncated_frame() {
+        let raw = masked_frame(0x81, b"hello");
+        let reader = Builder::new().read(&raw[..8]).build();
+        let mut frames = FramedRead::new(reader, WsFrameCodec::new(1024));
+        assert!(matches!(
+            frames.next().await,
+            Some(Err(FrameDecodeError::Io(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_encode_frames_into_sink() {
+        let writer = Builder::new()
+            .write(&[0x81, 0x02, b'h', b'i'])
+            .write(&[0x01, 0x02, b'h', b'e', 0x80, 0x01, b'y'])
+            .build();
+        let mut frames =
+            FramedWrite::new(writer, WsFrameCodec::new(1024).with_max_frame_size(Some(2)));
+
+        frames
+            .send(Frame::Text {
+                message: "hi".to_string(),
+            })
+            .await
+            .unwrap();
+        frames
+            .send(Frame::Text {
+                message: "hey".to_string(),
+            })
+            .await
+            .unwrap();
+    }
+}
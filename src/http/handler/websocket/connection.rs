@@ -0,0 +1,236 @@
+use crate::http::handler::websocket::codec::WsFrameCodec;
+use crate::http::handler::websocket::{Frame, FrameDecodeError};
+use anyhow::{bail, Result};
+use futures::{ready, Sink, SinkExt, Stream};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::Framed;
+
+/// WebSocket connection after the handshake, which is a stream of frames from the client
+/// and a sink of frames to it, e.g.
+///
+/// ```ignore
+/// let mut connection = WebSocketConnection::new(stream, WsFrameCodec::new(max_payload_size));
+/// while let Some(frame) = connection.next().await {
+///     if let Frame::Text { message } = frame? {
+///         connection.send(Frame::Text { message }).await?;
+///     }
+/// }
+/// ```
+///
+/// As the server side, frames from the client are unmasked and frames to it are sent unmasked
+/// (RFC 6455 5.1). The stream ends after Close is received, which is sent back unless Close
+/// has been sent already (RFC 6455 5.5.1). No frame can be sent after Close.
+pub struct WebSocketConnection<S> {
+    frames: Framed<S, WsFrameCodec>,
+    auto_pong: bool,
+    auto_close: bool,
+    // Pong or Close replied to the client, which is written before reading the next frame
+    reply: Option<Frame>,
+    // set while the reply is written to the buffer but not flushed yet
+    flushing: bool,
+    close_sent: bool,
+    close_received: bool,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> WebSocketConnection<S> {
+    pub fn new(stream: S, codec: WsFrameCodec) -> WebSocketConnection<S> {
+        WebSocketConnection {
+            frames: Framed::new(stream, codec),
+            auto_pong: true,
+            auto_close: true,
+            reply: None,
+            flushing: false,
+            close_sent: false,
+            close_received: false,
+        }
+    }
+
+    /// Reply Pong to each Ping if `auto_pong` is set, which is the default.
+    /// Ping is returned from the stream in either case.
+    pub fn with_auto_pong(mut self, auto_pong: bool) -> WebSocketConnection<S> {
+        self.auto_pong = auto_pong;
+        self
+    }
+
+    /// Send back Close received if `auto_close` is set, which is the default.
+    /// Unset it to send Close by yourself after frames which should precede it.
+    pub fn with_auto_close(mut self, auto_close: bool) -> WebSocketConnection<S> {
+        self.auto_close = auto_close;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub async fn send(&mut self, frame: Frame) -> Result<()> {
+        SinkExt::send(self, frame).await
+    }
+
+    /// Write the reply if any and flush it
+    fn poll_reply(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), FrameDecodeError>> {
+        if let Some(frame) = self.reply.take() {
+            match Pin::new(&mut self.frames).poll_ready(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(write_error(err))),
+                Poll::Pending => {
+                    self.reply = Some(frame);
+                    return Poll::Pending;
+                }
+            }
+            self.close_sent |= matches!(frame, Frame::Close { .. });
+            Pin::new(&mut self.frames)
+                .start_send(frame)
+                .map_err(write_error)?;
+            self.flushing = true;
+        }
+        if self.flushing {
+            ready!(Pin::new(&mut self.frames).poll_flush(cx)).map_err(write_error)?;
+            self.flushing = false;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Convert an error of writing a reply, which is returned from the stream
+fn write_error(err: anyhow::Error) -> FrameDecodeError {
+    match err.downcast::<io::Error>() {
+        Ok(err) => FrameDecodeError::Io(err),
+        Err(err) => FrameDecodeError::Io(io::Error::other(format!("{:#}", err))),
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Stream for WebSocketConnection<S> {
+    type Item = Result<Frame, FrameDecodeError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if let Err(err) = ready!(this.poll_reply(cx)) {
+            return Poll::Ready(Some(Err(err)));
+        }
+        if this.close_received {
+            return Poll::Ready(None);
+        }
+
+        let frame = match ready!(Pin::new(&mut this.frames).poll_next(cx)) {
+            Some(Ok(frame)) => frame,
+            other => return Poll::Ready(other),
+        };
+        match &frame {
+            Frame::Ping { data } if this.auto_pong => {
+                this.reply = Some(Frame::Pong { data: data.clone() });
+            }
+            Frame::Close { .. } => {
+                this.close_received = true;
+                if this.auto_close && !this.close_sent {
+                    this.reply = Some(frame.clone());
+                }
+            }
+            _ => {}
+        }
+        // the reply is written now if possible, or on the next poll otherwise
+        if let Poll::Ready(Err(err)) = this.poll_reply(cx) {
+            return Poll::Ready(Some(Err(err)));
+        }
+        Poll::Ready(Some(Ok(frame)))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Sink<Frame> for WebSocketConnection<S> {
+    type Error = anyhow::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_reply(cx))?;
+        Pin::new(&mut this.frames).poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, frame: Frame) -> Result<()> {
+        let this = self.get_mut();
+        if this.close_sent {
+            bail!("Close has been sent: {}", frame.name());
+        }
+        this.close_sent = matches!(frame, Frame::Close { .. });
+        Pin::new(&mut this.frames).start_send(frame)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_reply(cx))?;
+        Pin::new(&mut this.frames).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_reply(cx))?;
+        Pin::new(&mut this.frames).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Encode a frame masked with zero key as clients do
+    fn masked_frame(metadata: u8, payload: &[u8]) -> Vec<u8> {
+        let mut buf = vec![metadata, 0x80 | payload.len() as u8, 0x00, 0x00, 0x00, 0x00];
+        buf.extend(payload);
+        buf
+    }
+
+    #[tokio::test]
+    async fn test_collect_frames_from_connection() {
+        for auto_pong in [true, false] {
+            let (mut client, server) = tokio::io::duplex(1024);
+            let codec = WsFrameCodec::new(1024).with_mask_required(true);
+            let mut connection = WebSocketConnection::new(server, codec).with_auto_pong(auto_pong);
+            connection
+                .send(Frame::Text {
+                    message: "hi".to_string(),
+                })
+                .await
+                .unwrap();
+
+            // Text, Ping and Close with status code 1000
+            let mut raw = masked_frame(0x81, b"hello");
+            raw.extend(masked_frame(0x89, b"hi"));
+            raw.extend(masked_frame(0x88, &[0x03, 0xe8]));
+            client.write_all(&raw).await.unwrap();
+            let frames: Vec<Frame> = connection.by_ref().map(Result::unwrap).collect().await;
+            assert_eq!(
+                frames,
+                vec![
+                    Frame::Text {
+                        message: "hello".to_string()
+                    },
+                    Frame::Ping {
+                        data: b"hi".to_vec()
+                    },
+                    Frame::Close {
+                        status_code: Some(1000),
+                        message: vec![],
+                    },
+                ]
+            );
+            // no frame follows Close
+            assert!(connection
+                .send(Frame::Text {
+                    message: "bye".to_string(),
+                })
+                .await
+                .is_err());
+            drop(connection);
+
+            let mut expected = vec![0x81, 0x02, b'h', b'i'];
+            if auto_pong {
+                expected.extend([0x8a, 0x02, b'h', b'i']);
+            }
+            expected.extend([0x88, 0x02, 0x03, 0xe8]);
+            let mut actual = vec![];
+            client.read_to_end(&mut actual).await.unwrap();
+            assert_eq!(actual, expected, "auto_pong: {}", auto_pong);
+        }
+    }
+}
@@ -21,6 +21,7 @@ pub trait HeaderParser {
     fn parse(&self, s: &str) -> Option<Self::Value>;
 }
 
+#[allow(dead_code)]
 pub struct VecHeaderParser;
 
 impl HeaderParser for VecHeaderParser {
@@ -31,7 +32,83 @@ impl HeaderParser for VecHeaderParser {
     }
 }
 
+#[allow(dead_code)]
 pub static CONNECTION: Lazy<HTTPHeader<VecHeaderParser>> = Lazy::new(|| HTTPHeader {
     name: "Connection",
     parser: VecHeaderParser,
 });
+
+/// WebSocket extension offered in Sec-WebSocket-Extensions header (RFC 6455 9.1)
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct WebSocketExtension {
+    pub name: String,
+    pub params: Vec<(String, Option<String>)>,
+}
+
+pub struct WebSocketExtensionsParser;
+
+impl HeaderParser for WebSocketExtensionsParser {
+    type Value = Vec<WebSocketExtension>;
+
+    fn parse(&self, s: &str) -> Option<Self::Value> {
+        let mut extensions = vec![];
+        for item in s.split(',') {
+            let mut tokens = item.split(';').map(|x| x.trim());
+            let name = tokens.next().filter(|x| !x.is_empty())?.to_string();
+            let mut params = vec![];
+            for param in tokens {
+                match param.split_once('=') {
+                    Some((k, v)) => {
+                        let v = v.trim().trim_matches('"');
+                        params.push((k.trim().to_string(), Some(v.to_string())));
+                    }
+                    None => params.push((param.to_string(), None)),
+                }
+            }
+            extensions.push(WebSocketExtension { name, params });
+        }
+        Some(extensions)
+    }
+}
+
+/// Subprotocols offered by the client in preference order (RFC 6455 11.3.4)
+pub static SEC_WEBSOCKET_PROTOCOL: Lazy<HTTPHeader<VecHeaderParser>> = Lazy::new(|| HTTPHeader {
+    name: "Sec-WebSocket-Protocol",
+    parser: VecHeaderParser,
+});
+
+pub static SEC_WEBSOCKET_EXTENSIONS: Lazy<HTTPHeader<WebSocketExtensionsParser>> =
+    Lazy::new(|| HTTPHeader {
+        name: "Sec-WebSocket-Extensions",
+        parser: WebSocketExtensionsParser,
+    });
+
+/// Parser of `name=value` pairs separated by `;` in Cookie header (RFC 6265 4.2.1).
+/// Pairs without `=` are ignored, and quotes around values are removed.
+pub struct CookieParser;
+
+impl HeaderParser for CookieParser {
+    type Value = Vec<(String, String)>;
+
+    fn parse(&self, s: &str) -> Option<Self::Value> {
+        let cookies = s
+            .split(';')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(name, value)| {
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .unwrap_or(value);
+                (name.trim().to_string(), value.to_string())
+            })
+            .filter(|(name, _)| !name.is_empty())
+            .collect();
+        Some(cookies)
+    }
+}
+
+pub static COOKIE: Lazy<HTTPHeader<CookieParser>> = Lazy::new(|| HTTPHeader {
+    name: "Cookie",
+    parser: CookieParser,
+});
@@ -0,0 +1,280 @@
+use once_cell::sync::Lazy;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Upper bounds (inclusive) of histogram buckets in bytes.
+/// Sizes bigger than the last bound are counted in the overflow (`+Inf`) bucket.
+const SIZE_BUCKETS: [u64; 8] = [
+    64,
+    256,
+    1024,
+    4 * 1024,
+    16 * 1024,
+    64 * 1024,
+    256 * 1024,
+    1024 * 1024,
+];
+
+/// Histogram of received WebSocket message sizes over all connections
+static MESSAGE_SIZES: Lazy<Mutex<SizeHistogram>> = Lazy::new(|| Mutex::new(SizeHistogram::new()));
+
+static CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+static RESPONSE_BYTES: AtomicU64 = AtomicU64::new(0);
+static ACTIVE_WEBSOCKETS: AtomicU64 = AtomicU64::new(0);
+static WS_RECEIVED_BYTES: AtomicU64 = AtomicU64::new(0);
+static WS_SENT_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Types of WebSocket frames counted in `DECODED_FRAMES` with the same index
+const FRAME_TYPES: [&str; 5] = ["Text", "Binary", "Close", "Ping", "Pong"];
+
+static DECODED_FRAMES: [AtomicU64; FRAME_TYPES.len()] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+/// Histogram of sizes in bytes bucketed by `SIZE_BUCKETS`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeHistogram {
+    counts: [u64; SIZE_BUCKETS.len() + 1],
+    sum: u64,
+}
+
+impl SizeHistogram {
+    pub fn new() -> SizeHistogram {
+        SizeHistogram {
+            counts: [0; SIZE_BUCKETS.len() + 1],
+            sum: 0,
+        }
+    }
+
+    pub fn record(&mut self, size: usize) {
+        let size = size as u64;
+        let idx = SIZE_BUCKETS
+            .iter()
+            .position(|bound| size <= *bound)
+            .unwrap_or(SIZE_BUCKETS.len());
+        self.counts[idx] += 1;
+        self.sum += size;
+    }
+
+    /// Return the number of recorded sizes
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Return the sum of recorded sizes
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+
+    /// Return pairs of upper bound and count for each bucket (not cumulative).
+    /// The upper bound of the overflow bucket is `None`.
+    pub fn buckets(&self) -> Vec<(Option<u64>, u64)> {
+        SIZE_BUCKETS
+            .iter()
+            .map(|bound| Some(*bound))
+            .chain([None])
+            .zip(self.counts.iter().copied())
+            .collect()
+    }
+
+    /// Render the histogram in Prometheus text exposition format
+    pub fn to_prometheus(&self, name: &str, help: &str) -> String {
+        let mut res = String::new();
+        let _ = writeln!(res, "# HELP {} {}", name, help);
+        let _ = writeln!(res, "# TYPE {} histogram", name);
+        let mut cumulative = 0;
+        for (bound, count) in self.buckets() {
+            cumulative += count;
+            let le = match bound {
+                Some(bound) => bound.to_string(),
+                None => "+Inf".to_string(),
+            };
+            let _ = writeln!(res, "{}_bucket{{le=\"{}\"}} {}", name, le, cumulative);
+        }
+        let _ = writeln!(res, "{}_sum {}", name, self.sum);
+        let _ = writeln!(res, "{}_count {}", name, self.count());
+        res
+    }
+}
+
+impl Default for SizeHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Record the size of a received message to the global histogram
+pub fn record_message_size(size: usize) {
+    MESSAGE_SIZES.lock().unwrap().record(size);
+}
+
+/// Return a copy of the global histogram of received message sizes
+pub fn message_sizes() -> SizeHistogram {
+    MESSAGE_SIZES.lock().unwrap().clone()
+}
+
+/// Count an accepted connection
+pub fn record_connection() {
+    CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Count bytes of a response written by handlers
+pub fn record_response_bytes(bytes: usize) {
+    RESPONSE_BYTES.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+/// Count a decoded WebSocket frame of the type (e.g. "Text") and its payload bytes
+pub fn record_decoded_frame(frame_type: &str, payload_bytes: usize) {
+    if let Some(idx) = FRAME_TYPES.iter().position(|x| *x == frame_type) {
+        DECODED_FRAMES[idx].fetch_add(1, Ordering::Relaxed);
+    }
+    WS_RECEIVED_BYTES.fetch_add(payload_bytes as u64, Ordering::Relaxed);
+}
+
+/// Count bytes of encoded WebSocket frames sent to the client
+pub fn record_sent_frame_bytes(bytes: usize) {
+    WS_SENT_BYTES.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+/// WebSocket connection counted as active until this is dropped
+pub struct ActiveWebSocket(());
+
+impl ActiveWebSocket {
+    pub fn start() -> ActiveWebSocket {
+        ACTIVE_WEBSOCKETS.fetch_add(1, Ordering::Relaxed);
+        ActiveWebSocket(())
+    }
+}
+
+impl Drop for ActiveWebSocket {
+    fn drop(&mut self) {
+        ACTIVE_WEBSOCKETS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Render a counter or gauge in Prometheus text exposition format.
+/// Each of `values` is a pair of labels like `type="text"` (may be empty) and the value.
+fn render_metric(res: &mut String, name: &str, kind: &str, help: &str, values: &[(String, u64)]) {
+    let _ = writeln!(res, "# HELP {} {}", name, help);
+    let _ = writeln!(res, "# TYPE {} {}", name, kind);
+    for (labels, value) in values {
+        if labels.is_empty() {
+            let _ = writeln!(res, "{} {}", name, value);
+        } else {
+            let _ = writeln!(res, "{}{{{}}} {}", name, labels, value);
+        }
+    }
+}
+
+/// Render all metrics in Prometheus text exposition format
+pub fn render_prometheus() -> String {
+    let load = |counter: &AtomicU64| vec![(String::new(), counter.load(Ordering::Relaxed))];
+    let mut res = String::new();
+    render_metric(
+        &mut res,
+        "http_connections_total",
+        "counter",
+        "Number of accepted connections",
+        &load(&CONNECTIONS),
+    );
+    render_metric(
+        &mut res,
+        "http_response_bytes_total",
+        "counter",
+        "Bytes of HTTP responses including WebSocket handshakes",
+        &load(&RESPONSE_BYTES),
+    );
+    render_metric(
+        &mut res,
+        "ws_active_connections",
+        "gauge",
+        "Number of WebSocket connections in session",
+        &load(&ACTIVE_WEBSOCKETS),
+    );
+    let decoded: Vec<_> = FRAME_TYPES
+        .iter()
+        .zip(DECODED_FRAMES.iter())
+        .map(|(frame_type, counter)| {
+            (
+                format!("type=\"{}\"", frame_type.to_ascii_lowercase()),
+                counter.load(Ordering::Relaxed),
+            )
+        })
+        .collect();
+    render_metric(
+        &mut res,
+        "ws_decoded_frames_total",
+        "counter",
+        "Number of decoded WebSocket frames by type",
+        &decoded,
+    );
+    render_metric(
+        &mut res,
+        "ws_received_bytes_total",
+        "counter",
+        "Payload bytes of decoded WebSocket frames",
+        &load(&WS_RECEIVED_BYTES),
+    );
+    render_metric(
+        &mut res,
+        "ws_sent_bytes_total",
+        "counter",
+        "Bytes of encoded WebSocket frames sent to clients",
+        &load(&WS_SENT_BYTES),
+    );
+    res.push_str(&message_sizes().to_prometheus(
+        "ws_received_message_size_bytes",
+        "Size of received WebSocket messages in bytes",
+    ));
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_size_histogram_record() {
+        let mut histogram = SizeHistogram::new();
+        for size in [0, 64, 65, 1000, 1024, 5000, 2 * 1024 * 1024] {
+            histogram.record(size);
+        }
+        assert_eq!(
+            histogram.buckets(),
+            vec![
+                (Some(64), 2),
+                (Some(256), 1),
+                (Some(1024), 2),
+                (Some(4 * 1024), 0),
+                (Some(16 * 1024), 1),
+                (Some(64 * 1024), 0),
+                (Some(256 * 1024), 0),
+                (Some(1024 * 1024), 0),
+                (None, 1),
+            ]
+        );
+        assert_eq!(histogram.count(), 7);
+        assert_eq!(
+            histogram.sum(),
+            64 + 65 + 1000 + 1024 + 5000 + 2 * 1024 * 1024
+        );
+    }
+
+    #[test]
+    fn test_size_histogram_to_prometheus() {
+        let mut histogram = SizeHistogram::new();
+        histogram.record(10);
+        histogram.record(100);
+        let text = histogram.to_prometheus("msg_size", "Size of messages");
+        assert!(text.starts_with("# HELP msg_size Size of messages\n# TYPE msg_size histogram\n"));
+        assert!(text.contains("msg_size_bucket{le=\"64\"} 1\n"));
+        assert!(text.contains("msg_size_bucket{le=\"256\"} 2\n"));
+        assert!(text.contains("msg_size_bucket{le=\"+Inf\"} 2\n"));
+        assert!(text.ends_with("msg_size_sum 110\nmsg_size_count 2\n"));
+    }
+}
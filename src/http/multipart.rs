@@ -0,0 +1,258 @@
+use crate::http::request::{RequestHeaders, RequestParseError};
+use crate::http::response::ResponseStatus;
+
+/// A part of multipart/form-data body (RFC 7578)
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MultipartPart {
+    headers: RequestHeaders,
+    data: Vec<u8>,
+}
+
+impl MultipartPart {
+    #[allow(dead_code)]
+    pub fn get_header(&self, key: &str) -> Option<&str> {
+        self.headers.get_raw(key)
+    }
+
+    /// Return `name` parameter of Content-Disposition, which is the field name of the form
+    #[allow(dead_code)]
+    pub fn name(&self) -> Option<String> {
+        self.disposition_param("name")
+    }
+
+    /// Return `filename` parameter of Content-Disposition, which is set for file parts
+    #[allow(dead_code)]
+    pub fn filename(&self) -> Option<String> {
+        self.disposition_param("filename")
+    }
+
+    /// Return Content-Type of the part, which defaults to text/plain (RFC 7578 4.4)
+    #[allow(dead_code)]
+    pub fn content_type(&self) -> &str {
+        self.get_header("Content-Type").unwrap_or("text/plain")
+    }
+
+    #[allow(dead_code)]
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn disposition_param(&self, key: &str) -> Option<String> {
+        let disposition = self.get_header("Content-Disposition")?;
+        parse_params(disposition)
+            .into_iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v)
+    }
+}
+
+fn client_error(msg: &str) -> RequestParseError {
+    RequestParseError::new(ResponseStatus::BadRequest, msg)
+}
+
+/// Parse parameters following the value like `form-data; name="a"; filename="b;c.txt"`.
+/// Values may be quoted strings with backslash escapes.
+fn parse_params(s: &str) -> Vec<(String, String)> {
+    let mut params = vec![];
+    let mut chars = s.chars().peekable();
+    // skip the value before parameters
+    for c in chars.by_ref() {
+        if c == ';' {
+            break;
+        }
+    }
+    loop {
+        let mut key = String::new();
+        let mut has_value = false;
+        for c in chars.by_ref() {
+            match c {
+                '=' => {
+                    has_value = true;
+                    break;
+                }
+                ';' => break,
+                c => key.push(c),
+            }
+        }
+        let key = key.trim().to_string();
+        if !has_value {
+            // parameter without value
+            if !key.is_empty() {
+                params.push((key, String::new()));
+            }
+            if chars.peek().is_none() {
+                break;
+            }
+            continue;
+        }
+
+        while chars.next_if(|c| *c == ' ' || *c == '\t').is_some() {}
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => value.extend(chars.next()),
+                    c => value.push(c),
+                }
+            }
+            // skip until the next parameter
+            for c in chars.by_ref() {
+                if c == ';' {
+                    break;
+                }
+            }
+        } else {
+            let raw: String = chars.by_ref().take_while(|c| *c != ';').collect();
+            value.push_str(raw.trim());
+        }
+        params.push((key, value));
+    }
+    params
+}
+
+/// Return `boundary` parameter of Content-Type of multipart body
+fn boundary(content_type: &str) -> Result<String, RequestParseError> {
+    let media_type = content_type.split(';').next().unwrap_or("").trim();
+    if !media_type.eq_ignore_ascii_case("multipart/form-data") {
+        return Err(RequestParseError::new(
+            ResponseStatus::UnsupportedMediaType,
+            "Content-Type must be multipart/form-data",
+        ));
+    }
+    // boundary is 1 to 70 characters (RFC 2046 5.1.1)
+    parse_params(content_type)
+        .into_iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case("boundary"))
+        .map(|(_, v)| v)
+        .filter(|v| !v.is_empty() && v.len() <= 70)
+        .ok_or_else(|| client_error("Illegal boundary of multipart body"))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn parse_part(bytes: &[u8]) -> Result<MultipartPart, RequestParseError> {
+    // a part without headers starts with the empty line
+    let (head, data) = if let Some(data) = bytes.strip_prefix(b"\r\n") {
+        (&b""[..], data)
+    } else {
+        let pos = find(bytes, b"\r\n\r\n").ok_or_else(|| client_error("Illegal multipart part"))?;
+        (&bytes[..pos], &bytes[(pos + 4)..])
+    };
+    let head =
+        std::str::from_utf8(head).map_err(|_| client_error("Illegal headers of multipart part"))?;
+    let lines: Vec<_> = head.split("\r\n").filter(|line| !line.is_empty()).collect();
+    Ok(MultipartPart {
+        headers: RequestHeaders::parse(&lines)?,
+        data: data.to_vec(),
+    })
+}
+
+/// Split multipart/form-data body into parts with the boundary in `content_type` (RFC 2046 5.1.1).
+/// Preamble before the first delimiter and epilogue after the last one are ignored.
+pub fn parse(body: &[u8], content_type: &str) -> Result<Vec<MultipartPart>, RequestParseError> {
+    let delimiter = format!("\r\n--{}", boundary(content_type)?).into_bytes();
+
+    // the first delimiter may not be preceded by CRLF if there is no preamble
+    let mut rest = if body.starts_with(&delimiter[2..]) {
+        &body[(delimiter.len() - 2)..]
+    } else {
+        let pos = find(body, &delimiter).ok_or_else(|| client_error("No multipart delimiter"))?;
+        &body[(pos + delimiter.len())..]
+    };
+
+    let mut parts = vec![];
+    loop {
+        if rest.starts_with(b"--") {
+            // close-delimiter
+            return Ok(parts);
+        }
+        // transport padding may follow the delimiter
+        let start = rest
+            .iter()
+            .position(|b| *b != b' ' && *b != b'\t')
+            .unwrap_or(rest.len());
+        rest = rest[start..]
+            .strip_prefix(b"\r\n")
+            .ok_or_else(|| client_error("Illegal multipart delimiter"))?;
+        let end = find(rest, &delimiter)
+            .ok_or_else(|| client_error("Multipart body is not terminated"))?;
+        parts.push(parse_part(&rest[..end])?);
+        rest = &rest[(end + delimiter.len())..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENT_TYPE: &str = "multipart/form-data; boundary=\"----boundary\"";
+
+    #[test]
+    fn test_parse_multipart_fields() {
+        let body = [
+            "preamble",
+            "------boundary",
+            "Content-Disposition: form-data; name=\"name\"",
+            "",
+            "alice",
+            "------boundary",
+            "Content-Disposition: form-data; name=\"age\"",
+            "",
+            "30",
+            "------boundary--",
+            "epilogue",
+        ]
+        .join("\r\n");
+
+        let parts = parse(body.as_bytes(), CONTENT_TYPE).unwrap();
+
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name().as_deref(), Some("name"));
+        assert_eq!(parts[0].data(), b"alice");
+        assert_eq!(parts[0].content_type(), "text/plain");
+        assert_eq!(parts[1].name().as_deref(), Some("age"));
+        assert_eq!(parts[1].data(), b"30");
+    }
+
+    #[test]
+    fn test_parse_multipart_file() {
+        let mut body = [
+            "------boundary",
+            "Content-Disposition: form-data; name=\"file\"; filename=\"a;b \\\"c\\\".bin\"",
+            "Content-Type: application/octet-stream",
+            "",
+            "",
+        ]
+        .join("\r\n")
+        .into_bytes();
+        // data containing CRLF and a string similar to the delimiter
+        body.extend(b"\x00\x01\r\n----boundar\r\n");
+        body.extend(b"\r\n------boundary--\r\n");
+
+        let parts = parse(&body, CONTENT_TYPE).unwrap();
+
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].name().as_deref(), Some("file"));
+        assert_eq!(parts[0].filename().as_deref(), Some("a;b \"c\".bin"));
+        assert_eq!(parts[0].content_type(), "application/octet-stream");
+        assert_eq!(parts[0].data(), b"\x00\x01\r\n----boundar\r\n");
+    }
+
+    #[test]
+    fn test_parse_illegal_multipart() {
+        let unterminated = "------boundary\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nx";
+        let err = parse(unterminated.as_bytes(), CONTENT_TYPE).unwrap_err();
+        assert_eq!(err.get_status(), &ResponseStatus::BadRequest);
+
+        let err = parse(b"", "multipart/form-data").unwrap_err();
+        assert_eq!(err.get_status(), &ResponseStatus::BadRequest);
+
+        let err = parse(b"", "application/json").unwrap_err();
+        assert_eq!(err.get_status(), &ResponseStatus::UnsupportedMediaType);
+    }
+}
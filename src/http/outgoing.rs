@@ -0,0 +1,119 @@
+use crate::http::handler::websocket::Frame;
+use crate::settings::OutgoingOverflowPolicy;
+use log::warn;
+use tokio::sync::{broadcast, mpsc};
+
+/// Sending half of the queue of frames to be written to a WebSocket client
+#[derive(Debug, Clone)]
+pub enum OutgoingSender {
+    Block(mpsc::Sender<Frame>),
+    DropOldest(broadcast::Sender<Frame>),
+}
+
+/// Receiving half of the queue of frames to be written to a WebSocket client
+#[derive(Debug)]
+pub enum OutgoingReceiver {
+    Block(mpsc::Receiver<Frame>),
+    DropOldest(broadcast::Receiver<Frame>),
+}
+
+/// Create a queue holding at most `capacity` pending frames.
+/// `capacity` is rounded up to a power of two for `DropOldest`.
+pub fn channel(
+    capacity: usize,
+    policy: OutgoingOverflowPolicy,
+) -> (OutgoingSender, OutgoingReceiver) {
+    let capacity = capacity.max(1);
+    match policy {
+        OutgoingOverflowPolicy::Block => {
+            let (sender, receiver) = mpsc::channel(capacity);
+            (
+                OutgoingSender::Block(sender),
+                OutgoingReceiver::Block(receiver),
+            )
+        }
+        OutgoingOverflowPolicy::DropOldest => {
+            let (sender, receiver) = broadcast::channel(capacity);
+            (
+                OutgoingSender::DropOldest(sender),
+                OutgoingReceiver::DropOldest(receiver),
+            )
+        }
+    }
+}
+
+impl OutgoingSender {
+    /// Queue the frame. Wait for a free slot if the queue is full and the policy is `Block`.
+    /// Return the frame back if the receiver has been dropped.
+    pub async fn send(&self, frame: Frame) -> Result<(), Frame> {
+        match self {
+            OutgoingSender::Block(sender) => sender.send(frame).await.map_err(|err| err.0),
+            OutgoingSender::DropOldest(sender) => {
+                sender.send(frame).map(|_| ()).map_err(|err| err.0)
+            }
+        }
+    }
+}
+
+impl OutgoingReceiver {
+    /// Return the next frame, or None if all senders have been dropped
+    pub async fn recv(&mut self) -> Option<Frame> {
+        match self {
+            OutgoingReceiver::Block(receiver) => receiver.recv().await,
+            OutgoingReceiver::DropOldest(receiver) => loop {
+                match receiver.recv().await {
+                    Ok(frame) => return Some(frame),
+                    Err(broadcast::error::RecvError::Lagged(count)) => {
+                        warn!(
+                            "Dropped {} oldest pending frames since outgoing queue is full",
+                            count
+                        );
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn text(message: &str) -> Frame {
+        Frame::Text {
+            message: message.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest_frame_if_queue_is_full() {
+        let (sender, mut receiver) = channel(2, OutgoingOverflowPolicy::DropOldest);
+        for message in ["a", "b", "c"] {
+            sender.send(text(message)).await.unwrap();
+        }
+        drop(sender);
+
+        assert_eq!(receiver.recv().await, Some(text("b")));
+        assert_eq!(receiver.recv().await, Some(text("c")));
+        assert_eq!(receiver.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_block_sender_if_queue_is_full() {
+        let (sender, mut receiver) = channel(2, OutgoingOverflowPolicy::Block);
+        sender.send(text("a")).await.unwrap();
+        sender.send(text("b")).await.unwrap();
+
+        let blocked = tokio::time::timeout(Duration::from_millis(50), sender.send(text("c"))).await;
+        assert!(blocked.is_err());
+
+        assert_eq!(receiver.recv().await, Some(text("a")));
+        sender.send(text("c")).await.unwrap();
+        drop(sender);
+        assert_eq!(receiver.recv().await, Some(text("b")));
+        assert_eq!(receiver.recv().await, Some(text("c")));
+        assert_eq!(receiver.recv().await, None);
+    }
+}
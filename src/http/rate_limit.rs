@@ -0,0 +1,74 @@
+use std::time::{Duration, Instant};
+
+/// Token bucket used to limit the rate of accepted connections.
+/// The bucket holds at most `capacity` tokens and refills `rate` tokens per second.
+pub struct TokenBucket {
+    capacity: f64,
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: u32) -> TokenBucket {
+        TokenBucket::new_at(rate, Instant::now())
+    }
+
+    fn new_at(rate: u32, now: Instant) -> TokenBucket {
+        let rate = rate as f64;
+        TokenBucket {
+            capacity: rate,
+            rate,
+            tokens: rate,
+            last_refill: now,
+        }
+    }
+
+    /// Return true if a token is available and consume it.
+    pub fn try_acquire(&mut self) -> bool {
+        self.try_acquire_at(Instant::now())
+    }
+
+    fn try_acquire_at(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.refill(elapsed);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn refill(&mut self, elapsed: Duration) {
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.capacity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket_drops_burst_over_limit() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new_at(10, now);
+        let accepted = (0..100).filter(|_| bucket.try_acquire_at(now)).count();
+        assert_eq!(accepted, 10);
+    }
+
+    #[test]
+    fn test_token_bucket_refills_over_time() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new_at(10, now);
+        for _ in 0..10 {
+            assert!(bucket.try_acquire_at(now));
+        }
+        assert!(!bucket.try_acquire_at(now));
+
+        let later = now + Duration::from_millis(500);
+        let accepted = (0..100).filter(|_| bucket.try_acquire_at(later)).count();
+        assert_eq!(accepted, 5);
+    }
+}
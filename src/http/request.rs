@@ -1,14 +1,20 @@
 use crate::http::common::HTTPVersion;
+use crate::http::compression;
 use crate::http::headers;
-use crate::http::response::ResponseStatus;
+use crate::http::multipart::{self, MultipartPart};
+use crate::http::response::{Response, ResponseBody, ResponseHeaders, ResponseStatus, StatusLine};
+use crate::settings::Settings;
 use anyhow::Result;
 use log::error;
+use serde::de::DeserializeOwned;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
 use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
 use std::str::FromStr;
-use tokio::io::{AsyncRead, AsyncReadExt};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct RequestParseError(ResponseStatus, String);
@@ -37,12 +43,36 @@ impl fmt::Display for RequestParseError {
 
 impl Error for RequestParseError {}
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum RequestMethod {
     GET,
     POST,
     PUT,
     DELETE,
+    OPTIONS,
+    HEAD,
+    PATCH,
+}
+
+impl RequestMethod {
+    /// Return all methods supported by the server
+    pub fn all() -> Vec<RequestMethod> {
+        vec![
+            RequestMethod::GET,
+            RequestMethod::POST,
+            RequestMethod::PUT,
+            RequestMethod::DELETE,
+            RequestMethod::OPTIONS,
+            RequestMethod::HEAD,
+            RequestMethod::PATCH,
+        ]
+    }
+
+    /// Return true if the method retrieves a resource, where HEAD omits the body of GET
+    pub fn is_get_or_head(&self) -> bool {
+        matches!(self, RequestMethod::GET | RequestMethod::HEAD)
+    }
 }
 
 impl FromStr for RequestMethod {
@@ -54,6 +84,9 @@ impl FromStr for RequestMethod {
             "POST" => Ok(RequestMethod::POST),
             "PUT" => Ok(RequestMethod::PUT),
             "DELETE" => Ok(RequestMethod::DELETE),
+            "OPTIONS" => Ok(RequestMethod::OPTIONS),
+            "HEAD" => Ok(RequestMethod::HEAD),
+            "PATCH" => Ok(RequestMethod::PATCH),
             _ => Err(RequestParseError::new(
                 ResponseStatus::NotImplemented,
                 "Unknown request method",
@@ -69,6 +102,9 @@ impl Display for RequestMethod {
             RequestMethod::POST => f.write_str("POST"),
             RequestMethod::PUT => f.write_str("PUT"),
             RequestMethod::DELETE => f.write_str("DELETE"),
+            RequestMethod::OPTIONS => f.write_str("OPTIONS"),
+            RequestMethod::HEAD => f.write_str("HEAD"),
+            RequestMethod::PATCH => f.write_str("PATCH"),
         }
     }
 }
@@ -76,19 +112,59 @@ impl Display for RequestMethod {
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct RequestLine {
     method: RequestMethod,
+    // request-target as the client sent
+    target: String,
+    // path normalized from target for routing
     path: String,
     version: HTTPVersion,
 }
 
 impl RequestLine {
-    pub fn new(method: RequestMethod, path: &str, version: HTTPVersion) -> RequestLine {
+    pub fn new(method: RequestMethod, target: &str, version: HTTPVersion) -> RequestLine {
         RequestLine {
             method,
-            path: path.to_string(),
+            target: target.to_string(),
+            path: Self::normalize_path(target),
             version,
         }
     }
 
+    /// Strip query and remove empty and dot segments from path of the target (RFC 3986 5.2.4)
+    fn normalize_path(target: &str) -> String {
+        let path = match target.find(['?', '#']) {
+            Some(pos) => &target[..pos],
+            None => target,
+        };
+        if !path.starts_with('/') {
+            // asterisk-form or absolute-form
+            return path.to_string();
+        }
+
+        let mut segments = vec![];
+        for segment in path.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => {
+                    segments.pop();
+                }
+                s => segments.push(s),
+            }
+        }
+        let mut normalized = format!("/{}", segments.join("/"));
+        let ends_with_dir = path.ends_with('/') || path.ends_with("/.") || path.ends_with("/..");
+        if ends_with_dir && !segments.is_empty() {
+            normalized.push('/');
+        }
+        normalized
+    }
+
+    /// Return query of the target without the leading `?`
+    fn query(&self) -> Option<&str> {
+        let start = self.target.find('?')? + 1;
+        let query = &self.target[start..];
+        Some(query.split('#').next().unwrap_or(query))
+    }
+
     pub fn parse(line: &str) -> Result<RequestLine, RequestParseError> {
         let items: Vec<_> = line.split(' ').collect();
         if items.len() != 3 {
@@ -109,10 +185,35 @@ impl RequestLine {
     }
 }
 
+/// Values of a header field in the order received,
+/// which are also kept combined into one comma-separated value (RFC 7230 3.2.2)
 #[derive(Debug, PartialEq, Eq, Clone)]
-pub struct RequestHeaders(HashMap<String, String>);
+struct HeaderValues {
+    // field name as the client sent
+    name: String,
+    combined: String,
+    values: Vec<String>,
+}
+
+impl HeaderValues {
+    fn new(name: String, value: String) -> HeaderValues {
+        HeaderValues {
+            name,
+            combined: value.clone(),
+            values: vec![value],
+        }
+    }
+
+    fn push(&mut self, value: String) {
+        self.combined.push_str(", ");
+        self.combined.push_str(&value);
+        self.values.push(value);
+    }
+}
 
-type RequestHeadersIter<'a, K, V> = std::collections::hash_map::Iter<'a, K, V>;
+/// Request header fields, whose names are case-insensitive (RFC 7230 3.2)
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RequestHeaders(HashMap<String, HeaderValues>);
 
 impl RequestHeaders {
     pub fn new() -> RequestHeaders {
@@ -123,16 +224,42 @@ impl RequestHeaders {
     pub fn from<const N: usize>(
         arr: [(impl Into<String>, impl Into<String>); N],
     ) -> RequestHeaders {
-        let mut headers = HashMap::new();
+        let mut headers = RequestHeaders::new();
         for (k, v) in arr {
             headers.insert(k.into(), v.into());
         }
-        RequestHeaders(headers)
+        headers
     }
 
+    /// Return the value, which is comma-joined if the field appears more than once
     #[allow(dead_code)]
     pub fn get_raw(&self, key: &str) -> Option<&str> {
-        self.0.get(key).map(|x| x.as_str())
+        self.0
+            .get(&key.to_ascii_lowercase())
+            .map(|x| x.combined.as_str())
+    }
+
+    /// Return all values of the field in the order received
+    #[allow(dead_code)]
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.0
+            .get(&key.to_ascii_lowercase())
+            .map(|x| x.values.iter().map(|v| v.as_str()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Return header value as integer, or 400 error if it's not a number
+    pub fn get_int(&self, key: &str) -> Result<Option<u64>, RequestParseError> {
+        self.get_raw(key)
+            .map(|s| {
+                s.parse::<u64>().map_err(|_| {
+                    RequestParseError::new(
+                        ResponseStatus::BadRequest,
+                        &format!("Illegal {} header", key),
+                    )
+                })
+            })
+            .transpose()
     }
 
     #[allow(dead_code)]
@@ -143,24 +270,41 @@ impl RequestHeaders {
         self.get_raw(key.name()).and_then(|s| key.parse(s))
     }
 
+    /// Append the value to ones of the field
+    #[allow(dead_code)]
+    pub fn insert(&mut self, key: String, value: String) {
+        match self.0.get_mut(&key.to_ascii_lowercase()) {
+            Some(values) => values.push(value),
+            None => {
+                self.0
+                    .insert(key.to_ascii_lowercase(), HeaderValues::new(key, value));
+            }
+        }
+    }
+
+    /// Replace all values of the field and return the previous comma-joined value
     #[allow(dead_code)]
-    pub fn insert(&mut self, key: String, value: String) -> Option<String> {
-        self.0.insert(key, value)
+    pub fn set(&mut self, key: String, value: String) -> Option<String> {
+        self.0
+            .insert(key.to_ascii_lowercase(), HeaderValues::new(key, value))
+            .map(|x| x.combined)
     }
 
     #[allow(dead_code)]
     pub fn remove(&mut self, key: &str) -> Option<String> {
-        self.0.remove(key)
+        self.0.remove(&key.to_ascii_lowercase()).map(|x| x.combined)
     }
 
+    /// Return the number of distinct fields
     #[allow(dead_code)]
     pub fn len(&self) -> usize {
         self.0.len()
     }
 
+    /// Iterate fields with their comma-joined values. Names are as the client sent.
     #[allow(dead_code)]
-    pub fn iter(&self) -> RequestHeadersIter<'_, String, String> {
-        self.0.iter()
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.0.values().map(|v| (&v.name, &v.combined))
     }
 
     pub fn parse(lines: &[&str]) -> Result<RequestHeaders, RequestParseError> {
@@ -198,6 +342,16 @@ impl RequestHeaders {
     }
 }
 
+impl FromIterator<(String, String)> for RequestHeaders {
+    fn from_iter<I: IntoIterator<Item = (String, String)>>(iter: I) -> Self {
+        let mut headers = RequestHeaders::new();
+        for (k, v) in iter {
+            headers.insert(k, v);
+        }
+        headers
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct RequestBody(Vec<u8>);
 
@@ -210,6 +364,35 @@ impl RequestBody {
     pub fn parse<T: BodyParser>(&self) -> Result<T> {
         T::parse(&self.0)
     }
+
+    /// Parse the body as application/x-www-form-urlencoded in the same way as the query,
+    /// or return 400 error if it's not UTF-8
+    #[allow(dead_code)]
+    pub fn parse_form(&self) -> Result<HashMap<String, String>, RequestParseError> {
+        let s = std::str::from_utf8(&self.0)
+            .map_err(|_| RequestParseError::new(ResponseStatus::BadRequest, "Illegal form body"))?;
+        Ok(parse_urlencoded(s))
+    }
+
+    /// Split the body into parts of multipart/form-data with the boundary in `content_type`
+    #[allow(dead_code)]
+    pub fn parse_multipart(
+        &self,
+        content_type: &str,
+    ) -> Result<Vec<MultipartPart>, RequestParseError> {
+        multipart::parse(&self.0, content_type)
+    }
+
+    /// Deserialize the body as JSON, or return 400 error if it's malformed
+    #[allow(dead_code)]
+    pub fn parse_json<T: DeserializeOwned>(&self) -> Result<T, RequestParseError> {
+        serde_json::from_slice(&self.0).map_err(|err| {
+            RequestParseError::new(
+                ResponseStatus::BadRequest,
+                &format!("Illegal JSON body: {}", err),
+            )
+        })
+    }
 }
 
 pub trait BodyParser: Sized {
@@ -224,11 +407,106 @@ impl BodyParser for String {
     }
 }
 
+/// Limits applied while parsing a request
+#[derive(Debug, Clone)]
+pub struct RequestParseLimits {
+    /// Maximum number of bytes scanned for CRLF across all reads of a request
+    max_scanned_bytes: usize,
+    /// Maximum length of body declared in Content-Length
+    max_body_bytes: Option<usize>,
+    /// Maximum length of body decoded from Content-Encoding, which is also limited by
+    /// `max_body_bytes`
+    max_decoded_body_bytes: Option<usize>,
+    /// Maximum length of request line and headers including CRLF
+    max_header_bytes: Option<usize>,
+    /// Maximum duration to receive request line and headers
+    header_read_timeout: Option<Duration>,
+}
+
+impl RequestParseLimits {
+    pub fn new(max_scanned_bytes: usize) -> RequestParseLimits {
+        RequestParseLimits {
+            max_scanned_bytes,
+            max_body_bytes: None,
+            max_decoded_body_bytes: None,
+            max_header_bytes: None,
+            header_read_timeout: None,
+        }
+    }
+
+    pub fn with_header_read_timeout(
+        self,
+        header_read_timeout: Option<Duration>,
+    ) -> RequestParseLimits {
+        RequestParseLimits {
+            header_read_timeout,
+            ..self
+        }
+    }
+
+    pub fn with_max_header_bytes(self, max_header_bytes: Option<usize>) -> RequestParseLimits {
+        RequestParseLimits {
+            max_header_bytes,
+            ..self
+        }
+    }
+
+    pub fn with_max_body_bytes(self, max_body_bytes: Option<usize>) -> RequestParseLimits {
+        RequestParseLimits {
+            max_body_bytes,
+            ..self
+        }
+    }
+
+    pub fn with_max_decoded_body_bytes(
+        self,
+        max_decoded_body_bytes: Option<usize>,
+    ) -> RequestParseLimits {
+        RequestParseLimits {
+            max_decoded_body_bytes,
+            ..self
+        }
+    }
+
+    /// Return the limit of body decoded from Content-Encoding
+    fn decoded_body_limit(&self) -> Option<usize> {
+        match (self.max_body_bytes, self.max_decoded_body_bytes) {
+            (Some(body), Some(decoded)) => Some(body.min(decoded)),
+            (body, decoded) => body.or(decoded),
+        }
+    }
+
+    pub fn from_settings(settings: &Settings) -> RequestParseLimits {
+        let http = settings.http();
+        let max_request_bytes = http.max_request_bytes().to_bytes() as usize;
+        RequestParseLimits::new(max_request_bytes.saturating_mul(*http.max_scan_factor()))
+            .with_max_body_bytes(
+                http.max_body_bytes()
+                    .as_ref()
+                    .map(|x| x.to_bytes() as usize),
+            )
+            .with_max_decoded_body_bytes(Some(http.max_decoded_body_bytes().to_bytes() as usize))
+            .with_max_header_bytes(Some(http.max_header_bytes().to_bytes() as usize))
+            .with_header_read_timeout(Some(*http.header_read_timeout()))
+    }
+}
+
+impl Default for RequestParseLimits {
+    fn default() -> Self {
+        RequestParseLimits::from_settings(&Settings::default())
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Request {
     request_line: RequestLine,
     headers: RequestHeaders,
     body: RequestBody,
+    // addresses of the connection, which are set by the server
+    remote_addr: Option<SocketAddr>,
+    local_addr: Option<SocketAddr>,
+    // headers which handlers add to the response, e.g. set by handlers wrapping them
+    response_headers: Vec<(String, String)>,
 }
 
 impl Request {
@@ -237,19 +515,91 @@ impl Request {
             request_line,
             headers,
             body,
+            remote_addr: None,
+            local_addr: None,
+            response_headers: vec![],
         }
     }
 
+    /// Set addresses of the connection which the request is received from
+    pub fn set_addrs(&mut self, remote_addr: SocketAddr, local_addr: Option<SocketAddr>) {
+        self.remote_addr = Some(remote_addr);
+        self.local_addr = local_addr;
+    }
+
+    /// Return address of the client
+    #[allow(dead_code)]
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    /// Return address of the server which accepted the connection
+    #[allow(dead_code)]
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
     #[allow(dead_code)]
     pub fn get_method(&self) -> &RequestMethod {
         &self.request_line.method
     }
 
+    #[allow(dead_code)]
+    pub fn get_version(&self) -> &HTTPVersion {
+        &self.request_line.version
+    }
+
+    /// Return path normalized for routing, which doesn't contain query
     #[allow(dead_code)]
     pub fn get_path(&self) -> &str {
         &self.request_line.path
     }
 
+    /// Return path normalized for routing with `%XX` sequences decoded,
+    /// or 400 error if a sequence is malformed or the decoded path is not UTF-8.
+    /// Decoded `/` and dot segments are not normalized.
+    #[allow(dead_code)]
+    pub fn get_decoded_path(&self) -> Result<String, RequestParseError> {
+        let illegal_path = || {
+            RequestParseError::new(
+                ResponseStatus::BadRequest,
+                &format!("Illegal path: {}", self.get_path()),
+            )
+        };
+        let bytes = percent_decode_path(self.get_path()).ok_or_else(illegal_path)?;
+        String::from_utf8(bytes).map_err(|_| illegal_path())
+    }
+
+    /// Return request-target as the client sent
+    #[allow(dead_code)]
+    pub fn raw_target(&self) -> &str {
+        &self.request_line.target
+    }
+
+    /// Return parameters in the query of the target, whose keys and values are percent-decoded.
+    /// A key without `=` has an empty value, and the last one wins for repeated keys.
+    #[allow(dead_code)]
+    pub fn get_query(&self) -> HashMap<String, String> {
+        let query = match self.request_line.query() {
+            Some(query) => query,
+            None => return HashMap::new(),
+        };
+        parse_urlencoded(query)
+    }
+
+    /// Return cookies sent in Cookie headers.
+    /// The first one wins for repeated names since it's the most specific (RFC 6265 5.4).
+    #[allow(dead_code)]
+    pub fn get_cookies(&self) -> HashMap<String, String> {
+        let mut cookies = HashMap::new();
+        for value in self.headers.get_all(headers::COOKIE.name()) {
+            for (name, value) in headers::COOKIE.parse(value).unwrap_or_default() {
+                cookies.entry(name).or_insert(value);
+            }
+        }
+        cookies
+    }
+
     #[allow(dead_code)]
     pub fn get_headers(&self) -> &RequestHeaders {
         &self.headers
@@ -260,9 +610,16 @@ impl Request {
         self.headers.get_raw(key)
     }
 
+    /// Return header value as integer, or 400 error if it's not a number
+    #[allow(dead_code)]
+    pub fn get_header_int(&self, key: &str) -> Result<Option<u64>, RequestParseError> {
+        self.headers.get_int(key)
+    }
+
+    /// Replace all values of the header and return the previous comma-joined value
     #[allow(dead_code)]
     pub fn insert_header(&mut self, key: String, value: String) -> Option<String> {
-        self.headers.insert(key, value)
+        self.headers.set(key, value)
     }
 
     #[allow(dead_code)]
@@ -270,155 +627,496 @@ impl Request {
         self.headers.remove(key)
     }
 
+    /// Replace the method, e.g. to ask whether a handler accepts the request with another method
+    #[allow(dead_code)]
+    pub fn set_method(&mut self, method: RequestMethod) {
+        self.request_line.method = method;
+    }
+
+    /// Ask the handler of the request to add the header to its response
+    #[allow(dead_code)]
+    pub fn add_response_header(&mut self, key: &str, value: &str) {
+        self.response_headers
+            .push((key.to_string(), value.to_string()));
+    }
+
+    #[allow(dead_code)]
+    pub fn get_response_headers(&self) -> &[(String, String)] {
+        &self.response_headers
+    }
+
     /// Return header value converted to lower cases
     #[allow(dead_code)]
     pub fn get_header_lc(&self, key: &str) -> Option<String> {
         self.headers.get_raw(key).map(|s| s.to_ascii_lowercase())
     }
 
+    /// Return true if the request asks for upgrading to WebSocket
+    pub fn is_websocket_upgrade(&self) -> bool {
+        matches!(self.get_header_lc("Upgrade").as_deref(), Some("websocket"))
+    }
+
     #[allow(dead_code)]
     pub fn get_body(&self) -> &[u8] {
         &self.body.0
     }
 
+    /// Encode the request as a client sends it, with the request-target as it is
+    pub fn encode(&self) -> Vec<u8> {
+        let line = &self.request_line;
+        let mut res = format!("{} {} {}\r\n", line.method, line.target, line.version);
+        for (k, v) in self.headers.iter() {
+            res.push_str(&format!("{}: {}\r\n", k, v));
+        }
+        res.push_str("\r\n");
+        let mut res = res.into_bytes();
+        res.extend(&self.body.0);
+        res
+    }
+
+    /// Deserialize the body as JSON.
+    /// 415 error is returned unless Content-Type is `application/json` or `+json` suffixed one,
+    /// and 400 error if the body is malformed.
+    #[allow(dead_code)]
+    pub fn parse_json<T: DeserializeOwned>(&self) -> Result<T, RequestParseError> {
+        let media_type = self
+            .get_header("Content-Type")
+            .and_then(|value| value.split(';').next())
+            .map(|media_type| media_type.trim().to_ascii_lowercase());
+        match media_type {
+            Some(media_type)
+                if media_type == "application/json" || media_type.ends_with("+json") =>
+            {
+                self.body.parse_json()
+            }
+            _ => Err(RequestParseError::new(
+                ResponseStatus::UnsupportedMediaType,
+                "Content-Type must be application/json",
+            )),
+        }
+    }
+
+    #[allow(dead_code)]
     pub async fn parse<T: AsyncRead + Unpin>(reader: &mut T) -> Result<Self, RequestParseError> {
-        let mut metadata_reader = reader::RequestMetadataReader::new(reader);
+        Self::parse_with_limits(reader, &RequestParseLimits::default()).await
+    }
 
-        let request_line = RequestLine::parse(&metadata_reader.read().await.map_err(|err| {
-            RequestParseError::new(
-                ResponseStatus::InternalServerError,
-                &format!("Failed to read request line: {:?}", err),
-            )
-        })?)?;
+    #[allow(dead_code)]
+    pub async fn parse_with_limits<T: AsyncRead + Unpin>(
+        reader: &mut T,
+        limits: &RequestParseLimits,
+    ) -> Result<Self, RequestParseError> {
+        Self::parse_with_interim(reader, &mut tokio::io::sink(), limits).await
+    }
 
-        let mut lines = vec![];
-        loop {
-            let line = metadata_reader.read().await.map_err(|err| {
+    /// Parse request, writing an interim response (100 Continue) to `writer`
+    /// if the client waits for it before sending body (RFC 7231 5.1.1)
+    pub async fn parse_with_interim<T: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+        reader: &mut T,
+        writer: &mut W,
+        limits: &RequestParseLimits,
+    ) -> Result<Self, RequestParseError> {
+        let (request, _) = Self::parse_with_rest(reader, writer, limits).await?;
+        Ok(request)
+    }
+
+    /// Parse request like `parse_with_interim` and return bytes read beyond the request as well,
+    /// which are the head of the next request on a persistent connection
+    pub async fn parse_with_rest<T: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+        reader: &mut T,
+        writer: &mut W,
+        limits: &RequestParseLimits,
+    ) -> Result<(Self, Vec<u8>), RequestParseError> {
+        // keep RequestParseError raised in the reader as it is
+        fn read_error(err: anyhow::Error, target: &str) -> RequestParseError {
+            err.downcast::<RequestParseError>().unwrap_or_else(|err| {
                 RequestParseError::new(
                     ResponseStatus::InternalServerError,
-                    &format!("Failed to read header line: {:?}", err),
+                    &format!("Failed to read {}: {:?}", target, err),
                 )
-            })?;
-            if line.is_empty() {
-                break;
-            }
-            lines.push(line);
+            })
         }
-        let request_headers =
-            RequestHeaders::parse(&lines.iter().map(|x| x.as_str()).collect::<Vec<_>>()[..])?;
-        let content_length = {
-            let cl = request_headers.get_raw("Content-Length").unwrap_or("0");
-            cl.parse::<usize>().map_err(|_| {
-                RequestParseError::new(ResponseStatus::BadRequest, "Illegal Content-Length")
-            })?
-        };
 
-        let mut body_reader = metadata_reader.into_body_reader(content_length);
-        let request_body = RequestBody::new(
-            body_reader
-                .read()
+        let mut metadata_reader = reader::RequestMetadataReader::new(
+            reader,
+            limits.max_scanned_bytes,
+            limits.max_header_bytes,
+        );
+
+        let reading_metadata = async {
+            // empty lines before the request line are ignored (RFC 7230 3.5)
+            let request_line = loop {
+                let line = metadata_reader
+                    .read()
+                    .await
+                    .map_err(|err| read_error(err, "request line"))?;
+                if !line.is_empty() {
+                    break RequestLine::parse(&line)?;
+                }
+            };
+
+            let mut lines = vec![];
+            loop {
+                let line = metadata_reader
+                    .read()
+                    .await
+                    .map_err(|err| read_error(err, "header line"))?;
+                if line.is_empty() {
+                    break;
+                }
+                lines.push(line);
+            }
+            Ok::<_, RequestParseError>((request_line, lines))
+        };
+        let (request_line, lines) = match limits.header_read_timeout {
+            Some(duration) => tokio::time::timeout(duration, reading_metadata)
                 .await
-                .map_err(|err| {
-                    error!("Failed to read header line: {:?}", err);
+                .map_err(|_| {
                     RequestParseError::new(
-                        ResponseStatus::BadRequest,
-                        "Failed to read request body",
+                        ResponseStatus::RequestTimeout,
+                        "Timed out reading request headers",
                     )
-                })?
-                .to_vec(),
-        );
+                })??,
+            None => reading_metadata.await?,
+        };
+        let mut request_headers =
+            RequestHeaders::parse(&lines.iter().map(|x| x.as_str()).collect::<Vec<_>>()[..])?;
+        let chunked = match request_headers.get_raw("Transfer-Encoding") {
+            Some(coding) if coding.eq_ignore_ascii_case("chunked") => {
+                // the message may be an attempt of request smuggling (RFC 7230 3.3.3)
+                if request_headers.get_raw("Content-Length").is_some() {
+                    return Err(RequestParseError::new(
+                        ResponseStatus::BadRequest,
+                        "Both Transfer-Encoding and Content-Length are specified",
+                    ));
+                }
+                true
+            }
+            Some(_) => {
+                return Err(RequestParseError::new(
+                    ResponseStatus::NotImplemented,
+                    "Unsupported transfer coding",
+                ));
+            }
+            None => false,
+        };
+        let content_length = request_headers.get_int("Content-Length")?.unwrap_or(0) as usize;
+
+        let expects_continue = match request_headers.get_raw("Expect") {
+            Some(expect) if expect.eq_ignore_ascii_case("100-continue") => true,
+            Some(_) => {
+                return Err(RequestParseError::new(
+                    ResponseStatus::ExpectationFailed,
+                    "Unsupported expectation",
+                ));
+            }
+            None => false,
+        };
+
+        // no body is indicated, so don't touch the reader any more
+        if !chunked && content_length == 0 {
+            return Ok((
+                Request::new(request_line, request_headers, RequestBody::new(vec![])),
+                metadata_reader.into_rest(),
+            ));
+        }
+
+        // reject before the client sends body if it waits for 100 Continue
+        if matches!(limits.max_body_bytes, Some(max) if content_length > max) {
+            return Err(RequestParseError::new(
+                ResponseStatus::PayloadTooLarge,
+                "Too big request body",
+            ));
+        }
+
+        if expects_continue && request_line.version == HTTPVersion::V1_1 {
+            let response = Response::new(
+                StatusLine::new(HTTPVersion::V1_1, ResponseStatus::Continue),
+                ResponseHeaders::empty(),
+                ResponseBody::new(vec![]),
+            );
+            response.write_to(writer).await.map_err(|err| {
+                RequestParseError::new(
+                    ResponseStatus::InternalServerError,
+                    &format!("Failed to write 100 Continue: {:?}", err),
+                )
+            })?;
+        }
+
+        let body_error = |err: anyhow::Error| {
+            err.downcast::<RequestParseError>().unwrap_or_else(|err| {
+                error!("Failed to read request body: {:?}", err);
+                RequestParseError::new(ResponseStatus::BadRequest, "Failed to read request body")
+            })
+        };
+        let (mut body, rest) = if chunked {
+            let body = metadata_reader
+                .read_chunked(limits.max_body_bytes)
+                .await
+                .map_err(body_error)?;
+            // pass body to handlers as if it's sent with Content-Length
+            request_headers.remove("Transfer-Encoding");
+            request_headers.set("Content-Length".to_string(), body.len().to_string());
+            (body, metadata_reader.into_rest())
+        } else {
+            let mut body_reader = metadata_reader.into_body_reader(content_length);
+            let body = body_reader.read().await.map_err(body_error)?.to_vec();
+            (body, body_reader.into_rest())
+        };
+
+        // pass decoded body to handlers as if it's sent without Content-Encoding
+        if let Some(content_encoding) = request_headers.remove("Content-Encoding") {
+            let limit = limits.decoded_body_limit();
+            body = compression::decompress(&body, &content_encoding, limit)?;
+            request_headers.set("Content-Length".to_string(), body.len().to_string());
+        }
 
-        Ok(Request::new(request_line, request_headers, request_body))
+        Ok((
+            Request::new(request_line, request_headers, RequestBody::new(body)),
+            rest,
+        ))
     }
 }
 
 mod reader {
     use super::*;
     use anyhow::bail;
+    use tokio::io::{AsyncBufReadExt, BufReader};
 
     pub struct RequestMetadataReader<'a, T: AsyncRead> {
-        reader: &'a mut T,
-        buf: Vec<u8>,
+        // buffered so that lines are found without a read per a few bytes
+        reader: BufReader<&'a mut T>,
+        // total bytes scanned to find CRLF, which guards against pathological inputs
+        scanned_bytes: usize,
+        max_scanned_bytes: usize,
+        // total bytes of lines read, which are request line and headers until body is read
+        line_bytes: usize,
+        max_line_bytes: Option<usize>,
     }
 
     impl<'a, T: AsyncRead + Unpin> RequestMetadataReader<'a, T> {
-        pub fn new(reader: &mut T) -> RequestMetadataReader<T> {
+        pub fn new(
+            reader: &mut T,
+            max_scanned_bytes: usize,
+            max_header_bytes: Option<usize>,
+        ) -> RequestMetadataReader<'_, T> {
             RequestMetadataReader {
-                reader,
-                buf: Vec::new(),
+                reader: BufReader::new(reader),
+                scanned_bytes: 0,
+                max_scanned_bytes,
+                line_bytes: 0,
+                max_line_bytes: max_header_bytes,
             }
         }
 
         pub async fn read(&mut self) -> Result<String> {
+            let mut line = vec![];
             loop {
-                if !self.buf.is_empty() {
-                    let mut pos_crlf = None;
-                    for i in 0..(self.buf.len() - 1) {
-                        if self.buf[i] == b'\r' && self.buf[i + 1] == b'\n' {
-                            pos_crlf = Some(i);
-                            break;
-                        }
-                    }
-                    if let Some(pos_crlf) = pos_crlf {
-                        let line = String::from_utf8_lossy(
-                            &self.buf.drain(..pos_crlf).collect::<Vec<_>>(),
-                        )
-                        .to_string();
-                        self.buf.drain(..2);
-                        return Ok(line);
-                    }
-                }
-
-                let mut buf = Vec::new();
-                let n = self.reader.read_buf(&mut buf).await?;
-                if n == 0 {
+                let available = self.reader.fill_buf().await?;
+                if available.is_empty() {
                     // this should be the case when the client disconnected
                     bail!("client disconnected");
                 }
-                self.buf.extend(buf);
+                // bytes after the line end may be body, which are left in the buffer
+                let (used, found_lf) = match available.iter().position(|b| *b == b'\n') {
+                    Some(pos) => (pos + 1, true),
+                    None => (available.len(), false),
+                };
+                line.extend_from_slice(&available[..used]);
+                self.reader.consume(used);
+
+                self.scanned_bytes += used;
+                if self.scanned_bytes > self.max_scanned_bytes {
+                    return Err(RequestParseError::new(
+                        ResponseStatus::BadRequest,
+                        "Too many bytes scanned in request metadata",
+                    )
+                    .into());
+                }
+                // an incomplete line is counted as well, so the line never grows beyond it
+                let line_bytes = self.line_bytes + line.len();
+                if matches!(self.max_line_bytes, Some(max) if line_bytes > max) {
+                    return Err(RequestParseError::new(
+                        ResponseStatus::RequestHeaderFieldsTooLarge,
+                        "Too big request headers",
+                    )
+                    .into());
+                }
+                // lines end with CRLF, so bare LF is a part of the line
+                if found_lf && line.ends_with(b"\r\n") {
+                    line.truncate(line.len() - 2);
+                    self.line_bytes = line_bytes;
+                    return Ok(String::from_utf8_lossy(&line).to_string());
+                }
             }
         }
 
+        /// Read body in chunked transfer coding (RFC 7230 4.1), which must not exceed `max_size`.
+        /// Chunk extensions and trailers are discarded.
+        pub async fn read_chunked(&mut self, max_size: Option<usize>) -> Result<Vec<u8>> {
+            fn malformed(line: &str) -> anyhow::Error {
+                RequestParseError::new(
+                    ResponseStatus::BadRequest,
+                    &format!("Illegal chunk header: {}", line),
+                )
+                .into()
+            }
+
+            // each line of chunk framing is limited apart from request metadata and the others,
+            // so that a body of many small chunks is bounded only by `max_size`
+            self.max_line_bytes = None;
+            let mut body = vec![];
+            loop {
+                self.scanned_bytes = 0;
+                let line = self.read().await?;
+                let size = line.split(';').next().unwrap_or("").trim();
+                if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return Err(malformed(&line));
+                }
+                let size = usize::from_str_radix(size, 16).map_err(|_| malformed(&line))?;
+                if size == 0 {
+                    break;
+                }
+                if matches!(max_size, Some(max) if body.len().saturating_add(size) > max) {
+                    return Err(RequestParseError::new(
+                        ResponseStatus::PayloadTooLarge,
+                        "Too big request body",
+                    )
+                    .into());
+                }
+                read_exactly(&mut self.reader, &mut body, size).await?;
+                let line = self.read().await?;
+                if !line.is_empty() {
+                    return Err(malformed(&line));
+                }
+            }
+            // trailers are limited as a whole like headers
+            self.scanned_bytes = 0;
+            while !self.read().await?.is_empty() {}
+            Ok(body)
+        }
+
+        /// Return bytes read but not consumed yet
+        pub fn into_rest(self) -> Vec<u8> {
+            self.reader.buffer().to_vec()
+        }
+
         pub fn into_body_reader(self, length: usize) -> RequestBodyReader<'a, T> {
-            RequestBodyReader::new(self.reader, self.buf, length)
+            RequestBodyReader::new(self.reader, length)
         }
     }
 
+    /// Append `length` bytes from `reader` to `buf`, which grows as bytes arrive
+    /// rather than by the length claimed by the client
+    async fn read_exactly<R: AsyncRead + Unpin>(
+        reader: &mut R,
+        buf: &mut Vec<u8>,
+        length: usize,
+    ) -> Result<()> {
+        let n = reader.take(length as u64).read_to_end(buf).await?;
+        if n < length {
+            // this should be the case when the client disconnected
+            bail!("client disconnected");
+        }
+        Ok(())
+    }
+
     pub struct RequestBodyReader<'a, T: AsyncRead> {
-        reader: &'a mut T,
+        reader: BufReader<&'a mut T>,
         buf: Vec<u8>,
         content_length: usize,
     }
 
-    impl<T: AsyncRead + Unpin> RequestBodyReader<'_, T> {
+    impl<'a, T: AsyncRead + Unpin> RequestBodyReader<'a, T> {
         pub fn new(
-            reader: &mut T,
-            buf: Vec<u8>,
+            reader: BufReader<&'a mut T>,
             content_length: usize,
-        ) -> RequestBodyReader<'_, T> {
+        ) -> RequestBodyReader<'a, T> {
             RequestBodyReader {
                 reader,
-                buf,
+                buf: Vec::new(),
                 content_length,
             }
         }
 
         pub async fn read(&mut self) -> Result<&[u8]> {
-            loop {
-                if self.buf.len() >= self.content_length {
-                    return Ok(&self.buf[..self.content_length]);
-                }
+            if self.buf.len() < self.content_length {
+                let remaining = self.content_length - self.buf.len();
+                read_exactly(&mut self.reader, &mut self.buf, remaining).await?;
+            }
+            Ok(&self.buf)
+        }
 
-                let mut buf = Vec::new();
-                let n = self.reader.read_buf(&mut buf).await?;
-                if n == 0 {
-                    // this should be the case when the client disconnected
-                    bail!("client disconnected");
+        /// Return bytes read beyond the body
+        pub fn into_rest(self) -> Vec<u8> {
+            self.reader.buffer().to_vec()
+        }
+    }
+}
+
+/// Return byte of `%XX` sequence at the head of `bytes`
+fn decode_hex(bytes: &[u8]) -> Option<u8> {
+    bytes
+        .get(1..3)
+        .filter(|hex| bytes[0] == b'%' && hex.iter().all(u8::is_ascii_hexdigit))
+        .and_then(|hex| std::str::from_utf8(hex).ok())
+        .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+}
+
+/// Decode `%XX` sequences of a path, or return None if a sequence is malformed
+fn percent_decode_path(s: &str) -> Option<Vec<u8>> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            decoded.push(decode_hex(&bytes[i..])?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Some(decoded)
+}
+
+/// Parse `key=value` pairs separated by `&` like application/x-www-form-urlencoded,
+/// whose keys and values are percent-decoded.
+/// A key without `=` has an empty value, and the last one wins for repeated keys.
+fn parse_urlencoded(s: &str) -> HashMap<String, String> {
+    s.split('&')
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+/// Decode `%XX` sequences and `+` as a space like application/x-www-form-urlencoded.
+/// Malformed sequences are kept as they are.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let hex = decode_hex(&bytes[i..]);
+                match hex {
+                    Some(b) => {
+                        decoded.push(b);
+                        i += 2;
+                    }
+                    None => decoded.push(b'%'),
                 }
-                self.buf.extend(buf);
             }
+            b => decoded.push(b),
         }
+        i += 1;
     }
+    String::from_utf8_lossy(&decoded).to_string()
 }
 
 #[cfg(test)]
@@ -437,17 +1135,140 @@ mod tests {
     }
 
     #[test]
-    fn test_parse_request_line_with_unsupported_method() {
-        let str = "PATCH / HTTP/1.1";
-        let actual = RequestLine::parse(str);
-        assert!(matches!(
-            actual,
-            Err(RequestParseError(ResponseStatus::NotImplemented, _))
-        ));
+    fn test_parse_request_line_with_http_1_0() {
+        let actual = RequestLine::parse("GET / HTTP/1.0").unwrap();
+        let expected = RequestLine::new(RequestMethod::GET, "/", HTTPVersion::V1_0);
+        assert_eq!(actual, expected);
+        assert!(RequestLine::parse("GET / HTTP/2.0").is_err());
     }
 
     #[test]
-    fn test_parse_request_headers() {
+    fn test_request_keeps_raw_target_and_normalized_path() {
+        let request = Request::new(
+            RequestLine::parse("GET /a//b/../c?x=1 HTTP/1.1").unwrap(),
+            RequestHeaders::new(),
+            RequestBody::new(vec![]),
+        );
+        assert_eq!(request.raw_target(), "/a//b/../c?x=1");
+        assert_eq!(request.get_path(), "/a/c");
+    }
+
+    #[test]
+    fn test_get_query() {
+        let create_request = |target: &str| {
+            Request::new(
+                RequestLine::new(RequestMethod::GET, target, HTTPVersion::V1_1),
+                RequestHeaders::new(),
+                RequestBody::new(vec![]),
+            )
+        };
+
+        let request = create_request("/search?q=hello%20world&page=2");
+        assert_eq!(request.get_path(), "/search");
+        assert_eq!(
+            request.get_query(),
+            HashMap::from([
+                ("q".to_string(), "hello world".to_string()),
+                ("page".to_string(), "2".to_string()),
+            ])
+        );
+
+        let request = create_request("/search?a=1&a=2&empty=&flag&&%E3%81%82=%zz&plus=%+1+#top");
+        assert_eq!(
+            request.get_query(),
+            HashMap::from([
+                ("a".to_string(), "2".to_string()),
+                ("empty".to_string(), "".to_string()),
+                ("flag".to_string(), "".to_string()),
+                ("あ".to_string(), "%zz".to_string()),
+                ("plus".to_string(), "% 1 ".to_string()),
+            ])
+        );
+
+        let request = create_request("/search");
+        assert_eq!(request.get_path(), "/search");
+        assert!(request.get_query().is_empty());
+    }
+
+    #[test]
+    fn test_get_decoded_path() {
+        let create_request = |target: &str| {
+            Request::new(
+                RequestLine::new(RequestMethod::GET, target, HTTPVersion::V1_1),
+                RequestHeaders::new(),
+                RequestBody::new(vec![]),
+            )
+        };
+
+        let request = create_request("/files/my%20file.txt?q=%20");
+        assert_eq!(request.get_decoded_path().unwrap(), "/files/my file.txt");
+        assert_eq!(request.get_path(), "/files/my%20file.txt");
+        assert_eq!(request.raw_target(), "/files/my%20file.txt?q=%20");
+
+        let request = create_request("/a%2Fb/%E3%81%82+");
+        assert_eq!(request.get_decoded_path().unwrap(), "/a/b/あ+");
+
+        for target in ["/files/%G1", "/files/%2", "/files/%FF"] {
+            let err = create_request(target).get_decoded_path().unwrap_err();
+            assert_eq!(err.get_status(), &ResponseStatus::BadRequest);
+        }
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(RequestLine::normalize_path("/"), "/");
+        assert_eq!(RequestLine::normalize_path("/a/./b/"), "/a/b/");
+        assert_eq!(RequestLine::normalize_path("/../a/.."), "/");
+        assert_eq!(RequestLine::normalize_path("*"), "*");
+    }
+
+    #[test]
+    fn test_parse_request_line_with_patch() {
+        let actual = RequestLine::parse("PATCH /resource HTTP/1.1").unwrap();
+        assert_eq!(actual.method, RequestMethod::PATCH);
+        assert_eq!(actual.method.to_string(), "PATCH");
+        assert_eq!(actual.path, "/resource");
+    }
+
+    #[test]
+    fn test_parse_request_line_with_asterisk() {
+        let actual = RequestLine::parse("OPTIONS * HTTP/1.1").unwrap();
+        let expected = RequestLine::new(RequestMethod::OPTIONS, "*", HTTPVersion::V1_1);
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_parse_request_line_with_unsupported_method() {
+        let str = "TRACE / HTTP/1.1";
+        let actual = RequestLine::parse(str);
+        assert!(matches!(
+            actual,
+            Err(RequestParseError(ResponseStatus::NotImplemented, _))
+        ));
+    }
+
+    #[test]
+    fn test_request_headers_from_array() {
+        let actual = RequestHeaders::from([("Host", "localhost"), ("Content-Length", "0")]);
+        assert_eq!(actual.len(), 2);
+        assert_eq!(actual.get_raw("Host"), Some("localhost"));
+        assert_eq!(actual.get_raw("Content-Length"), Some("0"));
+    }
+
+    #[test]
+    fn test_request_headers_from_iterator() {
+        let actual: RequestHeaders = vec![("Host", "localhost"), ("Content-Length", "0")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        assert_eq!(
+            actual,
+            RequestHeaders::from([("Host", "localhost"), ("Content-Length", "0")])
+        );
+    }
+
+    #[test]
+    fn test_parse_request_headers() {
         let ss = [
             "Content-Type: text/plain",
             "Content-Length: 0",
@@ -463,6 +1284,94 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_websocket_extensions() {
+        let headers = RequestHeaders::from([(
+            "Sec-WebSocket-Extensions",
+            "permessage-deflate; client_max_window_bits, foo; bar=\"baz\"; qux=1",
+        )]);
+        let actual = headers.get(&headers::SEC_WEBSOCKET_EXTENSIONS).unwrap();
+        assert_eq!(
+            actual,
+            vec![
+                headers::WebSocketExtension {
+                    name: "permessage-deflate".to_string(),
+                    params: vec![("client_max_window_bits".to_string(), None)],
+                },
+                headers::WebSocketExtension {
+                    name: "foo".to_string(),
+                    params: vec![
+                        ("bar".to_string(), Some("baz".to_string())),
+                        ("qux".to_string(), Some("1".to_string())),
+                    ],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_get_header_int() {
+        let request = Request::new(
+            RequestLine::new(RequestMethod::GET, "/", HTTPVersion::V1_1),
+            RequestHeaders::from([("Content-Length", "10"), ("X-Count", "ten")]),
+            RequestBody::new(vec![]),
+        );
+        assert_eq!(request.get_header_int("Content-Length"), Ok(Some(10)));
+        assert!(matches!(
+            request.get_header_int("X-Count"),
+            Err(RequestParseError(ResponseStatus::BadRequest, _))
+        ));
+        assert_eq!(request.get_header_int("X-Missing"), Ok(None));
+    }
+
+    #[test]
+    fn test_parse_repeated_request_headers() {
+        let ss = [
+            "Accept: text/html",
+            "Content-Length: 0",
+            "Accept: application/json;q=0.9",
+        ];
+        let actual = RequestHeaders::parse(&ss).unwrap();
+        assert_eq!(actual.len(), 2);
+        assert_eq!(
+            actual.get_all("Accept"),
+            vec!["text/html", "application/json;q=0.9"]
+        );
+        assert_eq!(
+            actual.get_raw("Accept"),
+            Some("text/html, application/json;q=0.9")
+        );
+        assert_eq!(actual.get_all("Content-Length"), vec!["0"]);
+        assert!(actual.get_all("Host").is_empty());
+    }
+
+    #[test]
+    fn test_request_header_names_are_case_insensitive() {
+        let ss = ["content-length: 5", "HOST: localhost", "Host: example.com"];
+        let mut actual = RequestHeaders::parse(&ss).unwrap();
+        assert_eq!(actual.get_int("Content-Length"), Ok(Some(5)));
+        assert_eq!(actual.get_raw("host"), Some("localhost, example.com"));
+        assert_eq!(actual.len(), 2);
+        // the name first sent is kept
+        let names: Vec<_> = actual.iter().map(|(k, _)| k.as_str()).collect();
+        assert!(names.contains(&"HOST"));
+
+        assert_eq!(
+            actual.set("CONTENT-LENGTH".to_string(), "0".to_string()),
+            Some("5".to_string())
+        );
+        assert_eq!(actual.remove("Content-length"), Some("0".to_string()));
+        assert_eq!(actual.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_parse_request_with_lowercase_content_length() {
+        let raw = "POST / HTTP/1.1\r\nhost: localhost\r\ncontent-length: 5\r\n\r\nhello";
+        let request = Request::parse(&mut raw.as_bytes()).await.unwrap();
+        assert_eq!(request.get_body(), b"hello");
+        assert_eq!(request.get_header("Host"), Some("localhost"));
+    }
+
     #[test]
     fn test_parse_request_headers_with_illegal_format() {
         let ss = ["Content-Type : text/plain"];
@@ -473,6 +1382,213 @@ mod tests {
         ));
     }
 
+    /// Reader returning one byte per read, which maximizes rescans in the parser
+    struct OneByteReader(Vec<u8>, usize);
+
+    impl AsyncRead for OneByteReader {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            let pos = self.1;
+            if pos < self.0.len() {
+                buf.put_slice(&self.0[pos..(pos + 1)]);
+                self.1 += 1;
+            }
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_parse_request_read_one_byte_at_a_time() {
+        let raw = "POST /a HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello";
+        let mut reader = OneByteReader(raw.as_bytes().to_vec(), 0);
+        let actual = Request::parse(&mut reader).await.unwrap();
+
+        assert_eq!(actual.get_path(), "/a");
+        assert_eq!(actual.get_header("Host"), Some("localhost"));
+        assert_eq!(actual.get_body(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_parse_request_after_empty_lines() {
+        let raw = "\r\n\r\nGET /a HTTP/1.1\r\n\r\n";
+        let mut reader = OneByteReader(raw.as_bytes().to_vec(), 0);
+        let actual = Request::parse(&mut reader).await.unwrap();
+        assert_eq!(actual.get_path(), "/a");
+        assert!(actual.get_headers().iter().next().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_parse_request_with_chunked_body() {
+        let raw = [
+            "POST / HTTP/1.1",
+            "Host: localhost",
+            "Transfer-Encoding: chunked",
+            "",
+            "5;name=value",
+            "hello",
+            "7",
+            ", world",
+            "0",
+            "Trailer: ignored",
+            "",
+            "",
+        ]
+        .join("\r\n");
+        let request = Request::parse(&mut raw.as_bytes()).await.unwrap();
+        assert_eq!(request.get_body(), b"hello, world");
+        assert_eq!(request.get_header("Content-Length"), Some("12"));
+        assert_eq!(request.get_header("Transfer-Encoding"), None);
+    }
+
+    #[tokio::test]
+    async fn test_parse_request_with_many_small_chunks() {
+        let limits = RequestParseLimits::new(4096).with_max_body_bytes(Some(1024 * 1024));
+        let chunk = format!("64;name=value\r\n{}\r\n", "a".repeat(100));
+        let raw = format!(
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{}0\r\n\r\n",
+            chunk.repeat(10_000)
+        );
+        let request = Request::parse_with_limits(&mut raw.as_bytes(), &limits)
+            .await
+            .unwrap();
+        assert_eq!(request.get_body().len(), 1_000_000);
+
+        // each line of chunk framing is still limited
+        let raw = format!(
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5;{}\r\nhello\r\n0\r\n\r\n",
+            "a".repeat(5000)
+        );
+        let actual = Request::parse_with_limits(&mut raw.as_bytes(), &limits).await;
+        assert_eq!(
+            actual.unwrap_err().get_status(),
+            &ResponseStatus::BadRequest
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reject_malformed_chunked_body() {
+        let parse = |chunks: &[&str], limits: RequestParseLimits| {
+            let raw = format!(
+                "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{}\r\n",
+                chunks.join("\r\n")
+            );
+            async move { Request::parse_with_limits(&mut raw.as_bytes(), &limits).await }
+        };
+        let limits = RequestParseLimits::default;
+
+        let actual = parse(&["xyz", "hello", "0", ""], limits()).await;
+        assert_eq!(
+            actual.unwrap_err().get_status(),
+            &ResponseStatus::BadRequest
+        );
+        // data longer than the chunk size
+        let actual = parse(&["3", "hello", "0", ""], limits()).await;
+        assert_eq!(
+            actual.unwrap_err().get_status(),
+            &ResponseStatus::BadRequest
+        );
+        let actual = parse(
+            &["5", "hello", "0", ""],
+            limits().with_max_body_bytes(Some(4)),
+        )
+        .await;
+        assert_eq!(
+            actual.unwrap_err().get_status(),
+            &ResponseStatus::PayloadTooLarge
+        );
+
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n";
+        let actual = Request::parse(&mut raw.as_bytes()).await;
+        assert_eq!(
+            actual.unwrap_err().get_status(),
+            &ResponseStatus::NotImplemented
+        );
+        let raw = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 0\r\n\r\n";
+        let actual = Request::parse(&mut raw.as_bytes()).await;
+        assert_eq!(
+            actual.unwrap_err().get_status(),
+            &ResponseStatus::BadRequest
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reject_too_big_request_headers() {
+        let headers: String = (0..200)
+            .map(|i| format!("X-Header-{}: {}\r\n", i, "a".repeat(40)))
+            .collect();
+        let raw = format!("GET / HTTP/1.1\r\n{}\r\n", headers);
+        let actual = Request::parse(&mut raw.as_bytes()).await;
+        assert_eq!(
+            actual.unwrap_err().get_status(),
+            &ResponseStatus::RequestHeaderFieldsTooLarge
+        );
+
+        // the limit includes request line and CRLF of each line
+        let raw = "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        for (max, ok) in [(raw.len(), true), (raw.len() - 1, false)] {
+            let limits = RequestParseLimits::new(4096).with_max_header_bytes(Some(max));
+            let actual = Request::parse_with_limits(&mut raw.as_bytes(), &limits).await;
+            assert_eq!(actual.is_ok(), ok, "max_header_bytes: {}", max);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_parse_request_aborts_on_too_many_scanned_bytes() {
+        let mut reader = OneByteReader(
+            format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(100_000)).into_bytes(),
+            0,
+        );
+        let actual = Request::parse_with_limits(&mut reader, &RequestParseLimits::new(4096)).await;
+        assert!(matches!(
+            actual,
+            Err(RequestParseError(ResponseStatus::BadRequest, _))
+        ));
+        // abort long before reading the whole input, as each byte is scanned only once
+        assert!(reader.1 <= 4097);
+    }
+
+    /// Count reads reaching the inner reader
+    struct CountingReader<'a>(&'a [u8], usize);
+
+    impl AsyncRead for CountingReader<'_> {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            self.1 += 1;
+            std::pin::Pin::new(&mut self.0).poll_read(cx, buf)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_parse_request_with_buffered_reads() {
+        let headers: String = (0..20)
+            .map(|i| format!("X-Header-{}: {}\r\n", i, "a".repeat(40)))
+            .collect();
+        let raw = format!(
+            "POST /a HTTP/1.1\r\n{}Content-Length: 5\r\n\r\nhelloGET /b HTTP/1.1\r\n",
+            headers
+        );
+        let mut reader = CountingReader(raw.as_bytes(), 0);
+        let (request, rest) = Request::parse_with_rest(
+            &mut reader,
+            &mut tokio::io::sink(),
+            &RequestParseLimits::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(request.get_path(), "/a");
+        assert_eq!(request.get_headers().len(), 21);
+        assert_eq!(request.get_body(), b"hello");
+        assert_eq!(rest, b"GET /b HTTP/1.1\r\n");
+        // about 1 KB of metadata is read at once instead of a read per line or 64 bytes
+        assert_eq!(reader.1, 1);
+    }
+
     #[tokio::test]
     async fn test_parse_request_only_request_line() {
         // setup
@@ -497,6 +1613,226 @@ mod tests {
         assert_eq!(actual.headers.len(), 0)
     }
 
+    #[tokio::test]
+    async fn test_parse_bodyless_request_without_waiting_for_body() {
+        // the client keeps the connection open without sending anything after headers
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client
+            .write_all("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n".as_bytes())
+            .await
+            .unwrap();
+
+        let actual = tokio::time::timeout(
+            std::time::Duration::from_millis(100),
+            Request::parse(&mut server),
+        )
+        .await
+        .expect("parse should not wait for body")
+        .unwrap();
+
+        assert_eq!(actual.get_method(), &RequestMethod::GET);
+        assert!(actual.get_body().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reject_too_big_body_before_reading_it() {
+        // the client waits for 100 Continue without sending body
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client
+            .write_all(
+                "POST / HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 100\r\n\r\n".as_bytes(),
+            )
+            .await
+            .unwrap();
+
+        let mut interim = vec![];
+        let limits = RequestParseLimits::new(4096).with_max_body_bytes(Some(10));
+        let actual = tokio::time::timeout(
+            std::time::Duration::from_millis(100),
+            Request::parse_with_interim(&mut server, &mut interim, &limits),
+        )
+        .await
+        .expect("parse should not wait for body");
+
+        assert!(matches!(
+            actual,
+            Err(RequestParseError(ResponseStatus::PayloadTooLarge, _))
+        ));
+        assert!(interim.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reject_request_with_slow_headers() {
+        // the client never finishes headers
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client
+            .write_all("GET / HTTP/1.1\r\nHost: loc".as_bytes())
+            .await
+            .unwrap();
+
+        let limits = RequestParseLimits::new(4096)
+            .with_header_read_timeout(Some(std::time::Duration::from_millis(50)));
+        let actual = tokio::time::timeout(
+            std::time::Duration::from_secs(1),
+            Request::parse_with_limits(&mut server, &limits),
+        )
+        .await
+        .expect("parse should time out by itself");
+
+        assert_eq!(
+            actual.unwrap_err().get_status(),
+            &ResponseStatus::RequestTimeout
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_pipelined_requests() {
+        let raw = [
+            "POST /a HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello",
+            "POST /b HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nhi\r\n0\r\n\r\n",
+            "GET /c HTTP/1.1\r\n\r\n",
+        ]
+        .concat();
+        let mut stream = raw.as_bytes();
+        let mut rest = vec![];
+        let mut actual = vec![];
+        while !(rest.is_empty() && stream.is_empty()) {
+            // the next request begins with bytes read beyond the previous one
+            let mut reader = rest.as_slice().chain(stream);
+            let (request, next) = Request::parse_with_rest(
+                &mut reader,
+                &mut tokio::io::sink(),
+                &RequestParseLimits::default(),
+            )
+            .await
+            .unwrap();
+            actual.push((request.get_path().to_string(), request.get_body().to_vec()));
+            let (unread, remaining) = reader.into_inner();
+            rest = [next.as_slice(), unread].concat();
+            stream = remaining;
+        }
+
+        assert_eq!(
+            actual,
+            vec![
+                ("/a".to_string(), b"hello".to_vec()),
+                ("/b".to_string(), b"hi".to_vec()),
+                ("/c".to_string(), vec![]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_body_size_limit_boundary() {
+        let limits = RequestParseLimits::new(4096).with_max_body_bytes(Some(10));
+
+        let raw = format!(
+            "POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\n{}",
+            "a".repeat(10)
+        );
+        let actual = Request::parse_with_limits(&mut raw.as_bytes(), &limits).await;
+        assert_eq!(actual.unwrap().get_body(), "a".repeat(10).as_bytes());
+
+        let raw = format!(
+            "POST / HTTP/1.1\r\nContent-Length: 11\r\n\r\n{}",
+            "a".repeat(11)
+        );
+        let actual = Request::parse_with_limits(&mut raw.as_bytes(), &limits).await;
+        assert_eq!(
+            actual.unwrap_err().get_status(),
+            &ResponseStatus::PayloadTooLarge
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_continue_before_reading_body() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client
+            .write_all(
+                "POST / HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\nhello"
+                    .as_bytes(),
+            )
+            .await
+            .unwrap();
+
+        let mut interim = vec![];
+        let limits = RequestParseLimits::new(4096).with_max_body_bytes(Some(10));
+        let actual = Request::parse_with_interim(&mut server, &mut interim, &limits)
+            .await
+            .unwrap();
+
+        assert_eq!(interim, "HTTP/1.1 100 Continue\r\n\r\n".as_bytes());
+        assert_eq!(actual.get_body(), "hello".as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_reject_unsupported_expectation() {
+        let mut data = "POST / HTTP/1.1\r\nExpect: foo\r\nContent-Length: 5\r\n\r\n".as_bytes();
+        let actual = Request::parse(&mut data).await;
+        assert!(matches!(
+            actual,
+            Err(RequestParseError(ResponseStatus::ExpectationFailed, _))
+        ));
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let mut encoder = GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn create_gzip_request(data: &[u8]) -> Vec<u8> {
+        let body = gzip(data);
+        let mut raw = format!(
+            "POST / HTTP/1.1\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n",
+            body.len()
+        )
+        .into_bytes();
+        raw.extend(body);
+        raw
+    }
+
+    #[tokio::test]
+    async fn test_parse_request_with_gzip_body() {
+        let raw = create_gzip_request(b"name=alice");
+        let actual = Request::parse(&mut raw.as_slice()).await.unwrap();
+        assert_eq!(actual.get_body(), b"name=alice");
+        assert_eq!(actual.get_header("Content-Encoding"), None);
+        assert_eq!(actual.get_header("Content-Length"), Some("10"));
+    }
+
+    #[tokio::test]
+    async fn test_reject_decompression_bomb() {
+        // 1MB of zeros is compressed into a few KB
+        let raw = create_gzip_request(&vec![0u8; 1024 * 1024]);
+        let limits = RequestParseLimits::default().with_max_body_bytes(Some(64 * 1024));
+        let actual = Request::parse_with_limits(&mut raw.as_slice(), &limits).await;
+        assert!(matches!(
+            actual,
+            Err(RequestParseError(ResponseStatus::PayloadTooLarge, _))
+        ));
+
+        // decoded body is limited even if the body is not
+        let limits = RequestParseLimits::default()
+            .with_max_body_bytes(None)
+            .with_max_decoded_body_bytes(Some(64 * 1024));
+        let actual = Request::parse_with_limits(&mut raw.as_slice(), &limits).await;
+        assert!(matches!(
+            actual,
+            Err(RequestParseError(ResponseStatus::PayloadTooLarge, _))
+        ));
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "http": {"max_body_bytes": "unlimited"},
+            "ws": {},
+        }))
+        .unwrap();
+        let limits = RequestParseLimits::from_settings(&settings);
+        assert_eq!(limits.decoded_body_limit(), Some(10_000_000));
+    }
+
     #[tokio::test]
     async fn test_parse_request_post() {
         // setup
@@ -530,4 +1866,114 @@ mod tests {
         );
         assert_eq!(&actual.body.parse::<String>().unwrap(), "name=alice")
     }
+
+    #[test]
+    fn test_parse_form_body() {
+        let body = RequestBody::new(b"name=alice&age=30".to_vec());
+        assert_eq!(
+            body.parse_form().unwrap(),
+            HashMap::from([
+                ("name".to_string(), "alice".to_string()),
+                ("age".to_string(), "30".to_string()),
+            ])
+        );
+
+        let body = RequestBody::new(b"q=fish+%26+chips&empty=&flag&q2=a%3Db".to_vec());
+        assert_eq!(
+            body.parse_form().unwrap(),
+            HashMap::from([
+                ("q".to_string(), "fish & chips".to_string()),
+                ("empty".to_string(), "".to_string()),
+                ("flag".to_string(), "".to_string()),
+                ("q2".to_string(), "a=b".to_string()),
+            ])
+        );
+
+        let body = RequestBody::new(b"name=alice&name=bob".to_vec());
+        assert_eq!(body.parse_form().unwrap()["name"], "bob");
+        assert!(RequestBody::new(vec![0xff]).parse_form().is_err());
+    }
+
+    #[test]
+    fn test_get_cookies() {
+        let create_request = |headers: RequestHeaders| {
+            Request::new(
+                RequestLine::new(RequestMethod::GET, "/", HTTPVersion::V1_1),
+                headers,
+                RequestBody::new(vec![]),
+            )
+        };
+        let cookies = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+
+        let request = create_request(RequestHeaders::new());
+        assert_eq!(request.get_cookies(), HashMap::new());
+
+        let request = create_request(RequestHeaders::from([("Cookie", "session=abc")]));
+        assert_eq!(request.get_cookies(), cookies(&[("session", "abc")]));
+
+        let request = create_request(RequestHeaders::from([(
+            "Cookie",
+            "session=abc; theme=\"dark\"; session=xyz",
+        )]));
+        assert_eq!(
+            request.get_cookies(),
+            cookies(&[("session", "abc"), ("theme", "dark")])
+        );
+
+        let request = create_request(RequestHeaders::from([(
+            "Cookie",
+            " a = 1 ;b=2 ;  ; broken ; c=",
+        )]));
+        assert_eq!(
+            request.get_cookies(),
+            cookies(&[("a", "1"), ("b", "2"), ("c", "")])
+        );
+    }
+
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    struct User {
+        name: String,
+        age: u32,
+    }
+
+    fn create_json_request(content_type: &str, body: &str) -> Request {
+        Request::new(
+            RequestLine::new(RequestMethod::POST, "/users", HTTPVersion::V1_1),
+            RequestHeaders::from([("Content-Type", content_type)]),
+            RequestBody::new(body.as_bytes().to_owned()),
+        )
+    }
+
+    #[test]
+    fn test_parse_json_body() {
+        let request = create_json_request(
+            "application/json; charset=utf-8",
+            r#"{"name": "alice", "age": 20}"#,
+        );
+        let expected = User {
+            name: "alice".to_string(),
+            age: 20,
+        };
+        assert_eq!(request.parse_json::<User>().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_parse_malformed_json_body() {
+        let request = create_json_request("application/json", r#"{"name": "alice""#);
+        let err = request.parse_json::<User>().unwrap_err();
+        assert_eq!(err.get_status(), &ResponseStatus::BadRequest);
+
+        let request = create_json_request("application/json", r#"{"name": "alice"}"#);
+        let err = request.parse_json::<User>().unwrap_err();
+        assert_eq!(err.get_status(), &ResponseStatus::BadRequest);
+
+        let request = create_json_request("text/plain", r#"{"name": "alice", "age": 20}"#);
+        let err = request.parse_json::<User>().unwrap_err();
+        assert_eq!(err.get_status(), &ResponseStatus::UnsupportedMediaType);
+    }
 }
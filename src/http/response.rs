@@ -1,7 +1,23 @@
-use crate::http::common::HTTPVersion;
+use crate::http::common::{HTTPVersion, IMFDateTime};
+use crate::http::cookie::Cookie;
+use anyhow::{Context, Result};
+use serde::Serialize;
 use std::collections::HashMap;
 use std::fmt;
 use std::fmt::Formatter;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+// size of chunks read from the source of a streamed body
+const CHUNK_SIZE: usize = 8192;
+
+/// Encode data as a chunk of chunked transfer coding (RFC 7230 4.1).
+/// Empty data is encoded as the last chunk without trailers.
+fn encode_chunk(data: &[u8]) -> Vec<u8> {
+    let mut res = format!("{:x}\r\n", data.len()).into_bytes();
+    res.extend(data);
+    res.extend(b"\r\n");
+    res
+}
 
 #[derive(Debug, Clone)]
 pub struct StatusLine {
@@ -31,40 +47,94 @@ impl StatusLine {
     }
 }
 
+/// Response header fields kept in the inserted order, whose names are case-insensitive.
+/// A field may have multiple values sent in separate lines like Set-Cookie (RFC 7230 3.2.2).
 #[derive(Debug)]
-pub struct ResponseHeaders(HashMap<String, String>);
+pub struct ResponseHeaders(Vec<(String, String)>);
 
 impl ResponseHeaders {
+    #[allow(dead_code)]
     pub fn new(headers: HashMap<String, String>) -> ResponseHeaders {
-        ResponseHeaders(headers)
+        headers.into_iter().collect()
     }
 
     #[allow(dead_code)]
     pub fn from<const N: usize>(
         arr: [(impl Into<String>, impl Into<String>); N],
     ) -> ResponseHeaders {
-        let mut headers = HashMap::new();
-        for (k, v) in arr {
-            headers.insert(k.into(), v.into());
-        }
-        ResponseHeaders::new(headers)
+        arr.into_iter().map(|(k, v)| (k.into(), v.into())).collect()
     }
 
     #[allow(dead_code)]
     pub fn empty() -> ResponseHeaders {
-        ResponseHeaders(HashMap::new())
+        ResponseHeaders(vec![])
     }
 
+    /// Return the first value of the field
     #[allow(dead_code)]
     pub fn get(&self, key: &str) -> Option<&str> {
-        self.0.get(key).map(|x| x.as_str())
+        self.0
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
     }
 
+    #[allow(dead_code)]
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.0
+            .iter()
+            .filter(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    /// Replace values of the field with the value at the position of the first one,
+    /// or add the field at the end. The first value replaced is returned.
     #[allow(dead_code)]
     pub fn insert(&mut self, key: String, value: String) -> Option<String> {
-        self.0.insert(key, value)
+        match self
+            .0
+            .iter()
+            .position(|(k, _)| k.eq_ignore_ascii_case(&key))
+        {
+            Some(pos) => {
+                let old = std::mem::replace(&mut self.0[pos].1, value);
+                // values following the first one are removed
+                let mut index = 0;
+                self.0.retain(|(k, _)| {
+                    let keep = index <= pos || !k.eq_ignore_ascii_case(&key);
+                    index += 1;
+                    keep
+                });
+                Some(old)
+            }
+            None => {
+                self.0.push((key, value));
+                None
+            }
+        }
+    }
+
+    /// Add the value at the end, which is sent in a separate line from other values of the field
+    #[allow(dead_code)]
+    pub fn append(&mut self, key: String, value: String) {
+        self.0.push((key, value));
+    }
+
+    #[allow(dead_code)]
+    pub fn add_cookie(&mut self, cookie: Cookie) {
+        self.append("Set-Cookie".to_string(), cookie.to_string());
     }
 
+    /// Remove all values of the field, returning the first one
+    #[allow(dead_code)]
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let first = self.get(key).map(|v| v.to_string());
+        self.0.retain(|(k, _)| !k.eq_ignore_ascii_case(key));
+        first
+    }
+
+    /// Return the number of lines
     #[allow(dead_code)]
     pub fn len(&self) -> usize {
         self.0.len()
@@ -79,6 +149,16 @@ impl ResponseHeaders {
     }
 }
 
+impl FromIterator<(String, String)> for ResponseHeaders {
+    fn from_iter<I: IntoIterator<Item = (String, String)>>(iter: I) -> Self {
+        let mut headers = ResponseHeaders::empty();
+        for (k, v) in iter {
+            headers.insert(k, v);
+        }
+        headers
+    }
+}
+
 #[derive(Debug)]
 pub struct ResponseBody(Vec<u8>);
 
@@ -104,6 +184,8 @@ pub struct Response {
     status_line: StatusLine,
     headers: ResponseHeaders,
     body: ResponseBody,
+    // body is not written though headers describe it, e.g. for HEAD requests
+    headers_only: bool,
 }
 
 impl Response {
@@ -112,10 +194,15 @@ impl Response {
             status_line,
             headers,
             body,
+            headers_only: false,
         }
     }
 
-    #[allow(dead_code)]
+    /// Omit body on writing the response, keeping headers like Content-Length as they are
+    pub fn set_headers_only(&mut self, headers_only: bool) {
+        self.headers_only = headers_only;
+    }
+
     pub fn get_status(&self) -> &ResponseStatus {
         self.status_line.get_status()
     }
@@ -125,48 +212,268 @@ impl Response {
         self.headers.get(key)
     }
 
-    pub fn encode(&self) -> Vec<u8> {
+    #[allow(dead_code)]
+    pub fn insert_header(&mut self, key: String, value: String) -> Option<String> {
+        self.headers.insert(key, value)
+    }
+
+    #[allow(dead_code)]
+    pub fn get_body(&self) -> &[u8] {
+        &self.body.0
+    }
+
+    /// Add headers requested with `Request::add_response_header`
+    pub fn add_headers(&mut self, headers: &[(String, String)]) {
+        for (k, v) in headers {
+            self.headers.append(k.clone(), v.clone());
+        }
+    }
+
+    /// Replace body and update Content-Length
+    #[allow(dead_code)]
+    pub fn replace_body(&mut self, body: ResponseBody) -> ResponseBody {
+        self.headers
+            .insert("Content-Length".to_string(), body.len().to_string());
+        std::mem::replace(&mut self.body, body)
+    }
+
+    /// Replace Content-Length with `Transfer-Encoding: chunked` to send body of unknown length
+    fn set_chunked(&mut self) {
+        self.headers.remove("Content-Length");
+        self.insert_header("Transfer-Encoding".to_string(), "chunked".to_string());
+    }
+
+    fn encode_head(&self) -> Vec<u8> {
         let mut res = vec![];
         res.extend(self.status_line.encode());
         res.extend([b'\r', b'\n']);
         res.extend(self.headers.encode());
         res.extend([b'\r', b'\n']);
-        res.extend(self.body.encode());
         res
     }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut res = self.encode_head();
+        if !self.headers_only {
+            res.extend(self.body.encode());
+        }
+        res
+    }
+
+    /// Encode the response with chunked transfer coding, splitting body into `chunk_size` chunks
+    #[allow(dead_code)]
+    pub fn encode_chunked(mut self, chunk_size: usize) -> Vec<u8> {
+        self.set_chunked();
+        let mut res = self.encode_head();
+        if !self.headers_only {
+            for chunk in self.body.0.chunks(chunk_size) {
+                res.extend(encode_chunk(chunk));
+            }
+            res.extend(encode_chunk(&[]));
+        }
+        res
+    }
+
+    /// Write the whole encoded response, retrying partial writes, and return the written bytes.
+    /// The writer is flushed since the client may wait for the response before sending more.
+    pub async fn write_to<W: AsyncWrite + Unpin>(&self, writer: &mut W) -> Result<usize> {
+        let encoded = self.encode();
+        writer
+            .write_all(&encoded)
+            .await
+            .context("Failed to write response")?;
+        writer.flush().await.context("Failed to flush response")?;
+        Ok(encoded.len())
+    }
+
+    /// Write the response with body streamed from `source` in chunked transfer coding,
+    /// so that body of unknown length is sent without buffering it. Body of `self` is ignored.
+    /// Return the written bytes including chunk framing.
+    #[allow(dead_code)]
+    pub async fn write_streaming_to<R, W>(mut self, source: &mut R, writer: &mut W) -> Result<usize>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        self.set_chunked();
+        let head = self.encode_head();
+        writer
+            .write_all(&head)
+            .await
+            .context("Failed to write response")?;
+        let mut written = head.len();
+        if !self.headers_only {
+            let mut buf = vec![0; CHUNK_SIZE];
+            loop {
+                let n = source
+                    .read(&mut buf)
+                    .await
+                    .context("Failed to read response body")?;
+                // the last chunk is written on EOF
+                let chunk = encode_chunk(&buf[..n]);
+                writer
+                    .write_all(&chunk)
+                    .await
+                    .context("Failed to write response")?;
+                written += chunk.len();
+                if n == 0 {
+                    break;
+                }
+            }
+        }
+        writer.flush().await.context("Failed to flush response")?;
+        Ok(written)
+    }
+}
+
+/// Builder of `Response`, e.g.
+///
+/// ```ignore
+/// let response = ResponseBuilder::ok()
+///     .header("X-Foo", "bar")
+///     .json(&value)?
+///     .build();
+/// ```
+///
+/// Date and Content-Length are filled in on `build`, and Content-Type is set by the body.
+pub struct ResponseBuilder {
+    status: ResponseStatus,
+    headers: ResponseHeaders,
+    body: Vec<u8>,
+}
+
+impl ResponseBuilder {
+    pub fn new(status: ResponseStatus) -> ResponseBuilder {
+        ResponseBuilder {
+            status,
+            headers: ResponseHeaders::empty(),
+            body: vec![],
+        }
+    }
+
+    pub fn ok() -> ResponseBuilder {
+        ResponseBuilder::new(ResponseStatus::Ok)
+    }
+
+    pub fn header(mut self, key: &str, value: impl Into<String>) -> ResponseBuilder {
+        self.headers.insert(key.to_string(), value.into());
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn cookie(mut self, cookie: Cookie) -> ResponseBuilder {
+        self.headers.add_cookie(cookie);
+        self
+    }
+
+    pub fn body(self, body: Vec<u8>, content_type: &str) -> ResponseBuilder {
+        let mut builder = self.header("Content-Type", content_type);
+        builder.body = body;
+        builder
+    }
+
+    #[allow(dead_code)]
+    pub fn text(self, text: impl Into<String>) -> ResponseBuilder {
+        self.body(text.into().into_bytes(), "text/plain; charset=utf-8")
+    }
+
+    #[allow(dead_code)]
+    pub fn json<T: Serialize + ?Sized>(self, value: &T) -> Result<ResponseBuilder> {
+        let body = serde_json::to_vec(value).context("Failed to serialize response body")?;
+        Ok(self.body(body, "application/json"))
+    }
+
+    pub fn build(self) -> Response {
+        let mut headers = self.headers;
+        if headers.get("Date").is_none() {
+            headers.insert("Date".to_string(), IMFDateTime::now().to_string());
+        }
+        headers.insert("Content-Length".to_string(), self.body.len().to_string());
+        Response::new(
+            StatusLine::new(HTTPVersion::V1_1, self.status),
+            headers,
+            ResponseBody::new(self.body),
+        )
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum ResponseStatus {
+    Continue,
     SwitchingProtocol,
     Ok,
+    NoContent,
+    MovedPermanently,
+    Found,
+    NotModified,
     BadRequest,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    RequestTimeout,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    ExpectationFailed,
+    RequestHeaderFieldsTooLarge,
     InternalServerError,
     NotImplemented,
+    ServiceUnavailable,
 }
 
 impl ResponseStatus {
     pub fn status_code(&self) -> u16 {
         match self {
+            ResponseStatus::Continue => 100,
             ResponseStatus::SwitchingProtocol => 101,
             ResponseStatus::Ok => 200,
+            ResponseStatus::NoContent => 204,
+            ResponseStatus::MovedPermanently => 301,
+            ResponseStatus::Found => 302,
+            ResponseStatus::NotModified => 304,
             ResponseStatus::BadRequest => 400,
+            ResponseStatus::Forbidden => 403,
+            ResponseStatus::NotFound => 404,
+            ResponseStatus::MethodNotAllowed => 405,
+            ResponseStatus::RequestTimeout => 408,
+            ResponseStatus::PayloadTooLarge => 413,
+            ResponseStatus::UnsupportedMediaType => 415,
+            ResponseStatus::ExpectationFailed => 417,
+            ResponseStatus::RequestHeaderFieldsTooLarge => 431,
             ResponseStatus::InternalServerError => 500,
             ResponseStatus::NotImplemented => 501,
+            ResponseStatus::ServiceUnavailable => 503,
         }
     }
 
     pub fn reason_phrase(&self) -> String {
         match self {
+            ResponseStatus::Continue => "Continue",
             ResponseStatus::SwitchingProtocol => "Switching Protocol",
             ResponseStatus::Ok => "OK",
+            ResponseStatus::NoContent => "No Content",
+            ResponseStatus::MovedPermanently => "Moved Permanently",
+            ResponseStatus::Found => "Found",
+            ResponseStatus::NotModified => "Not Modified",
             ResponseStatus::BadRequest => "Bad Request",
+            ResponseStatus::Forbidden => "Forbidden",
+            ResponseStatus::NotFound => "Not Found",
+            ResponseStatus::MethodNotAllowed => "Method Not Allowed",
+            ResponseStatus::RequestTimeout => "Request Timeout",
+            ResponseStatus::PayloadTooLarge => "Payload Too Large",
+            ResponseStatus::UnsupportedMediaType => "Unsupported Media Type",
+            ResponseStatus::ExpectationFailed => "Expectation Failed",
+            ResponseStatus::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
             ResponseStatus::InternalServerError => "Internal Server Error",
             ResponseStatus::NotImplemented => "Not Implemented",
+            ResponseStatus::ServiceUnavailable => "Service Unavailable",
         }
         .to_string()
     }
 
+    pub fn is_client_error(&self) -> bool {
+        self.status_code() / 100 == 4
+    }
+
     pub fn is_server_error(&self) -> bool {
         self.status_code() / 100 == 5
     }
@@ -181,6 +488,53 @@ impl fmt::Display for ResponseStatus {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::pin::Pin;
+    use std::task::Poll;
+
+    /// Writer accepting at most 3 bytes per write
+    struct SlowWriter(Vec<u8>);
+
+    impl AsyncWrite for SlowWriter {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let n = buf.len().min(3);
+            self.0.extend(&buf[..n]);
+            Poll::Ready(Ok(n))
+        }
+
+        fn poll_flush(
+            self: Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_whole_response_to_slow_writer() {
+        let data = "hello".as_bytes();
+        let response = Response::new(
+            StatusLine::new(HTTPVersion::V1_1, ResponseStatus::Ok),
+            ResponseHeaders::from([("Content-Length", data.len().to_string())]),
+            ResponseBody::new(data.to_owned()),
+        );
+
+        let mut writer = SlowWriter(vec![]);
+        let written = response.write_to(&mut writer).await.unwrap();
+
+        assert_eq!(writer.0, response.encode());
+        assert_eq!(written, writer.0.len());
+    }
 
     #[test]
     fn test_encode_response() {
@@ -200,4 +554,216 @@ mod tests {
 
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn test_encode_status_line_of_http_1_0() {
+        let status_line = StatusLine::new(HTTPVersion::V1_0, ResponseStatus::NotFound);
+        assert_eq!(status_line.encode(), b"HTTP/1.0 404 Not Found");
+    }
+
+    #[test]
+    fn test_encode_response_chunked() {
+        let data = "hello, world".as_bytes();
+        let response = Response::new(
+            StatusLine::new(HTTPVersion::V1_1, ResponseStatus::Ok),
+            ResponseHeaders::from([("Content-Length", data.len().to_string())]),
+            ResponseBody::new(data.to_owned()),
+        );
+
+        let actual = String::from_utf8(response.encode_chunked(5)).unwrap();
+
+        let expected = [
+            "HTTP/1.1 200 OK",
+            "Transfer-Encoding: chunked",
+            "",
+            "5",
+            "hello",
+            "5",
+            ", wor",
+            "2",
+            "ld",
+            "0",
+            "",
+            "",
+        ]
+        .join("\r\n");
+        assert_eq!(actual, expected);
+    }
+
+    #[tokio::test]
+    async fn test_write_streaming_response() {
+        let data = "a".repeat(CHUNK_SIZE + 16);
+        let response = Response::new(
+            StatusLine::new(HTTPVersion::V1_1, ResponseStatus::Ok),
+            ResponseHeaders::empty(),
+            ResponseBody::new(vec![]),
+        );
+
+        let mut writer = vec![];
+        let written = response
+            .write_streaming_to(&mut data.as_bytes(), &mut writer)
+            .await
+            .unwrap();
+
+        let expected = [
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n".to_string(),
+            format!("2000\r\n{}\r\n", &data[..CHUNK_SIZE]),
+            format!("10\r\n{}\r\n", &data[CHUNK_SIZE..]),
+            "0\r\n\r\n".to_string(),
+        ]
+        .concat();
+        assert_eq!(written, expected.len());
+        assert_eq!(String::from_utf8(writer).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_encode_response_headers_only() {
+        let data = "hello".as_bytes();
+        let mut response = Response::new(
+            StatusLine::new(HTTPVersion::V1_1, ResponseStatus::Ok),
+            ResponseHeaders::from([("Content-Length", data.len().to_string())]),
+            ResponseBody::new(data.to_owned()),
+        );
+        response.set_headers_only(true);
+
+        let actual = String::from_utf8_lossy(&response.encode()).to_string();
+
+        let expected = ["HTTP/1.1 200 OK", "Content-Length: 5", "", ""].join("\r\n");
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_status_code_and_reason_phrase() {
+        let cases = [
+            (ResponseStatus::NoContent, 204, "No Content"),
+            (ResponseStatus::MovedPermanently, 301, "Moved Permanently"),
+            (ResponseStatus::Found, 302, "Found"),
+            (ResponseStatus::Forbidden, 403, "Forbidden"),
+            (ResponseStatus::NotFound, 404, "Not Found"),
+            (ResponseStatus::MethodNotAllowed, 405, "Method Not Allowed"),
+            (
+                ResponseStatus::ServiceUnavailable,
+                503,
+                "Service Unavailable",
+            ),
+        ];
+        for (status, code, phrase) in cases {
+            assert_eq!(status.status_code(), code);
+            assert_eq!(status.reason_phrase(), phrase);
+        }
+        assert!(ResponseStatus::ServiceUnavailable.is_server_error());
+        assert!(ResponseStatus::MethodNotAllowed.is_client_error());
+        assert!(!ResponseStatus::Found.is_client_error());
+    }
+
+    #[test]
+    fn test_encode_multiple_set_cookie_lines() {
+        let mut headers = ResponseHeaders::from([("Content-Length", "0")]);
+        headers.add_cookie(Cookie::new("session", "abc").with_http_only(true));
+        headers.add_cookie(Cookie::new("theme", "dark"));
+        let response = Response::new(
+            StatusLine::new(HTTPVersion::V1_1, ResponseStatus::Ok),
+            headers,
+            ResponseBody::new(vec![]),
+        );
+
+        let actual = String::from_utf8(response.encode()).unwrap();
+
+        let mut lines: Vec<_> = actual.split("\r\n").collect();
+        lines.sort();
+        assert_eq!(
+            lines,
+            vec![
+                "",
+                "",
+                "Content-Length: 0",
+                "HTTP/1.1 200 OK",
+                "Set-Cookie: session=abc; HttpOnly",
+                "Set-Cookie: theme=dark",
+            ]
+        );
+        assert_eq!(
+            response.headers.get_all("Set-Cookie"),
+            vec!["session=abc; HttpOnly", "theme=dark"]
+        );
+    }
+
+    #[test]
+    fn test_encode_headers_in_inserted_order() {
+        let mut headers = ResponseHeaders::from([("Date", "today"), ("Vary", "Origin")]);
+        headers.insert("Content-Type".to_string(), "text/plain".to_string());
+        headers.append("vary".to_string(), "Accept-Encoding".to_string());
+        headers.insert("Content-Length".to_string(), "0".to_string());
+        // replaced in place
+        headers.insert("date".to_string(), "tomorrow".to_string());
+
+        let actual = String::from_utf8(headers.encode()).unwrap();
+
+        let expected = [
+            "Date: tomorrow",
+            "Vary: Origin",
+            "Content-Type: text/plain",
+            "vary: Accept-Encoding",
+            "Content-Length: 0",
+            "",
+        ]
+        .join("\r\n");
+        assert_eq!(actual, expected);
+        assert_eq!(headers.get_all("Vary"), vec!["Origin", "Accept-Encoding"]);
+
+        headers.insert("VARY".to_string(), "*".to_string());
+        assert_eq!(headers.get_all("Vary"), vec!["*"]);
+        assert_eq!(headers.len(), 4);
+    }
+
+    /// Split encoded response into the status line, sorted headers and the body
+    fn split_encoded(response: &Response) -> (String, Vec<String>, String) {
+        let encoded = String::from_utf8(response.encode()).unwrap();
+        let (head, body) = encoded.split_once("\r\n\r\n").unwrap();
+        let mut lines = head.split("\r\n").map(|line| line.to_string());
+        let status_line = lines.next().unwrap();
+        let mut headers: Vec<String> = lines.filter(|line| !line.starts_with("Date: ")).collect();
+        headers.sort();
+        (status_line, headers, body.to_string())
+    }
+
+    #[test]
+    fn test_build_json_response() {
+        let response = ResponseBuilder::ok()
+            .header("X-Foo", "bar")
+            .json(&serde_json::json!({"message": "hello"}))
+            .unwrap()
+            .build();
+
+        assert!(response.get_header("Date").is_some());
+        let (status_line, headers, body) = split_encoded(&response);
+        assert_eq!(status_line, "HTTP/1.1 200 OK");
+        assert_eq!(
+            headers,
+            vec![
+                "Content-Length: 19",
+                "Content-Type: application/json",
+                "X-Foo: bar"
+            ]
+        );
+        assert_eq!(body, r#"{"message":"hello"}"#);
+    }
+
+    #[test]
+    fn test_build_text_response() {
+        let response = ResponseBuilder::new(ResponseStatus::NotFound)
+            .text("not found")
+            .build();
+
+        let (status_line, headers, body) = split_encoded(&response);
+        assert_eq!(status_line, "HTTP/1.1 404 Not Found");
+        assert_eq!(
+            headers,
+            vec![
+                "Content-Length: 9",
+                "Content-Type: text/plain; charset=utf-8"
+            ]
+        );
+        assert_eq!(body, "not found");
+    }
 }
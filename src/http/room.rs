@@ -0,0 +1,171 @@
+use crate::http::handler::websocket::Frame;
+use crate::http::outgoing::OutgoingSender;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// Command sent by a client as a text message to join or leave a room
+#[derive(Debug, PartialEq, Eq)]
+pub enum RoomCommand {
+    Join(String),
+    Leave(String),
+}
+
+impl RoomCommand {
+    /// Parse `/join <room>` or `/leave <room>`. Return None for other messages.
+    pub fn parse(message: &str) -> Option<RoomCommand> {
+        let (command, name) = message.split_once(' ')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+        match command {
+            "/join" => Some(RoomCommand::Join(name.to_string())),
+            "/leave" => Some(RoomCommand::Leave(name.to_string())),
+            _ => None,
+        }
+    }
+}
+
+/// Named channel delivering messages to all of its members
+#[derive(Debug, Default)]
+pub struct Room {
+    members: HashMap<Uuid, OutgoingSender>,
+}
+
+impl Room {
+    #[allow(dead_code)]
+    pub fn new() -> Room {
+        Room::default()
+    }
+
+    pub fn join(&mut self, id: Uuid, sender: OutgoingSender) {
+        self.members.insert(id, sender);
+    }
+
+    pub fn leave(&mut self, id: &Uuid) {
+        self.members.remove(id);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    pub fn members(&self) -> Vec<(Uuid, OutgoingSender)> {
+        self.members
+            .iter()
+            .map(|(id, sender)| (*id, sender.clone()))
+            .collect()
+    }
+}
+
+/// Rooms shared by WebSocket sessions, which are created on the first join
+#[derive(Debug, Default)]
+pub struct Rooms(Mutex<HashMap<String, Room>>);
+
+impl Rooms {
+    #[allow(dead_code)]
+    pub fn new() -> Rooms {
+        Rooms::default()
+    }
+
+    pub fn join(&self, name: &str, id: Uuid, sender: OutgoingSender) {
+        let mut rooms = self.0.lock().unwrap();
+        rooms.entry(name.to_string()).or_default().join(id, sender);
+    }
+
+    pub fn leave(&self, name: &str, id: &Uuid) {
+        let mut rooms = self.0.lock().unwrap();
+        if let Some(room) = rooms.get_mut(name) {
+            room.leave(id);
+            if room.is_empty() {
+                rooms.remove(name);
+            }
+        }
+    }
+
+    /// Return true if no room has members
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.0.lock().unwrap().is_empty()
+    }
+
+    /// Send text message to all members. Members whose session has finished are removed.
+    /// It waits for slow members if their outgoing queue is full and the policy is `Block`.
+    pub async fn broadcast(&self, name: &str, message: &str) {
+        // the lock is not held while waiting for members
+        let members = match self.0.lock().unwrap().get(name) {
+            Some(room) => room.members(),
+            None => return,
+        };
+        for (id, sender) in members {
+            let frame = Frame::Text {
+                message: message.to_string(),
+            };
+            if sender.send(frame).await.is_err() {
+                self.leave(name, &id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::outgoing;
+    use crate::settings::OutgoingOverflowPolicy;
+
+    fn channel() -> (OutgoingSender, outgoing::OutgoingReceiver) {
+        outgoing::channel(4, OutgoingOverflowPolicy::Block)
+    }
+
+    #[test]
+    fn test_parse_room_command() {
+        assert_eq!(
+            RoomCommand::parse("/join lobby"),
+            Some(RoomCommand::Join("lobby".to_string()))
+        );
+        assert_eq!(
+            RoomCommand::parse("/leave lobby"),
+            Some(RoomCommand::Leave("lobby".to_string()))
+        );
+        assert_eq!(RoomCommand::parse("/join "), None);
+        assert_eq!(RoomCommand::parse("hello lobby"), None);
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_to_members_of_room() {
+        let rooms = Rooms::new();
+        let (sender1, mut receiver1) = channel();
+        let (sender2, mut receiver2) = channel();
+        let (sender3, mut receiver3) = channel();
+        rooms.join("a", Uuid::new_v4(), sender1);
+        rooms.join("a", Uuid::new_v4(), sender2);
+        rooms.join("b", Uuid::new_v4(), sender3);
+
+        rooms.broadcast("a", "hello").await;
+        drop(rooms);
+
+        for receiver in [&mut receiver1, &mut receiver2] {
+            assert!(matches!(
+                receiver.recv().await,
+                Some(Frame::Text { message }) if message == "hello"
+            ));
+        }
+        assert_eq!(receiver3.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_leave_room() {
+        let rooms = Rooms::new();
+        let id = Uuid::new_v4();
+        let (sender, mut receiver) = channel();
+        rooms.join("a", id, sender);
+        rooms.leave("a", &id);
+
+        rooms.broadcast("a", "hello").await;
+
+        assert_eq!(receiver.recv().await, None);
+        assert!(rooms.0.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,68 @@
+use tokio::sync::watch;
+
+/// Create a pair of trigger and signal of shutdown
+pub fn channel() -> (ShutdownTrigger, Shutdown) {
+    let (sender, receiver) = watch::channel(false);
+    (ShutdownTrigger(sender), Shutdown(receiver))
+}
+
+/// Trigger notifying all `Shutdown` of the channel
+#[derive(Debug)]
+pub struct ShutdownTrigger(watch::Sender<bool>);
+
+impl ShutdownTrigger {
+    pub fn trigger(&self) {
+        // fails only if all signals have been dropped, which nobody waits for
+        let _ = self.0.send(true);
+    }
+}
+
+/// Signal waited by tasks which should finish when the server is shutting down
+#[derive(Debug, Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    /// Return signal which is never triggered
+    #[allow(dead_code)]
+    pub fn never() -> Shutdown {
+        channel().1
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Wait until shutdown is triggered. It never completes if the trigger has been dropped.
+    pub async fn wait(&mut self) {
+        while !self.is_triggered() {
+            if self.0.changed().await.is_err() {
+                futures::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_wait_shutdown() {
+        let (trigger, shutdown) = channel();
+        let mut waiting = shutdown.clone();
+        let waiter = tokio::spawn(async move { waiting.wait().await });
+
+        assert!(!shutdown.is_triggered());
+        trigger.trigger();
+        tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(shutdown.is_triggered());
+
+        let mut never = Shutdown::never();
+        let res = tokio::time::timeout(Duration::from_millis(50), never.wait()).await;
+        assert!(res.is_err());
+    }
+}
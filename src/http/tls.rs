@@ -0,0 +1,76 @@
+use crate::settings::{Tls, TlsVersion};
+use anyhow::{bail, Context, Result};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+use tokio_rustls::rustls::{
+    version, Certificate, PrivateKey, ServerConfig, SupportedCipherSuite, SupportedProtocolVersion,
+    ALL_CIPHER_SUITES, DEFAULT_CIPHER_SUITES,
+};
+use tokio_rustls::TlsAcceptor;
+
+fn load_certs(path: &Path) -> Result<Vec<Certificate>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .with_context(|| format!("Failed to read certificates in {:?}", path))?;
+    if certs.is_empty() {
+        bail!("No certificate found in {:?}", path);
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_key(path: &Path) -> Result<PrivateKey> {
+    let file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    let items = rustls_pemfile::read_all(&mut BufReader::new(file))
+        .with_context(|| format!("Failed to read private key in {:?}", path))?;
+    items
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .with_context(|| format!("No private key found in {:?}", path))
+}
+
+/// Return cipher suites named in settings, or the safe defaults of rustls
+fn cipher_suites(tls: &Tls) -> Result<Vec<SupportedCipherSuite>> {
+    let names = match tls.cipher_suites() {
+        Some(names) => names,
+        None => return Ok(DEFAULT_CIPHER_SUITES.to_vec()),
+    };
+    names
+        .iter()
+        .map(|name| {
+            ALL_CIPHER_SUITES
+                .iter()
+                .find(|suite| format!("{:?}", suite.suite()).eq_ignore_ascii_case(name))
+                .copied()
+                .with_context(|| format!("Unsupported cipher suite: {}", name))
+        })
+        .collect()
+}
+
+/// Return versions of TLS from `min_tls_version`
+fn protocol_versions(tls: &Tls) -> Vec<&'static SupportedProtocolVersion> {
+    match tls.min_tls_version() {
+        TlsVersion::V1_2 => vec![&version::TLS13, &version::TLS12],
+        TlsVersion::V1_3 => vec![&version::TLS13],
+    }
+}
+
+/// Create acceptor completing TLS handshake with the certificate and the key in settings.
+/// Clients are rejected if they support no version and no cipher suite allowed in settings.
+pub fn acceptor(tls: &Tls) -> Result<TlsAcceptor> {
+    let config = ServerConfig::builder()
+        .with_cipher_suites(&cipher_suites(tls)?)
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&protocol_versions(tls))
+        .context("No cipher suite usable with the TLS versions")?
+        .with_no_client_auth()
+        .with_single_cert(load_certs(tls.cert_path())?, load_key(tls.key_path())?)
+        .context("Illegal certificate or private key")?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
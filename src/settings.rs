@@ -1,12 +1,14 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use config::Config;
 use getset::Getters;
 use human_size::Size;
-use serde::Deserialize;
-use std::path::Path;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
 
-#[derive(Debug, Deserialize, Getters)]
+#[derive(Debug, Deserialize, Serialize, Getters)]
 pub struct Http {
     #[serde(default)]
     #[getset(get = "pub")]
@@ -14,6 +16,109 @@ pub struct Http {
     #[serde(default)]
     #[getset(get = "pub")]
     port: u16,
+    /// Also listen at these addresses (e.g. "[::1]:8888") with the same settings
+    #[serde(default)]
+    #[getset(get = "pub")]
+    listen: Vec<SocketAddr>,
+    /// Also listen on the Unix domain socket at this path (e.g. "/tmp/sws.sock"),
+    /// which is removed on shutdown
+    #[serde(default)]
+    #[getset(get = "pub")]
+    unix_socket: Option<PathBuf>,
+    /// Maximum number of connections accepted per second (unlimited if not set)
+    #[serde(default)]
+    #[getset(get = "pub")]
+    max_accepts_per_sec: Option<u32>,
+    #[serde(default = "default_max_request_bytes")]
+    #[getset(get = "pub")]
+    max_request_bytes: Size,
+    /// Requests whose request line and headers exceed this size are rejected with 431
+    #[serde(default = "default_max_header_bytes")]
+    #[getset(get = "pub")]
+    max_header_bytes: Size,
+    /// Requests declaring bigger body in Content-Length are rejected with 413
+    /// without reading the body. "unlimited" disables the limit.
+    #[serde(default = "default_max_body_bytes", with = "size_or_unlimited")]
+    #[getset(get = "pub")]
+    max_body_bytes: Option<Size>,
+    /// Bodies decoded from Content-Encoding are rejected with 413 beyond this size,
+    /// even if `max_body_bytes` is "unlimited", so that a small body cannot expand without limit
+    #[serde(default = "default_max_decoded_body_bytes")]
+    #[getset(get = "pub")]
+    max_decoded_body_bytes: Size,
+    /// Requests whose request line and headers are not received in this duration
+    /// are rejected with 408, e.g. to guard against Slowloris attacks
+    #[serde(default = "default_header_read_timeout", with = "humantime_serde")]
+    #[getset(get = "pub")]
+    header_read_timeout: Duration,
+    /// Parsing a request is aborted if bytes scanned for CRLF exceed
+    /// `max_request_bytes * max_scan_factor`
+    #[serde(default = "default_max_scan_factor")]
+    #[getset(get = "pub")]
+    max_scan_factor: usize,
+    /// Compress response bodies with a content coding accepted by the client
+    #[serde(default)]
+    #[getset(get = "pub")]
+    enable_compression: bool,
+    /// Response bodies smaller than this size are not compressed
+    #[serde(default = "default_compression_min_bytes")]
+    #[getset(get = "pub")]
+    compression_min_bytes: Size,
+    /// Maximum number of request headers reflected by the echo handler (unlimited if not set)
+    #[serde(default)]
+    #[getset(get = "pub")]
+    echo_max_headers: Option<usize>,
+    /// Request headers reflected by the echo handler (all headers if not set)
+    #[serde(default)]
+    #[getset(get = "pub")]
+    echo_header_allowlist: Option<Vec<String>>,
+    /// Request headers never reflected by the echo handler
+    #[serde(default)]
+    #[getset(get = "pub")]
+    echo_header_denylist: Vec<String>,
+    /// Log a line in this format at info level with target `access` after each response.
+    /// See `AccessLog::format` for placeholders (no access log if not set).
+    #[serde(default)]
+    #[getset(get = "pub")]
+    access_log_format: Option<String>,
+    /// Body of error responses for clients preferring text/html.
+    /// `{status}` and `{reason}` are replaced with the status code and the reason phrase.
+    #[serde(default = "default_error_page_template")]
+    #[getset(get = "pub")]
+    error_page_template: String,
+}
+
+fn default_max_request_bytes() -> Size {
+    Size::from_str("8KB").unwrap()
+}
+
+fn default_max_body_bytes() -> Option<Size> {
+    Some(Size::from_str("1MB").unwrap())
+}
+
+fn default_max_decoded_body_bytes() -> Size {
+    Size::from_str("10MB").unwrap()
+}
+
+fn default_max_header_bytes() -> Size {
+    Size::from_str("8KB").unwrap()
+}
+
+fn default_header_read_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_max_scan_factor() -> usize {
+    4
+}
+
+fn default_compression_min_bytes() -> Size {
+    Size::from_str("1KB").unwrap()
+}
+
+fn default_error_page_template() -> String {
+    "<html><head><title>{status} {reason}</title></head><body><h1>{status} {reason}</h1></body></html>"
+        .to_string()
 }
 
 impl Default for Http {
@@ -21,35 +126,313 @@ impl Default for Http {
         Self {
             addr: "127.0.0.1".to_string(),
             port: 8888,
+            listen: vec![],
+            unix_socket: None,
+            max_accepts_per_sec: None,
+            max_request_bytes: default_max_request_bytes(),
+            max_header_bytes: default_max_header_bytes(),
+            header_read_timeout: default_header_read_timeout(),
+            max_body_bytes: default_max_body_bytes(),
+            max_decoded_body_bytes: default_max_decoded_body_bytes(),
+            max_scan_factor: default_max_scan_factor(),
+            enable_compression: false,
+            compression_min_bytes: default_compression_min_bytes(),
+            echo_max_headers: None,
+            echo_header_allowlist: None,
+            echo_header_denylist: vec![],
+            access_log_format: None,
+            error_page_template: default_error_page_template(),
         }
     }
 }
 
-#[derive(Debug, Deserialize, Getters)]
+#[derive(Debug, Deserialize, Serialize, Getters)]
 pub struct WebSocket {
     #[serde(default = "default_max_payload_size")]
     #[getset(get = "pub")]
     max_payload_size: Size,
+    /// Number of payload bytes shown in debug logs of frames
+    #[serde(default = "default_log_preview_size")]
+    #[getset(get = "pub")]
+    log_preview_size: usize,
+    /// Reject handshake if the client offers extensions which the server doesn't support.
+    /// Otherwise such extensions are just ignored.
+    #[serde(default)]
+    #[getset(get = "pub")]
+    reject_unsupported_extensions: bool,
+    /// Close connections with status code 1001 after this duration (e.g. "1h")
+    #[serde(default, with = "humantime_serde")]
+    #[getset(get = "pub")]
+    max_session_duration: Option<Duration>,
+    /// Send Ping at this interval (e.g. "30s") to detect dead connections (never sent if not set)
+    #[serde(default, with = "humantime_serde")]
+    #[getset(get = "pub")]
+    ping_interval: Option<Duration>,
+    /// Close connections with status code 1001 if Pong is not received in this duration after Ping
+    #[serde(default = "default_pong_timeout", with = "humantime_serde")]
+    #[getset(get = "pub")]
+    pong_timeout: Duration,
+    /// Close connections with status code 1001 if no frame is received in this duration
+    #[serde(default, with = "humantime_serde")]
+    #[getset(get = "pub")]
+    idle_timeout: Option<Duration>,
+    /// Log CRC32 of payload of each received and sent frame for debugging
+    #[serde(default)]
+    #[getset(get = "pub")]
+    log_checksums: bool,
+    /// Let clients join rooms by `/join <room>` and `/leave <room>` text messages.
+    /// Messages from a client in rooms are broadcast to all members instead of echoed back.
+    #[serde(default)]
+    #[getset(get = "pub")]
+    enable_rooms: bool,
+    /// Deliver text messages from a client to all other clients instead of the app, e.g. for chat.
+    /// Clients missing more than `max_pending_frames` messages are closed with status code 1011.
+    #[serde(default)]
+    #[getset(get = "pub")]
+    broadcast_all: bool,
+    /// Text and Binary messages are sent in frames whose payload is at most this size
+    /// (sent in a single frame if not set)
+    #[serde(default)]
+    #[getset(get = "pub")]
+    max_frame_size: Option<Size>,
+    /// Reject unmasked frames from clients (RFC 6455 5.1), which are accepted otherwise.
+    /// Reserved bits, close codes and UTF-8 of frames are validated regardless of this.
+    #[serde(default)]
+    #[getset(get = "pub")]
+    require_masked_frames: bool,
+    /// Subprotocols supported by the server. The first one offered by the client is selected.
+    #[serde(default)]
+    #[getset(get = "pub")]
+    subprotocols: Vec<String>,
+    /// Accept permessage-deflate extension (RFC 7692) offered by clients
+    #[serde(default)]
+    #[getset(get = "pub")]
+    enable_permessage_deflate: bool,
+    /// Maximum number of frames waiting to be written to a client,
+    /// e.g. messages broadcast to a slow client in rooms
+    #[serde(default = "default_max_pending_frames")]
+    #[getset(get = "pub")]
+    max_pending_frames: usize,
+    /// What to do when a frame is sent to a client having `max_pending_frames` frames
+    #[serde(default)]
+    #[getset(get = "pub")]
+    outgoing_overflow_policy: OutgoingOverflowPolicy,
+}
+
+/// Policy applied when the queue of outgoing frames is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OutgoingOverflowPolicy {
+    /// Wait until the client consumes pending frames
+    #[default]
+    Block,
+    /// Drop the oldest pending frame with a warning
+    DropOldest,
 }
 
 fn default_max_payload_size() -> Size {
     Size::from_str("1MB").unwrap()
 }
 
+fn default_log_preview_size() -> usize {
+    16
+}
+
+fn default_pong_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_max_pending_frames() -> usize {
+    256
+}
+
 impl Default for WebSocket {
     fn default() -> Self {
         Self {
             max_payload_size: default_max_payload_size(),
+            log_preview_size: default_log_preview_size(),
+            reject_unsupported_extensions: false,
+            max_session_duration: None,
+            ping_interval: None,
+            pong_timeout: default_pong_timeout(),
+            idle_timeout: None,
+            log_checksums: false,
+            enable_rooms: false,
+            broadcast_all: false,
+            max_frame_size: None,
+            require_masked_frames: false,
+            subprotocols: vec![],
+            enable_permessage_deflate: false,
+            max_pending_frames: default_max_pending_frames(),
+            outgoing_overflow_policy: OutgoingOverflowPolicy::default(),
         }
     }
 }
 
-#[derive(Debug, Deserialize, Getters, Default)]
+/// Built-in handlers for paths which browsers and crawlers access
+#[derive(Debug, Deserialize, Serialize, Getters)]
+pub struct BuiltIn {
+    /// Serve /favicon.ico, /robots.txt and metrics
+    #[serde(default = "default_builtin_enabled")]
+    #[getset(get = "pub")]
+    enabled: bool,
+    /// Serve metrics in Prometheus text format at `metrics_path`
+    #[serde(default = "default_metrics_enabled")]
+    #[getset(get = "pub")]
+    metrics_enabled: bool,
+    #[serde(default = "default_metrics_path")]
+    #[getset(get = "pub")]
+    metrics_path: String,
+    /// Icon file served at /favicon.ico. An empty body is served if not set.
+    #[serde(default)]
+    #[getset(get = "pub")]
+    favicon_path: Option<PathBuf>,
+    #[serde(default = "default_robots_txt")]
+    #[getset(get = "pub")]
+    robots_txt: String,
+}
+
+fn default_builtin_enabled() -> bool {
+    true
+}
+
+fn default_metrics_enabled() -> bool {
+    true
+}
+
+fn default_metrics_path() -> String {
+    "/metrics".to_string()
+}
+
+fn default_robots_txt() -> String {
+    "User-agent: *\nDisallow: /\n".to_string()
+}
+
+impl Default for BuiltIn {
+    fn default() -> Self {
+        Self {
+            enabled: default_builtin_enabled(),
+            metrics_enabled: default_metrics_enabled(),
+            metrics_path: default_metrics_path(),
+            favicon_path: None,
+            robots_txt: default_robots_txt(),
+        }
+    }
+}
+
+/// Files served under `prefix` by the static file handler
+#[derive(Debug, Deserialize, Serialize, Getters)]
+pub struct StaticFiles {
+    /// Directory containing the files (no files are served if not set)
+    #[serde(default)]
+    #[getset(get = "pub")]
+    root: Option<PathBuf>,
+    #[serde(default = "default_static_files_prefix")]
+    #[getset(get = "pub")]
+    prefix: String,
+}
+
+fn default_static_files_prefix() -> String {
+    "/static/".to_string()
+}
+
+impl Default for StaticFiles {
+    fn default() -> Self {
+        Self {
+            root: None,
+            prefix: default_static_files_prefix(),
+        }
+    }
+}
+
+/// Cross-origin requests allowed by the CORS handler
+#[derive(Debug, Deserialize, Serialize, Getters, Default)]
+pub struct Cors {
+    /// Origins allowed to access, e.g. "https://example.com", or "*" allowing any origin.
+    /// No cross-origin request is allowed if empty.
+    #[serde(default)]
+    #[getset(get = "pub")]
+    allowed_origins: Vec<String>,
+    /// Methods allowed in preflight requests (methods of the wrapped handler if not set)
+    #[serde(default)]
+    #[getset(get = "pub")]
+    allowed_methods: Option<Vec<String>>,
+    /// Request headers allowed in preflight requests
+    #[serde(default)]
+    #[getset(get = "pub")]
+    allowed_headers: Vec<String>,
+    /// Duration for which results of preflight requests may be cached (e.g. "10m")
+    #[serde(default, with = "humantime_serde")]
+    #[getset(get = "pub")]
+    max_age: Option<Duration>,
+}
+
+/// Certificate and private key to serve HTTPS and wss:// with TLS
+#[derive(Debug, Deserialize, Serialize, Getters)]
+pub struct Tls {
+    /// PEM file of the certificate chain
+    #[getset(get = "pub")]
+    cert_path: PathBuf,
+    /// PEM file of the private key in PKCS#8, PKCS#1 or SEC1 format
+    #[getset(get = "pub")]
+    key_path: PathBuf,
+    /// Minimum version of TLS accepted, which is "1.2" or "1.3"
+    #[serde(default)]
+    #[getset(get = "pub")]
+    min_tls_version: TlsVersion,
+    /// Names of cipher suites accepted, e.g. "TLS13_AES_256_GCM_SHA384".
+    /// The safe defaults of rustls are accepted if not set.
+    #[serde(default)]
+    #[getset(get = "pub")]
+    cipher_suites: Option<Vec<String>>,
+}
+
+/// Version of TLS
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, Default)]
+pub enum TlsVersion {
+    #[default]
+    #[serde(rename = "1.2")]
+    V1_2,
+    #[serde(rename = "1.3")]
+    V1_3,
+}
+
+/// Additional listener served with its own settings
+#[derive(Debug, Deserialize, Serialize, Getters)]
+pub struct Listener {
+    #[getset(get = "pub")]
+    addr: String,
+    #[getset(get = "pub")]
+    port: u16,
+    /// Settings merged over the global ones for this listener,
+    /// e.g. `{ ws = { max_payload_size = "10MB" } }`
+    #[serde(default)]
+    #[getset(get = "pub")]
+    overrides: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize, Serialize, Getters, Default)]
 pub struct Settings {
     #[getset(get = "pub")]
     http: Http,
     #[getset(get = "pub")]
     ws: WebSocket,
+    #[serde(default)]
+    #[getset(get = "pub")]
+    builtin: BuiltIn,
+    #[serde(default)]
+    #[getset(get = "pub")]
+    static_files: StaticFiles,
+    #[serde(default)]
+    #[getset(get = "pub")]
+    cors: Cors,
+    /// Connections are accepted with TLS if set
+    #[serde(default)]
+    #[getset(get = "pub")]
+    tls: Option<Tls>,
+    #[serde(default)]
+    #[getset(get = "pub")]
+    listeners: Vec<Listener>,
 }
 
 impl Settings {
@@ -61,4 +444,107 @@ impl Settings {
         let settings = config.try_deserialize()?;
         Ok(settings)
     }
+
+    /// Return settings for the listener, which are merged with the global settings.
+    /// `listeners` of the returned settings is empty.
+    pub fn for_listener(&self, listener: &Listener) -> Result<Settings> {
+        fn merge(base: &mut serde_json::Value, overrides: &serde_json::Value) {
+            match (base, overrides) {
+                (serde_json::Value::Object(base), serde_json::Value::Object(overrides)) => {
+                    for (k, v) in overrides {
+                        merge(base.entry(k).or_insert(serde_json::Value::Null), v);
+                    }
+                }
+                (base, overrides) => *base = overrides.clone(),
+            }
+        }
+
+        let mut value = serde_json::to_value(self)?;
+        merge(&mut value, &listener.overrides);
+        value["listeners"] = serde_json::Value::Array(vec![]);
+        value["http"]["addr"] = serde_json::Value::String(listener.addr.clone());
+        value["http"]["port"] = serde_json::Value::from(listener.port);
+        serde_json::from_value(value).with_context(|| {
+            format!(
+                "Illegal settings for listener {}:{}",
+                listener.addr, listener.port
+            )
+        })
+    }
+}
+
+/// (De)serialize size which is disabled by "unlimited", e.g. `max_body_bytes = "unlimited"`
+mod size_or_unlimited {
+    use human_size::Size;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    const UNLIMITED: &str = "unlimited";
+
+    pub fn serialize<S: Serializer>(size: &Option<Size>, serializer: S) -> Result<S::Ok, S::Error> {
+        match size {
+            Some(size) => size.serialize(serializer),
+            None => serializer.serialize_str(UNLIMITED),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Size>, D::Error> {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(s) if s != UNLIMITED => s.parse().map(Some).map_err(serde::de::Error::custom),
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_for_listener() {
+        let settings: Settings = serde_json::from_str(
+            r#"{
+                "http": {"addr": "127.0.0.1", "port": 8888},
+                "ws": {"max_payload_size": "1MB", "log_preview_size": 8},
+                "listeners": [
+                    {"addr": "127.0.0.1", "port": 9999, "overrides": {"ws": {"max_payload_size": "10MB"}}}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let actual = settings.for_listener(&settings.listeners()[0]).unwrap();
+
+        assert_eq!(actual.http().port(), &9999);
+        assert_eq!(actual.ws().max_payload_size().to_bytes(), 10_000_000);
+        assert_eq!(actual.ws().log_preview_size(), &8);
+        assert!(actual.listeners().is_empty());
+    }
+
+    #[test]
+    fn test_max_body_bytes_is_limited_by_default() {
+        let settings: Settings = serde_json::from_str(r#"{"http": {}, "ws": {}}"#).unwrap();
+        assert_eq!(
+            settings.http().max_body_bytes().map(|x| x.to_bytes()),
+            Some(1_000_000)
+        );
+
+        let settings: Settings = serde_json::from_str(
+            r#"{
+                "http": {"max_body_bytes": "unlimited"},
+                "ws": {},
+                "listeners": [{"addr": "127.0.0.1", "port": 9999, "overrides": {}}]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(settings.http().max_body_bytes(), &None);
+        // kept through serialization
+        let actual = settings.for_listener(&settings.listeners()[0]).unwrap();
+        assert_eq!(actual.http().max_body_bytes(), &None);
+
+        let res =
+            serde_json::from_str::<Settings>(r#"{"http": {"max_body_bytes": "a lot"}, "ws": {}}"#);
+        assert!(res.is_err());
+    }
 }
@@ -20,6 +20,12 @@ impl TempFile {
         std::fs::create_dir_all(&path)?;
         let file_name = format!("{}.txt", Uuid::new_v4());
         path.push(file_name);
+        TempFile::with_path(path)
+    }
+
+    /// Create a temporary file at the path, which is deleted on drop
+    #[allow(dead_code)]
+    pub fn with_path(path: PathBuf) -> Result<TempFile> {
         let file = std::fs::File::create(&path)?;
         Ok(TempFile { inner: file, path })
     }